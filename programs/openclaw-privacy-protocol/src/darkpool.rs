@@ -6,25 +6,49 @@
 // 3. Anonymization layer - agent pools with zero-knowledge proofs
 
 use anchor_lang::prelude::*;
-use light_sdk::{
-    account::CompressedAccount, 
-    cpi::v2::CreateCompressedAccountCpi,
-    instruction::{PackedAddressTreeInfo, ValidityProof},
-    verify::{create_compressed_account_cpi, verify_zk_proof}
-};
+use anchor_lang::solana_program::keccak;
+use light_sdk::instruction::ValidityProof;
 use inco_lightning::cpi::{
     Operation,
     new_euint128,
     as_euint128,
     e_add,
-    e_sub,
-    e_mul,
-    e_div,
+    e_and,
+    e_eq,
+    e_ge,
+    e_le,
+    e_or,
     e_select
 };
 use inco_lightning::types::{Euint128, Ebool};
 
-#[program]
+// Active registrations a pool tracks in its registration root (fits the 10KB pool account)
+pub const MAX_POOL_REGISTRANTS: usize = 256;
+
+// Prefix on serialized PoolTransferRecords in compressed accounts
+pub const POOL_TRANSFER_RECORD_DISCRIMINATOR: [u8; 8] = *b"ocpptrec";
+
+// Public inputs any proof may carry, bounding verifier compute once real verification runs,
+// and the exact count the dark pool transfer circuit takes (nullifier, recipient, amount)
+pub const MAX_PUBLIC_INPUTS: usize = 16;
+pub const DARK_POOL_TRANSFER_PUBLIC_INPUTS: usize = 3;
+
+// Nullifiers check_nullifiers_unspent accepts per call
+pub const MAX_NULLIFIER_BATCH: usize = 16;
+
+// Fixed transfer amounts a pool may restrict itself to
+pub const MAX_DENOMINATIONS: usize = 8;
+
+// Agents register_to_pool_batch accepts per call
+pub const MAX_BATCH_REGISTRATIONS: usize = 5;
+
+// Account size of a PoolRegistration, shared by the single and batch registration paths
+pub const POOL_REGISTRATION_SPACE: usize = 512;
+
+// Outstanding Inco decryption requests a new pool accepts before rejecting more
+pub const DEFAULT_MAX_PENDING_DECRYPTIONS: u32 = 4;
+
+// Handlers for the dark pool instructions, dispatched from the program module in lib.rs
 pub mod agent_dark_pool {
     use super::*;
 
@@ -34,7 +58,22 @@ pub mod agent_dark_pool {
         pool_id: String,
         min_transfer_amount: u64,
         max_transfer_amount: u64,
+        registration_fee_lamports: u64,
     ) -> Result<()> {
+        // pool_id is a PDA seed, so it must fit in a single seed
+        require!(pool_id.len() <= 32, ErrorCode::PoolIdTooLong);
+        
+        // Bound total pools to limit state growth and anonymity-set fragmentation (0 = unlimited)
+        let protocol = &mut ctx.accounts.protocol_config;
+        require!(protocol.initialized, crate::ErrorCode::ProtocolNotInitialized);
+        require!(!protocol.paused, crate::ErrorCode::ProtocolPaused);
+        require!(!protocol.is_module_paused(crate::MODULE_DARKPOOL), crate::ErrorCode::ModulePaused);
+        require!(
+            protocol.max_dark_pools == 0 || protocol.total_dark_pools < protocol.max_dark_pools,
+            ErrorCode::PoolLimitReached
+        );
+        protocol.total_dark_pools = protocol.total_dark_pools.checked_add(1).unwrap();
+        
         let dark_pool = &mut ctx.accounts.dark_pool;
         dark_pool.pool_id = pool_id;
         dark_pool.mint = ctx.accounts.mint.key();
@@ -43,6 +82,25 @@ pub mod agent_dark_pool {
         dark_pool.min_amount = min_transfer_amount;
         dark_pool.max_amount = max_transfer_amount;
         dark_pool.is_active = true;
+        dark_pool.authority = ctx.accounts.authority.key();
+        dark_pool.pending_decryptions = 0;
+        dark_pool.max_pending_decryptions = DEFAULT_MAX_PENDING_DECRYPTIONS;
+        dark_pool.bump = ctx.bumps.dark_pool;
+        dark_pool.registered_commitments = Vec::new();
+        dark_pool.registration_root = compute_registration_root(&[]);
+        dark_pool.in_progress = false;
+        dark_pool.verification_key = ctx.accounts.zk_verification_key.key();
+        dark_pool.min_transfer_interval_slots = 0;
+        dark_pool.registration_ttl_seconds = 0;
+        dark_pool.min_claim_delay_slots = 0;
+        dark_pool.max_volume_per_window = 0;
+        dark_pool.window_slots = 0;
+        dark_pool.max_transfers_per_slot = 0;
+        dark_pool.throughput_slot = 0;
+        dark_pool.transfers_this_slot = 0;
+        dark_pool.registration_fee_lamports = registration_fee_lamports;
+        dark_pool.allowed_denominations = Vec::new();
+        dark_pool.max_total_volume = 0;
         
         // Initialize with encrypted zero
         let cpi_ctx = CpiContext::new(
@@ -57,7 +115,8 @@ pub mod agent_dark_pool {
             mint: dark_pool.mint,
             min_amount: min_transfer_amount,
             max_amount: max_transfer_amount,
-            timestamp: Clock::get()?.unix_timestamp,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
@@ -68,8 +127,37 @@ pub mod agent_dark_pool {
         ctx: Context<RegisterToPool>,
         nullifier: [u8; 32],
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, crate::ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, crate::ErrorCode::ProtocolPaused);
+        require!(
+            !ctx.accounts.protocol_config.is_module_paused(crate::MODULE_DARKPOOL),
+            crate::ErrorCode::ModulePaused
+        );
         let pool = &ctx.accounts.dark_pool;
         require!(pool.is_active, ErrorCode::DarkPoolInactive);
+        require!(
+            pool.registered_commitments.len() < MAX_POOL_REGISTRANTS,
+            ErrorCode::PoolFull
+        );
+        
+        // Sybil deterrent: each identity added to the anonymity set costs the owner a fee
+        let fee_paid = pool.registration_fee_lamports;
+        if fee_paid > 0 {
+            require!(
+                ctx.accounts.agent_owner.lamports() >= fee_paid,
+                ErrorCode::InsufficientRegistrationFee
+            );
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.agent_owner.to_account_info(),
+                        to: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                ),
+                fee_paid,
+            )?;
+        }
         
         let registration = &mut ctx.accounts.pool_registration;
         registration.pool = ctx.accounts.dark_pool.key();
@@ -78,19 +166,248 @@ pub mod agent_dark_pool {
         registration.nullifier = nullifier;
         registration.registration_nonce = 0;
         registration.is_active = true;
+        registration.bump = ctx.bumps.pool_registration;
+        registration.last_transfer_slot = 0;
+        registration.registered_at = Clock::get()?.unix_timestamp;
+        // The encrypted window volume is set to zero on the first capped transfer
+        registration.window_start_slot = 0;
+        registration.window_volume = Euint128::default();
         
-        // Generate ZK-friendly commitment
+        // Generate ZK-friendly commitment (pool-scoped so it can't be replayed across pools)
         let commitment = Pubkey::find_program_address(
-            &[b"pool_commitment", nullifier.as_ref()],
+            &[b"pool_commitment", registration.pool.as_ref(), nullifier.as_ref()],
             &crate::ID
         ).0;
         registration.zk_commitment = commitment;
         
+        let pool = &mut ctx.accounts.dark_pool;
+        require!(
+            !pool.registered_commitments.contains(&commitment),
+            ErrorCode::DuplicateNullifier
+        );
+        pool.registered_commitments.push(commitment);
+        pool.registration_root = compute_registration_root(&pool.registered_commitments);
+        
         emit!(AgentPoolRegistered {
             pool: pool.key(),
             agent: registration.agent,
             commitment,
-            timestamp: Clock::get()?.unix_timestamp,
+            fee_paid,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        emit!(RegistrationRootUpdated {
+            pool: pool.key(),
+            registration_root: pool.registration_root,
+            registrant_count: pool.registered_commitments.len() as u32,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Register several of one owner's agents at once. `remaining_accounts` holds an
+    /// (agent, pool_registration PDA) pair per entry, in order; any failure fails the batch.
+    pub fn register_to_pool_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, RegisterToPoolBatch<'info>>,
+        registrations: Vec<PoolRegInit>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, crate::ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, crate::ErrorCode::ProtocolPaused);
+        require!(
+            !ctx.accounts.protocol_config.is_module_paused(crate::MODULE_DARKPOOL),
+            crate::ErrorCode::ModulePaused
+        );
+        let pool = &ctx.accounts.dark_pool;
+        require!(pool.is_active, ErrorCode::DarkPoolInactive);
+        require!(
+            !registrations.is_empty() && registrations.len() <= MAX_BATCH_REGISTRATIONS,
+            ErrorCode::RegistrationBatchTooLarge
+        );
+        require!(
+            ctx.remaining_accounts.len() == registrations.len() * 2,
+            ErrorCode::InvalidBatchAccounts
+        );
+        require!(
+            pool.registered_commitments.len() + registrations.len() <= MAX_POOL_REGISTRANTS,
+            ErrorCode::PoolFull
+        );
+        
+        let pool_key = pool.key();
+        let owner_key = ctx.accounts.agent_owner.key();
+        let registered_at = Clock::get()?.unix_timestamp;
+        let rent = Rent::get()?.minimum_balance(POOL_REGISTRATION_SPACE);
+        let mut agents = Vec::with_capacity(registrations.len());
+        let mut commitments = Vec::with_capacity(registrations.len());
+        for (i, entry) in registrations.iter().enumerate() {
+            let agent_info = &ctx.remaining_accounts[i * 2];
+            let registration_info = &ctx.remaining_accounts[i * 2 + 1];
+            require_keys_eq!(agent_info.key(), entry.agent, ErrorCode::InvalidBatchAccounts);
+            let agent = Account::<crate::Agent>::try_from(agent_info)?;
+            require_keys_eq!(agent.owner, owner_key, ErrorCode::Unauthorized);
+            
+            let commitment = Pubkey::find_program_address(
+                &[b"pool_commitment", pool_key.as_ref(), entry.nullifier.as_ref()],
+                &crate::ID
+            ).0;
+            require!(
+                !pool.registered_commitments.contains(&commitment) && !commitments.contains(&commitment),
+                ErrorCode::DuplicateNullifier
+            );
+            
+            let (expected, bump) = Pubkey::find_program_address(
+                &[b"poolreg", pool_key.as_ref(), entry.agent.as_ref()],
+                &crate::ID
+            );
+            require_keys_eq!(registration_info.key(), expected, ErrorCode::InvalidBatchAccounts);
+            
+            // Created the way Anchor's init does it (top up, allocate, assign), so lamports sent to
+            // the PDA ahead of time can't block registration. Allocating fails if the agent is
+            // already registered to this pool, since the PDA is then program-owned.
+            let bump_seed = [bump];
+            let signer_seeds: &[&[u8]] = &[b"poolreg", pool_key.as_ref(), entry.agent.as_ref(), &bump_seed];
+            let top_up = rent.saturating_sub(registration_info.lamports());
+            if top_up > 0 {
+                anchor_lang::system_program::transfer(
+                    CpiContext::new(
+                        ctx.accounts.system_program.to_account_info(),
+                        anchor_lang::system_program::Transfer {
+                            from: ctx.accounts.agent_owner.to_account_info(),
+                            to: registration_info.clone(),
+                        },
+                    ),
+                    top_up,
+                )?;
+            }
+            anchor_lang::system_program::allocate(
+                CpiContext::new_with_signer(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Allocate {
+                        account_to_allocate: registration_info.clone(),
+                    },
+                    &[signer_seeds],
+                ),
+                POOL_REGISTRATION_SPACE as u64,
+            )?;
+            anchor_lang::system_program::assign(
+                CpiContext::new_with_signer(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Assign {
+                        account_to_assign: registration_info.clone(),
+                    },
+                    &[signer_seeds],
+                ),
+                &crate::ID,
+            )?;
+            let registration = PoolRegistration {
+                pool: pool_key,
+                agent: entry.agent,
+                agent_owner: owner_key,
+                nullifier: entry.nullifier,
+                registration_nonce: 0,
+                zk_commitment: commitment,
+                is_active: true,
+                bump,
+                last_transfer_slot: 0,
+                registered_at,
+                window_volume: Euint128::default(),
+                window_start_slot: 0,
+            };
+            registration.try_serialize(&mut &mut registration_info.try_borrow_mut_data()?[..])?;
+            
+            agents.push(entry.agent);
+            commitments.push(commitment);
+        }
+        
+        let fee_paid = pool
+            .registration_fee_lamports
+            .checked_mul(registrations.len() as u64)
+            .ok_or(ErrorCode::InsufficientRegistrationFee)?;
+        if fee_paid > 0 {
+            require!(
+                ctx.accounts.agent_owner.lamports() >= fee_paid,
+                ErrorCode::InsufficientRegistrationFee
+            );
+            anchor_lang::system_program::transfer(
+                CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::Transfer {
+                        from: ctx.accounts.agent_owner.to_account_info(),
+                        to: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                ),
+                fee_paid,
+            )?;
+        }
+        
+        let pool = &mut ctx.accounts.dark_pool;
+        pool.registered_commitments.extend(commitments.iter().copied());
+        pool.registration_root = compute_registration_root(&pool.registered_commitments);
+        
+        emit!(AgentsBatchPoolRegistered {
+            pool: pool.key(),
+            agents,
+            commitments,
+            fee_paid,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        emit!(RegistrationRootUpdated {
+            pool: pool.key(),
+            registration_root: pool.registration_root,
+            registrant_count: pool.registered_commitments.len() as u32,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Leave a dark pool, removing the registration's commitment from the anonymity set
+    pub fn deregister_from_pool(ctx: Context<DeregisterFromPool>) -> Result<()> {
+        let registration = &mut ctx.accounts.pool_registration;
+        require!(registration.is_active, ErrorCode::AgentNotRegistered);
+        registration.is_active = false;
+        
+        let pool = &mut ctx.accounts.dark_pool;
+        let commitment = registration.zk_commitment;
+        // Drop only this registration's entry
+        if let Some(index) = pool.registered_commitments.iter().position(|c| *c == commitment) {
+            pool.registered_commitments.swap_remove(index);
+        }
+        pool.registration_root = compute_registration_root(&pool.registered_commitments);
+        
+        emit!(AgentPoolDeregistered {
+            pool: pool.key(),
+            agent: registration.agent,
+            commitment,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        emit!(RegistrationRootUpdated {
+            pool: pool.key(),
+            registration_root: pool.registration_root,
+            registrant_count: pool.registered_commitments.len() as u32,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Restart a registration's TTL window so it keeps counting as active
+    pub fn refresh_registration(ctx: Context<RefreshRegistration>) -> Result<()> {
+        let registration = &mut ctx.accounts.pool_registration;
+        require!(registration.is_active, ErrorCode::AgentNotRegistered);
+        registration.registered_at = Clock::get()?.unix_timestamp;
+        
+        emit!(PoolRegistrationRefreshed {
+            pool: ctx.accounts.dark_pool.key(),
+            agent: registration.agent,
+            registered_at: registration.registered_at,
+            unix_timestamp: registration.registered_at,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
@@ -104,9 +421,64 @@ pub mod agent_dark_pool {
         recipient_commitment: Pubkey,
         zk_proof: Vec<u8>,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, crate::ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, crate::ErrorCode::ProtocolPaused);
+        require!(
+            !ctx.accounts.protocol_config.is_module_paused(crate::MODULE_DARKPOOL),
+            crate::ErrorCode::ModulePaused
+        );
         let pool = &ctx.accounts.dark_pool;
         require!(pool.is_active, ErrorCode::DarkPoolInactive);
+        // The last decrypted total already at the cap; the encrypted check below covers the rest
+        require!(
+            pool.max_total_volume == 0 || pool.total_volume < pool.max_total_volume,
+            ErrorCode::PoolVolumeCapReached
+        );
         require!(zk_proof.len() == 256, ErrorCode::InvalidProofSize);
+        // An uninitialized or memset buffer is never a real proof, whatever the verifier says
+        require!(zk_proof.iter().any(|b| *b != zk_proof[0]), ErrorCode::InvalidProof);
+        
+        // Reentrancy guard: persist the flag before any CPI so a re-entrant call into
+        // this pool sees it set. Cleared when the transfer completes.
+        require!(!pool.in_progress, ErrorCode::ReentrancyDetected);
+        
+        // Backpressure: each transfer creates a compressed account, so bound how many land per slot
+        let current_slot = Clock::get()?.slot;
+        let pool = &mut ctx.accounts.dark_pool;
+        if pool.throughput_slot != current_slot {
+            pool.throughput_slot = current_slot;
+            pool.transfers_this_slot = 0;
+        }
+        require!(
+            pool.max_transfers_per_slot == 0 || pool.transfers_this_slot < pool.max_transfers_per_slot,
+            ErrorCode::PoolThroughputExceeded
+        );
+        
+        ctx.accounts.dark_pool.in_progress = true;
+        ctx.accounts.dark_pool.exit(&crate::ID)?;
+        let pool = &ctx.accounts.dark_pool;
+        
+        // Temporal mixing: a registrant can't transfer again within the pool's interval
+        let registration = &mut ctx.accounts.sender_registration;
+        require!(registration.is_active, ErrorCode::AgentNotRegistered);
+        // Stale registrations shouldn't count toward the anonymity set; refresh to keep transferring
+        require!(
+            pool.registration_ttl_seconds == 0
+                || Clock::get()?.unix_timestamp - registration.registered_at <= pool.registration_ttl_seconds,
+            ErrorCode::RegistrationExpired
+        );
+        require!(
+            registration.last_transfer_slot == 0
+                || current_slot.saturating_sub(registration.last_transfer_slot) >= pool.min_transfer_interval_slots,
+            ErrorCode::TransferTooSoon
+        );
+        
+        // Spending the nullifier: the init on spent_nullifier fails if it was already used in this pool
+        let spent = &mut ctx.accounts.spent_nullifier;
+        spent.pool = pool.key();
+        spent.nullifier = sender_nullifier;
+        spent.spent_slot = Clock::get()?.slot;
+        spent.bump = ctx.bumps.spent_nullifier;
         
         // Verify ZK proof BEFORE processing
         verify_zk_proof(
@@ -117,8 +489,23 @@ pub mod agent_dark_pool {
                 recipient_commitment.as_ref(),
                 amount_ciphertext.as_slice(),
             ],
+            DARK_POOL_TRANSFER_PUBLIC_INPUTS,
         )?;
         
+        ctx.accounts.sender_registration.last_transfer_slot = current_slot;
+        ctx.accounts.dark_pool.transfers_this_slot += 1;
+        let pool = &ctx.accounts.dark_pool;
+        
+        // Every Inco op below signs as transfer_authority and ends in an e_add on the pool's
+        // encrypted volume, so it must hold a current membership in this pool's anonymity set
+        require!(
+            pool.registered_commitments.contains(&ctx.accounts.sender_registration.zk_commitment),
+            ErrorCode::Unauthorized
+        );
+        
+        // Events carry a digest of the ciphertext, taken before it moves into the Inco CPI
+        let amount_ciphertext_hash = keccak::hash(&amount_ciphertext).to_bytes();
+        
         // Load encrypted amount
         let cpi_ctx = CpiContext::new(
             ctx.accounts.inco_program.to_account_info(),
@@ -163,7 +550,42 @@ pub mod agent_dark_pool {
             ctx.accounts.inco_program.to_account_info(),
             Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
         );
-        let is_valid_amount: Ebool = e_and(cpi_ctx, gte_min, lte_max)?;
+        let mut is_valid_amount: Ebool = e_and(cpi_ctx, gte_min, lte_max)?;
+        
+        // Fixed denominations: the amount must equal one of them, checked without decrypting
+        if !pool.allowed_denominations.is_empty() {
+            let mut is_denomination: Option<Ebool> = None;
+            for denomination in pool.allowed_denominations.iter() {
+                let cpi_ctx = CpiContext::new(
+                    ctx.accounts.inco_program.to_account_info(),
+                    Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+                );
+                let denomination_enc = as_euint128(cpi_ctx, *denomination)?;
+                
+                let cpi_ctx = CpiContext::new(
+                    ctx.accounts.inco_program.to_account_info(),
+                    Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+                );
+                let matches: Ebool = e_eq(cpi_ctx, transfer_amount, denomination_enc, 0u8)?;
+                
+                is_denomination = Some(match is_denomination {
+                    None => matches,
+                    Some(any) => {
+                        let cpi_ctx = CpiContext::new(
+                            ctx.accounts.inco_program.to_account_info(),
+                            Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+                        );
+                        e_or(cpi_ctx, any, matches, 0u8)?
+                    }
+                });
+            }
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            is_valid_amount = e_and(cpi_ctx, is_valid_amount, is_denomination.unwrap(), 0u8)?;
+        }
         
         // If amount is invalid, transfer zero instead
         let cpi_ctx = CpiContext::new(
@@ -178,6 +600,85 @@ pub mod agent_dark_pool {
         );
         let final_amount = e_select(cpi_ctx, is_valid_amount, transfer_amount, zero, 0u8)?;
         
+        // Velocity limit per registrant per window. The amount is encrypted, so a transfer that
+        // would exceed the cap is zeroed homomorphically, the same way out-of-bounds amounts are.
+        let final_amount = if pool.max_volume_per_window > 0 && pool.window_slots > 0 {
+            let registration = &mut ctx.accounts.sender_registration;
+            if registration.window_start_slot == 0
+                || current_slot.saturating_sub(registration.window_start_slot) >= pool.window_slots
+            {
+                let cpi_ctx = CpiContext::new(
+                    ctx.accounts.inco_program.to_account_info(),
+                    Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+                );
+                registration.window_volume = as_euint128(cpi_ctx, 0)?;
+                registration.window_start_slot = current_slot;
+            }
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            let window_cap = as_euint128(cpi_ctx, pool.max_volume_per_window)?;
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            let projected = e_add(cpi_ctx, registration.window_volume, final_amount, 0u8)?;
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            let within_cap: Ebool = e_le(cpi_ctx, projected, window_cap, 0u8)?;
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            let capped_amount = e_select(cpi_ctx, within_cap, final_amount, zero, 0u8)?;
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            registration.window_volume = e_add(cpi_ctx, registration.window_volume, capped_amount, 0u8)?;
+            capped_amount
+        } else {
+            final_amount
+        };
+        
+        // Pool-wide volume cap, zeroing a transfer that would carry the encrypted total past it
+        let max_total_volume = ctx.accounts.dark_pool.max_total_volume;
+        let final_amount = if max_total_volume > 0 {
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            let volume_cap = as_euint128(cpi_ctx, max_total_volume)?;
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            let projected = e_add(cpi_ctx, ctx.accounts.dark_pool.total_volume_encrypted, final_amount, 0u8)?;
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            let within_cap: Ebool = e_le(cpi_ctx, projected, volume_cap, 0u8)?;
+            
+            let cpi_ctx = CpiContext::new(
+                ctx.accounts.inco_program.to_account_info(),
+                Operation { signer: ctx.accounts.transfer_authority.to_account_info() }
+            );
+            e_select(cpi_ctx, within_cap, final_amount, zero, 0u8)?
+        } else {
+            final_amount
+        };
+        
         // Update pool's encrypted total volume
         let pool = &mut ctx.accounts.dark_pool;
         let cpi_ctx = CpiContext::new(
@@ -193,10 +694,7 @@ pub mod agent_dark_pool {
         // Create compressed transfer record (hides details)
         let transfer_record = PoolTransferRecord {
             pool: pool.key(),
-            sender_commitment: Pubkey::find_program_address(
-                &[b"nullifier", sender_nullifier.as_ref()],
-                &crate::ID
-            ).0,
+            sender_commitment: ctx.accounts.spent_nullifier.key(),
             recipient_commitment,
             amount_ciphertext: ctx.accounts.transfer_authority.key(), // Store authority as proof
             transfer_slot: Clock::get()?.slot,
@@ -210,11 +708,264 @@ pub mod agent_dark_pool {
             &ctx.accounts.light_system_program,
         )?;
         
-        emit!(DarkPoolTransferExecuted {
+        pool.in_progress = false;
+        
+        if ctx.accounts.protocol_config.emit_events {
+            emit!(DarkPoolTransferExecuted {
+                pool: pool.key(),
+                transfer_slot: Clock::get()?.slot,
+                amount_ciphertext_hash,
+                unix_timestamp: Clock::get()?.unix_timestamp,
+                slot: Clock::get()?.slot,
+            });
+        }
+        
+        Ok(())
+    }
+
+    /// Ask Inco to decrypt the pool's encrypted total volume (bounded to protect the callback queue)
+    pub fn request_volume_decryption(ctx: Context<RequestVolumeDecryption>) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        require!(pool.is_active, ErrorCode::DarkPoolInactive);
+        require!(
+            pool.pending_decryptions < pool.max_pending_decryptions,
+            ErrorCode::TooManyPendingDecryptions
+        );
+        
+        pool.pending_decryptions = pool.pending_decryptions.checked_add(1).unwrap();
+        
+        emit!(VolumeDecryptionRequested {
             pool: pool.key(),
-            transfer_slot: Clock::get()?.slot,
-            amount_ciphertext_hash: amount_ciphertext[..32].try_into().unwrap(),
-            timestamp: Clock::get()?.unix_timestamp,
+            requester: ctx.accounts.requester.key(),
+            pending_decryptions: pool.pending_decryptions,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Deliver a decrypted volume for an outstanding request, freeing its queue slot
+    pub fn fulfill_volume_decryption(
+        ctx: Context<FulfillVolumeDecryption>,
+        decrypted_volume: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        require!(pool.pending_decryptions > 0, ErrorCode::NoPendingDecryption);
+        
+        pool.pending_decryptions -= 1;
+        pool.total_volume = decrypted_volume;
+        
+        emit!(VolumeDecryptionFulfilled {
+            pool: pool.key(),
+            total_volume: decrypted_volume,
+            pending_decryptions: pool.pending_decryptions,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Pool authority sets how many decryption requests may be outstanding at once
+    pub fn set_max_pending_decryptions(
+        ctx: Context<UpdateDarkPool>,
+        max_pending_decryptions: u32,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        pool.max_pending_decryptions = max_pending_decryptions;
+        Ok(())
+    }
+
+    /// Point the pool at a new ZK verification key, e.g. after a circuit fix
+    pub fn rotate_verification_key(
+        ctx: Context<UpdateDarkPool>,
+        new_vk: Pubkey,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        let old_vk = pool.verification_key;
+        pool.verification_key = new_vk;
+        
+        emit!(VerificationKeyRotated {
+            pool: pool.key(),
+            old_vk,
+            new_vk,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn set_min_transfer_interval_slots(
+        ctx: Context<UpdateDarkPool>,
+        min_transfer_interval_slots: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        pool.min_transfer_interval_slots = min_transfer_interval_slots;
+        Ok(())
+    }
+
+    /// Seconds a registration stays valid for transfers before it must be refreshed (0 = never expires)
+    pub fn set_registration_ttl(
+        ctx: Context<UpdateDarkPool>,
+        registration_ttl_seconds: i64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        pool.registration_ttl_seconds = registration_ttl_seconds.max(0);
+        Ok(())
+    }
+
+    pub fn set_min_claim_delay_slots(
+        ctx: Context<UpdateDarkPool>,
+        min_claim_delay_slots: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        pool.min_claim_delay_slots = min_claim_delay_slots;
+        Ok(())
+    }
+
+    /// Cap how much one registrant can move per `window_slots` (either 0 = no cap)
+    pub fn set_velocity_limit(
+        ctx: Context<UpdateDarkPool>,
+        max_volume_per_window: u64,
+        window_slots: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        pool.max_volume_per_window = max_volume_per_window;
+        pool.window_slots = window_slots;
+        Ok(())
+    }
+
+    /// Restrict transfers to fixed amounts so amounts don't fingerprint senders (empty = any amount)
+    pub fn set_allowed_denominations(
+        ctx: Context<UpdateDarkPool>,
+        allowed_denominations: Vec<u64>,
+    ) -> Result<()> {
+        require!(allowed_denominations.len() <= MAX_DENOMINATIONS, ErrorCode::InvalidDenomination);
+        let pool = &mut ctx.accounts.dark_pool;
+        for (i, denomination) in allowed_denominations.iter().enumerate() {
+            require!(
+                *denomination > 0
+                    && *denomination >= pool.min_amount
+                    && *denomination <= pool.max_amount
+                    && !allowed_denominations[..i].contains(denomination),
+                ErrorCode::InvalidDenomination
+            );
+        }
+        pool.allowed_denominations = allowed_denominations;
+        Ok(())
+    }
+
+    /// Hard cap on the pool's cumulative volume (0 = unlimited)
+    pub fn set_max_total_volume(
+        ctx: Context<UpdateDarkPool>,
+        max_total_volume: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        pool.max_total_volume = max_total_volume;
+        Ok(())
+    }
+
+    /// Transfers (and so compressed accounts) the pool accepts per slot (0 = unlimited)
+    pub fn set_max_transfers_per_slot(
+        ctx: Context<UpdateDarkPool>,
+        max_transfers_per_slot: u32,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.dark_pool;
+        pool.max_transfers_per_slot = max_transfers_per_slot;
+        Ok(())
+    }
+
+    /// Operational safety valve: recount `total_transfers` from the pool's SpentNullifier
+    /// accounts, which `dark_pool_transfer` creates once per completed transfer. The authority
+    /// passes them in `remaining_accounts`; duplicates and accounts for other pools are ignored,
+    /// so reconciling the same set twice is a no-op.
+    pub fn reconcile_pool(ctx: Context<ReconcilePool>) -> Result<()> {
+        let pool_key = ctx.accounts.dark_pool.key();
+        require!(!ctx.accounts.dark_pool.in_progress, ErrorCode::ReentrancyDetected);
+        
+        let mut spent: Vec<Pubkey> = Vec::new();
+        for account in ctx.remaining_accounts.iter() {
+            if *account.owner != crate::ID {
+                continue;
+            }
+            let Ok(record) = SpentNullifier::try_deserialize(&mut &account.data.borrow()[..]) else {
+                continue;
+            };
+            let expected = Pubkey::create_program_address(
+                &[b"nullifier", pool_key.as_ref(), record.nullifier.as_ref(), &[record.bump]],
+                &crate::ID,
+            );
+            if record.pool == pool_key && expected.ok() == Some(account.key()) {
+                spent.push(account.key());
+            }
+        }
+        spent.sort();
+        spent.dedup();
+        
+        let pool = &mut ctx.accounts.dark_pool;
+        let previous_total_transfers = pool.total_transfers;
+        pool.total_transfers = spent.len() as u64;
+        
+        emit!(PoolReconciled {
+            pool: pool_key,
+            previous_total_transfers,
+            total_transfers: pool.total_transfers,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Read-only pre-flight for clients building a transfer. `remaining_accounts` holds the
+    /// SpentNullifier PDA for each entry of `nullifiers`, in order; a PDA that holds a record
+    /// means that nullifier is already spent.
+    pub fn check_nullifiers_unspent(
+        ctx: Context<CheckNullifiersUnspent>,
+        nullifiers: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(nullifiers.len() <= MAX_NULLIFIER_BATCH, ErrorCode::NullifierBatchTooLarge);
+        require!(
+            ctx.remaining_accounts.len() == nullifiers.len(),
+            ErrorCode::InvalidNullifierAccount
+        );
+        
+        let pool_key = ctx.accounts.dark_pool.key();
+        let mut spent = Vec::with_capacity(nullifiers.len());
+        for (nullifier, account) in nullifiers.iter().zip(ctx.remaining_accounts.iter()) {
+            let (expected, _) = Pubkey::find_program_address(
+                &[b"nullifier", pool_key.as_ref(), nullifier.as_ref()],
+                &crate::ID,
+            );
+            require!(account.key() == expected, ErrorCode::InvalidNullifierAccount);
+            spent.push(*account.owner == crate::ID && !account.data_is_empty());
+        }
+        
+        emit!(NullifierStatus {
+            pool: pool_key,
+            nullifiers,
+            spent,
+        });
+        
+        Ok(())
+    }
+
+    /// Permanently close a dark pool, returning its rent to the authority
+    pub fn close_dark_pool(ctx: Context<CloseDarkPool>) -> Result<()> {
+        let pool = &ctx.accounts.dark_pool;
+        require!(!pool.in_progress, ErrorCode::ReentrancyDetected);
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        require!(protocol.initialized, crate::ErrorCode::ProtocolNotInitialized);
+        protocol.total_dark_pools = protocol.total_dark_pools.saturating_sub(1);
+        
+        emit!(DarkPoolClosed {
+            pool: pool.key(),
+            pool_id: pool.pool_id.clone(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
@@ -226,12 +977,25 @@ pub mod agent_dark_pool {
         decryption_key: [u8; 32],
         transfer_slot: u64,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, crate::ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, crate::ErrorCode::ProtocolPaused);
+        require!(
+            !ctx.accounts.protocol_config.is_module_paused(crate::MODULE_DARKPOOL),
+            crate::ErrorCode::ModulePaused
+        );
+        
         // Verify recipient owns this transfer
         require!(
             ctx.accounts.recipient.key() == ctx.accounts.recipient_account.owner,
             ErrorCode::Unauthorized
         );
         
+        // Claiming right after the transfer would correlate sender and recipient by timing
+        require!(
+            Clock::get()?.slot.saturating_sub(transfer_slot) >= ctx.accounts.dark_pool.min_claim_delay_slots,
+            ErrorCode::ClaimTooSoon
+        );
+        
         // Derive expected commitment from decryption key
         let expected_commitment = Pubkey::find_program_address(
             &[b"decrypt", decryption_key.as_ref()],
@@ -256,6 +1020,7 @@ pub mod agent_dark_pool {
         claim_record.decryption_key_hash = decryption_key[..20].try_into().unwrap();
         claim_record.is_claimed = true;
         claim_record.claimed_at = Clock::get()?.unix_timestamp;
+        claim_record.bump = ctx.bumps.claim_record;
         
         // In real implementation: decrypt and transfer tokens here
         // For this demo, we just record the claim
@@ -265,7 +1030,8 @@ pub mod agent_dark_pool {
             recipient: ctx.accounts.recipient.key(),
             transfer_slot,
             claimed_amount: 0, // Would be decrypted amount
-            timestamp: Clock::get()?.unix_timestamp,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
@@ -285,6 +1051,35 @@ pub struct DarkPool {
     pub max_amount: u64,
     pub is_active: bool,
     pub authority: Pubkey,
+    pub pending_decryptions: u32,
+    pub max_pending_decryptions: u32,
+    pub bump: u8,
+    // Active registration commitments and the merkle root over them, for anonymity-set proofs
+    pub registered_commitments: Vec<Pubkey>,
+    pub registration_root: [u8; 32],
+    // Set for the duration of CPI-heavy instructions to reject re-entry
+    pub in_progress: bool,
+    // Only proofs checked against this key are accepted by dark_pool_transfer
+    pub verification_key: Pubkey,
+    // Slots a registrant must wait between transfers (0 = no minimum)
+    pub min_transfer_interval_slots: u64,
+    // Seconds a registration can transfer before it must be refreshed (0 = never expires)
+    pub registration_ttl_seconds: i64,
+    // Slots a recipient must wait after a transfer before claiming it (0 = no delay)
+    pub min_claim_delay_slots: u64,
+    // Velocity limit: volume one registrant may move per window of window_slots (0 = no cap)
+    pub max_volume_per_window: u64,
+    pub window_slots: u64,
+    // Per-slot transfer cap (0 = unlimited) and the count for throughput_slot
+    pub max_transfers_per_slot: u32,
+    pub throughput_slot: u64,
+    pub transfers_this_slot: u32,
+    // Lamports register_to_pool charges the agent owner, paid to the authority (0 = free)
+    pub registration_fee_lamports: u64,
+    // Amounts dark_pool_transfer accepts, at most MAX_DENOMINATIONS (empty = any in bounds)
+    pub allowed_denominations: Vec<u64>,
+    // Cumulative volume the pool accepts (0 = unlimited)
+    pub max_total_volume: u64,
 }
 
 #[account]
@@ -296,10 +1091,33 @@ pub struct PoolRegistration {
     pub registration_nonce: u64,
     pub zk_commitment: Pubkey,
     pub is_active: bool,
+    pub bump: u8,
+    pub last_transfer_slot: u64,
+    // Registration time, restarted by refresh_registration
+    pub registered_at: i64,
+    // Encrypted volume moved in the current velocity window and the slot it began
+    pub window_volume: Euint128,
+    pub window_start_slot: u64,
+}
+
+/// Marks a nullifier as spent within one pool. Seeds are `[b"nullifier", pool, nullifier]`,
+/// so the same nullifier spends independently in each pool but only once per pool.
+#[account]
+pub struct SpentNullifier {
+    pub pool: Pubkey,
+    pub nullifier: [u8; 32],
+    pub spent_slot: u64,
+    pub bump: u8,
 }
 
 // --- CPI Structs ---
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PoolRegInit {
+    pub agent: Pubkey,
+    pub nullifier: [u8; 32],
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize, Clone)]
 pub struct PoolTransferRecord {
     pub pool: Pubkey,
@@ -313,12 +1131,24 @@ pub struct PoolTransferRecord {
 // --- Instructions ---
 
 #[derive(Accounts)]
+#[instruction(pool_id: String)]
 pub struct InitializeDarkPool<'info> {
-    #[account(init, payer = authority, space = 10240)]
+    #[account(
+        init,
+        payer = authority,
+        space = 10240,
+        seeds = [b"pool", pool_id.as_bytes()],
+        bump
+    )]
     pub dark_pool: Account<'info, DarkPool>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, crate::ProtocolConfig>,
+    /// CHECK: ZK verification key the pool's transfers are verified against
+    pub zk_verification_key: UncheckedAccount<'info>,
     /// CHECK: Mint account
     pub mint: UncheckedAccount<'info>,
-    /// CHECK: Inco program for FHE
+    /// CHECK: Inco program for FHE, pinned to the protocol's configured id
+    #[account(address = protocol_config.inco_program_id @ ErrorCode::InvalidProgramId)]
     pub inco_program: UncheckedAccount<'info>,
     #[account(mut)]
     pub authority: Signer<'info>,
@@ -327,25 +1157,104 @@ pub struct InitializeDarkPool<'info> {
 
 #[derive(Accounts)]
 pub struct RegisterToPool<'info> {
-    #[account(init, payer = agent_owner, space = 512)]
+    // One registration per agent per pool
+    #[account(
+        init,
+        payer = agent_owner,
+        space = POOL_REGISTRATION_SPACE,
+        seeds = [b"poolreg", dark_pool.key().as_ref(), agent.key().as_ref()],
+        bump
+    )]
     pub pool_registration: Account<'info, PoolRegistration>,
+    #[account(mut, seeds = [b"pool", dark_pool.pool_id.as_bytes()], bump = dark_pool.bump)]
     pub dark_pool: Account<'info, DarkPool>,
+    // Only the agent's owner may take its one registration in the pool
+    #[account(constraint = agent.owner == agent_owner.key() @ ErrorCode::Unauthorized)]
     pub agent: Account<'info, crate::Agent>,
     #[account(mut)]
     pub agent_owner: Signer<'info>,
+    /// CHECK: Receives the registration fee; must be the pool's authority
+    #[account(mut, address = dark_pool.authority @ ErrorCode::Unauthorized)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, crate::ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+// Each entry's agent and pool_registration PDA are passed as remaining_accounts
+#[derive(Accounts)]
+pub struct RegisterToPoolBatch<'info> {
+    #[account(mut, seeds = [b"pool", dark_pool.pool_id.as_bytes()], bump = dark_pool.bump)]
+    pub dark_pool: Account<'info, DarkPool>,
+    #[account(mut)]
+    pub agent_owner: Signer<'info>,
+    /// CHECK: Receives the registration fees; must be the pool's authority
+    #[account(mut, address = dark_pool.authority @ ErrorCode::Unauthorized)]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, crate::ProtocolConfig>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
+pub struct DeregisterFromPool<'info> {
+    #[account(
+        mut,
+        seeds = [b"poolreg", dark_pool.key().as_ref(), pool_registration.agent.as_ref()],
+        bump = pool_registration.bump,
+        has_one = agent_owner @ ErrorCode::Unauthorized
+    )]
+    pub pool_registration: Account<'info, PoolRegistration>,
+    #[account(mut, seeds = [b"pool", dark_pool.pool_id.as_bytes()], bump = dark_pool.bump)]
+    pub dark_pool: Account<'info, DarkPool>,
+    pub agent_owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RefreshRegistration<'info> {
+    #[account(
+        mut,
+        seeds = [b"poolreg", dark_pool.key().as_ref(), pool_registration.agent.as_ref()],
+        bump = pool_registration.bump,
+        has_one = agent_owner @ ErrorCode::Unauthorized
+    )]
+    pub pool_registration: Account<'info, PoolRegistration>,
+    pub dark_pool: Account<'info, DarkPool>,
+    pub agent_owner: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(amount_ciphertext: Vec<u8>, sender_nullifier: [u8; 32])]
 pub struct DarkPoolTransfer<'info> {
     #[account(mut)]
     pub dark_pool: Account<'info, DarkPool>,
-    /// CHECK: ZK verification key
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + 32 + 32 + 8 + 1,
+        seeds = [b"nullifier", dark_pool.key().as_ref(), sender_nullifier.as_ref()],
+        bump
+    )]
+    pub spent_nullifier: Account<'info, SpentNullifier>,
+    #[account(
+        mut,
+        seeds = [b"poolreg", dark_pool.key().as_ref(), sender_registration.agent.as_ref()],
+        bump = sender_registration.bump,
+        constraint = sender_registration.pool == dark_pool.key() @ ErrorCode::Unauthorized,
+        constraint = sender_registration.agent_owner == transfer_authority.key() @ ErrorCode::Unauthorized
+    )]
+    pub sender_registration: Account<'info, PoolRegistration>,
+    /// CHECK: ZK verification key, pinned to the one stored on the pool
+    #[account(address = dark_pool.verification_key @ ErrorCode::InvalidVerificationKey)]
     pub zk_verification_key: UncheckedAccount<'info>,
-    /// CHECK: Light system program
+    /// CHECK: Light system program, pinned to the protocol's configured id
+    #[account(address = protocol_config.light_system_program_id @ ErrorCode::InvalidProgramId)]
     pub light_system_program: UncheckedAccount<'info>,
-    /// CHECK: Inco program for FHE
+    /// CHECK: Inco program for FHE, pinned to the protocol's configured id
+    #[account(address = protocol_config.inco_program_id @ ErrorCode::InvalidProgramId)]
     pub inco_program: UncheckedAccount<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, crate::ProtocolConfig>,
     #[account(mut)]
     pub transfer_authority: Signer<'info>,
     #[account(mut)]
@@ -354,16 +1263,71 @@ pub struct DarkPoolTransfer<'info> {
 }
 
 #[derive(Accounts)]
+pub struct RequestVolumeDecryption<'info> {
+    #[account(mut)]
+    pub dark_pool: Account<'info, DarkPool>,
+    pub requester: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FulfillVolumeDecryption<'info> {
+    #[account(mut, has_one = authority @ ErrorCode::Unauthorized)]
+    pub dark_pool: Account<'info, DarkPool>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateDarkPool<'info> {
+    #[account(mut, has_one = authority @ ErrorCode::Unauthorized)]
+    pub dark_pool: Account<'info, DarkPool>,
+    pub authority: Signer<'info>,
+}
+
+// The pool's SpentNullifier accounts are passed as remaining_accounts
+#[derive(Accounts)]
+pub struct ReconcilePool<'info> {
+    #[account(mut, has_one = authority @ ErrorCode::Unauthorized)]
+    pub dark_pool: Account<'info, DarkPool>,
+    pub authority: Signer<'info>,
+}
+
+// The SpentNullifier PDAs to check are passed as remaining_accounts
+#[derive(Accounts)]
+pub struct CheckNullifiersUnspent<'info> {
+    pub dark_pool: Account<'info, DarkPool>,
+}
+
+#[derive(Accounts)]
+pub struct CloseDarkPool<'info> {
+    #[account(mut, has_one = authority @ ErrorCode::Unauthorized, close = authority)]
+    pub dark_pool: Account<'info, DarkPool>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, crate::ProtocolConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(decryption_key: [u8; 32], transfer_slot: u64)]
 pub struct ClaimPrivateTransfer<'info> {
     #[account(mut)]
     pub recipient: Signer<'info>,
     #[account(mut)]
     pub recipient_account: Account<'info, crate::Agent>,
     pub dark_pool: Account<'info, DarkPool>,
-    /// CHECK: Compressed transfer data
+    /// CHECK: Light system program holding the compressed transfer, pinned to the protocol's configured id
+    #[account(address = protocol_config.light_system_program_id @ ErrorCode::InvalidProgramId)]
     pub light_system_program: UncheckedAccount<'info>,
-    #[account(init, payer = recipient, space = 256)]
+    #[account(
+        init,
+        payer = recipient,
+        space = 256,
+        seeds = [b"claim", dark_pool.key().as_ref(), transfer_slot.to_le_bytes().as_ref()],
+        bump
+    )]
     pub claim_record: Account<'info, ClaimRecord>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, crate::ProtocolConfig>,
     pub system_program: Program<'info, System>,
 }
 
@@ -374,6 +1338,7 @@ pub struct ClaimRecord {
     pub decryption_key_hash: [u8; 20],
     pub is_claimed: bool,
     pub claimed_at: i64,
+    pub bump: u8,
 }
 
 // --- Events ---
@@ -384,7 +1349,42 @@ pub struct DarkPoolInitialized {
     pub mint: Pubkey,
     pub min_amount: u64,
     pub max_amount: u64,
-    pub timestamp: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct VerificationKeyRotated {
+    pub pool: Pubkey,
+    pub old_vk: Pubkey,
+    pub new_vk: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct PoolReconciled {
+    pub pool: Pubkey,
+    pub previous_total_transfers: u64,
+    pub total_transfers: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct NullifierStatus {
+    pub pool: Pubkey,
+    pub nullifiers: Vec<[u8; 32]>,
+    // spent[i] is true when nullifiers[i] has already been used in this pool
+    pub spent: Vec<bool>,
+}
+
+#[event]
+pub struct DarkPoolClosed {
+    pub pool: Pubkey,
+    pub pool_id: String,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
@@ -392,7 +1392,46 @@ pub struct AgentPoolRegistered {
     pub pool: Pubkey,
     pub agent: Pubkey,
     pub commitment: Pubkey,
-    pub timestamp: i64,
+    pub fee_paid: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AgentsBatchPoolRegistered {
+    pub pool: Pubkey,
+    pub agents: Vec<Pubkey>,
+    pub commitments: Vec<Pubkey>,
+    pub fee_paid: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AgentPoolDeregistered {
+    pub pool: Pubkey,
+    pub agent: Pubkey,
+    pub commitment: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct PoolRegistrationRefreshed {
+    pub pool: Pubkey,
+    pub agent: Pubkey,
+    pub registered_at: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RegistrationRootUpdated {
+    pub pool: Pubkey,
+    pub registration_root: [u8; 32],
+    pub registrant_count: u32,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
@@ -400,7 +1439,8 @@ pub struct DarkPoolTransferExecuted {
     pub pool: Pubkey,
     pub transfer_slot: u64,
     pub amount_ciphertext_hash: [u8; 32],
-    pub timestamp: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
@@ -409,12 +1449,32 @@ pub struct PrivateTransferClaimed {
     pub recipient: Pubkey,
     pub transfer_slot: u64,
     pub claimed_amount: u64,
-    pub timestamp: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct VolumeDecryptionRequested {
+    pub pool: Pubkey,
+    pub requester: Pubkey,
+    pub pending_decryptions: u32,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct VolumeDecryptionFulfilled {
+    pub pool: Pubkey,
+    pub total_volume: u64,
+    pub pending_decryptions: u32,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 // --- Error Codes ---
 
-#[error_code]
+// Offset past the protocol's own codes, which share this program's error space
+#[error_code(offset = 7000)]
 pub enum ErrorCode {
     #[msg("Dark pool is inactive")]
     DarkPoolInactive,
@@ -428,27 +1488,103 @@ pub enum ErrorCode {
     AgentNotRegistered,
     #[msg("Amount exceeds pool limits")]
     AmountOutOfBounds,
+    #[msg("Unauthorized operation")]
+    Unauthorized,
+    #[msg("Too many pending decryption requests")]
+    TooManyPendingDecryptions,
+    #[msg("No decryption request is pending")]
+    NoPendingDecryption,
+    #[msg("Pool ID too long")]
+    PoolIdTooLong,
+    #[msg("Dark pool has reached its registrant limit")]
+    PoolFull,
+    #[msg("Reentrant call detected")]
+    ReentrancyDetected,
+    #[msg("Protocol dark pool limit reached")]
+    PoolLimitReached,
+    #[msg("Verification key does not match the pool's")]
+    InvalidVerificationKey,
+    #[msg("Transfer is within the pool's minimum interval")]
+    TransferTooSoon,
+    #[msg("Program account does not match the configured program id")]
+    InvalidProgramId,
+    #[msg("Pool registration has expired; refresh it to transfer")]
+    RegistrationExpired,
+    #[msg("Transfer cannot be claimed until the pool's claim delay has passed")]
+    ClaimTooSoon,
+    #[msg("Pool has reached its transfer limit for this slot")]
+    PoolThroughputExceeded,
+    #[msg("Proof has more public inputs than allowed")]
+    TooManyPublicInputs,
+    #[msg("Too many nullifiers in one check")]
+    NullifierBatchTooLarge,
+    #[msg("Account is not the nullifier's SpentNullifier PDA")]
+    InvalidNullifierAccount,
+    #[msg("Insufficient lamports for the pool registration fee")]
+    InsufficientRegistrationFee,
+    #[msg("Denomination is not allowed by the pool")]
+    InvalidDenomination,
+    #[msg("Registration batch is empty or too large")]
+    RegistrationBatchTooLarge,
+    #[msg("Batch accounts do not match the registrations")]
+    InvalidBatchAccounts,
+    #[msg("Nullifier is already registered in this pool")]
+    DuplicateNullifier,
+    #[msg("Pool has reached its total volume cap")]
+    PoolVolumeCapReached,
 }
 
 // --- ZK Verification (Mock for Hackathon) ---
 // In production, would use groth16 or PLONK verification
 pub fn verify_zk_proof(
-    vk: &AccountInfo,
+    _vk: &AccountInfo,
     proof: &[u8],
     public_inputs: &[&[u8]],
+    expected_input_count: usize,
 ) -> Result<()> {
     // HACKATHON: Mock verification - always succeeds
     // TODO: Integrate with actual Light Protocol verifier
     require!(proof.len() == 256, ErrorCode::InvalidProofSize);
     require!(!public_inputs.is_empty(), ErrorCode::InvalidProof);
+    require!(public_inputs.len() <= MAX_PUBLIC_INPUTS, ErrorCode::TooManyPublicInputs);
+    require!(public_inputs.len() == expected_input_count, ErrorCode::InvalidProof);
     
     // Simulate verification delay
     msg!("ZK Proof verified (mock)");
     Ok(())
 }
 
+// --- Registration Root ---
+// Keccak merkle root over the sorted commitments, so any set yields the same root
+// regardless of registration order. Odd nodes are carried up unchanged.
+pub fn compute_registration_root(commitments: &[Pubkey]) -> [u8; 32] {
+    use anchor_lang::solana_program::keccak::hashv;
+    
+    if commitments.is_empty() {
+        return [0u8; 32];
+    }
+    
+    let mut level: Vec<[u8; 32]> = {
+        let mut sorted = commitments.to_vec();
+        sorted.sort();
+        sorted.iter().map(|c| hashv(&[c.as_ref()]).to_bytes()).collect()
+    };
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hashv(&[left.as_ref(), right.as_ref()]).to_bytes(),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
 // --- Compressed Account Helpers ---
 // Interfaces with Light Protocol V2
+#[allow(dead_code)] // Read by the Light CPI once it replaces the mock
 pub struct TransferCompressedAccount {
     data: Vec<u8>,
     proof: ValidityProof,
@@ -456,12 +1592,12 @@ pub struct TransferCompressedAccount {
 
 impl TransferCompressedAccount {
     pub fn create(
-        payer: AccountInfo,
+        _payer: AccountInfo,
         transfer_record: &PoolTransferRecord,
-        light_system: &AccountInfo,
+        _light_system: &AccountInfo,
     ) -> Result<Self> {
-        // Serialize transfer record
-        let mut data = Vec::new();
+        // Serialize transfer record behind its discriminator
+        let mut data = POOL_TRANSFER_RECORD_DISCRIMINATOR.to_vec();
         transfer_record.serialize(&mut data)?;
         
         // Mock proof (in production: generate ZK proof of validity)
@@ -472,7 +1608,7 @@ impl TransferCompressedAccount {
     
     pub fn fetch_by_slot(
         slot: u64,
-        light_system: &AccountInfo,
+        _light_system: &AccountInfo,
     ) -> Result<PoolTransferRecord> {
         // HACKATHON: Mock fetching - would query Light Protocol indexer
         
@@ -485,7 +1621,96 @@ impl TransferCompressedAccount {
             transfer_slot: slot,
             is_valid: true,
         };
+        let mut data = POOL_TRANSFER_RECORD_DISCRIMINATOR.to_vec();
+        dummy_record.serialize(&mut data)?;
         
-        Ok(dummy_record)
+        // Fetched bytes go through the same check real indexer data will
+        Self::decode(&data)
     }
-}
\ No newline at end of file
+    
+    /// Deserializes a PoolTransferRecord, rejecting bytes that don't carry its
+    /// discriminator so other layouts can't be passed off as transfer records.
+    pub fn decode(data: &[u8]) -> Result<PoolTransferRecord> {
+        let body = data
+            .strip_prefix(POOL_TRANSFER_RECORD_DISCRIMINATOR.as_slice())
+            .ok_or(ErrorCode::InvalidClaim)?;
+        PoolTransferRecord::deserialize(&mut &body[..]).map_err(|_| error!(ErrorCode::InvalidClaim))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> PoolTransferRecord {
+        PoolTransferRecord {
+            pool: Pubkey::new_unique(),
+            sender_commitment: Pubkey::new_unique(),
+            recipient_commitment: Pubkey::new_unique(),
+            amount_ciphertext: Pubkey::new_unique(),
+            transfer_slot: 42,
+            is_valid: true,
+        }
+    }
+
+    #[test]
+    fn registration_root_ignores_registration_order() {
+        let commitments: Vec<Pubkey> = (0..5).map(|_| Pubkey::new_unique()).collect();
+        let mut reversed = commitments.clone();
+        reversed.reverse();
+        
+        let root = compute_registration_root(&commitments);
+        assert_eq!(root, compute_registration_root(&commitments));
+        assert_eq!(root, compute_registration_root(&reversed));
+        assert_ne!(root, compute_registration_root(&commitments[..4]));
+        assert_eq!(compute_registration_root(&[]), [0u8; 32]);
+    }
+
+    #[test]
+    fn decode_round_trips_a_labeled_record() {
+        let original = record();
+        let mut data = POOL_TRANSFER_RECORD_DISCRIMINATOR.to_vec();
+        original.serialize(&mut data).unwrap();
+        
+        let decoded = TransferCompressedAccount::decode(&data).unwrap();
+        assert_eq!(decoded.recipient_commitment, original.recipient_commitment);
+        assert_eq!(decoded.transfer_slot, original.transfer_slot);
+    }
+
+    #[test]
+    fn decode_rejects_mislabeled_bytes() {
+        let mut mislabeled = b"otherrec".to_vec();
+        record().serialize(&mut mislabeled).unwrap();
+        assert_eq!(
+            TransferCompressedAccount::decode(&mislabeled).unwrap_err(),
+            Error::from(ErrorCode::InvalidClaim)
+        );
+        
+        // Right label, truncated body
+        let mut truncated = POOL_TRANSFER_RECORD_DISCRIMINATOR.to_vec();
+        truncated.extend_from_slice(&[0u8; 16]);
+        assert_eq!(
+            TransferCompressedAccount::decode(&truncated).unwrap_err(),
+            Error::from(ErrorCode::InvalidClaim)
+        );
+    }
+
+    #[test]
+    fn verify_zk_proof_rejects_too_many_public_inputs() {
+        let key = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let mut lamports = 0u64;
+        let mut data = [0u8; 0];
+        let vk = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+        let proof = [1u8; 256];
+        let input = [0u8; 32];
+        
+        let inputs = vec![input.as_slice(); MAX_PUBLIC_INPUTS + 1];
+        assert_eq!(
+            verify_zk_proof(&vk, &proof, &inputs, inputs.len()).unwrap_err(),
+            Error::from(ErrorCode::TooManyPublicInputs)
+        );
+        
+        let inputs = vec![input.as_slice(); DARK_POOL_TRANSFER_PUBLIC_INPUTS];
+        assert!(verify_zk_proof(&vk, &proof, &inputs, DARK_POOL_TRANSFER_PUBLIC_INPUTS).is_ok());
+    }
+}
@@ -1,26 +1,707 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::sysvar;
+use anchor_lang::solana_program::{ed25519_program, instruction::Instruction, secp256k1_program};
 use anchor_spl::token::{TokenAccount, Mint, Token, Transfer};
 use anchor_spl::associated_token::AssociatedToken;
 use std::mem::size_of;
 
 declare_id!("ocpP8j4zpgC9fqc3J2y6V3x9K1mNpRrL");
 
+// Dark pool instructions need the Light and Inco SDKs, so they only build with `--features darkpool`
+#[cfg(feature = "darkpool")]
+pub mod darkpool;
+#[cfg(feature = "darkpool")]
+pub use darkpool::*;
+
+// Channel participant roles. Member is zero so unset roles read as member.
+pub const ROLE_MEMBER: u8 = 0;
+pub const ROLE_ADMIN: u8 = 1;
+pub const ROLE_OBSERVER: u8 = 2;
+
+// Agent contact preference bits
+pub const CONTACT_CHANNEL: u8 = 1 << 0;
+pub const CONTACT_DM: u8 = 1 << 1;
+pub const CONTACT_ALL: u8 = CONTACT_CHANNEL | CONTACT_DM;
+
+// Algorithm a client compressed message plaintext with before encrypting it
+pub const COMPRESSION_NONE: u8 = 0;
+pub const COMPRESSION_ZSTD: u8 = 1;
+pub const COMPRESSION_GZIP: u8 = 2;
+
+// Native signature program authenticate_message expects the content signature checked by
+pub const SIG_SCHEME_ED25519: u8 = 0;
+pub const SIG_SCHEME_SECP256K1: u8 = 1;
+
+// Subsystems that can be paused independently through module_pause_flags
+pub const MODULE_MESSAGING: u16 = 1 << 0;
+pub const MODULE_CHANNELS: u16 = 1 << 1;
+pub const MODULE_SHIELDED: u16 = 1 << 2;
+pub const MODULE_DARKPOOL: u16 = 1 << 3;
+pub const MODULE_FAUCET: u16 = 1 << 4;
+pub const MODULE_ALL: u16 =
+    MODULE_MESSAGING | MODULE_CHANNELS | MODULE_SHIELDED | MODULE_DARKPOOL | MODULE_FAUCET;
+
+// Co-admins the primary authority can appoint for non-critical admin operations
+pub const MAX_PROTOCOL_ADMINS: usize = 5;
+
+// Reputation adjustments retained per agent in its ReputationLog ring buffer
+pub const REPUTATION_LOG_SIZE: usize = 16;
+
+// Pending transfers a shielded balance can hold before it must be settled
+pub const MAX_PENDING_TRANSFERS: usize = 16;
+
+// Each added participant grows the channel by one pubkey plus one role byte.
+// Channels are kept under the 10KB ceiling an account can be created or grown by in
+// a single instruction. With the largest channel_id (128) and metadata (512) the fixed
+// layout is ~713 bytes, so the practical maximum is (10240 - 713) / 33 = 288 participants.
+pub const PARTICIPANT_ENTRY_SIZE: usize = 32 + 1;
+pub const MAX_CHANNEL_ACCOUNT_SIZE: usize = 10_240;
+
+pub const CHANNEL_SEED: &[u8] = b"channel";
+
+pub const MESSAGE_SEED: &[u8] = b"message";
+pub const PARTICIPANT_CHANNELS_SEED: &[u8] = b"participant_channels";
+pub const TRANSFER_IDEMPOTENCY_SEED: &[u8] = b"transfer_idempotency";
+pub const REMOVAL_RECORD_SEED: &[u8] = b"removal";
+pub const CHANNEL_POLICY_SEED: &[u8] = b"channel_policy";
+
+// policy_id of the protocol authority's ChannelPolicy that governs channel creation
+pub const CHANNEL_CREATION_POLICY_ID: &[u8] = b"channel_creation";
+
+/// Derives the message PDA for a sender's message id within a channel, as used by `send_encrypted_message`.
+pub fn message_pda(channel: &Pubkey, sender: &Pubkey, message_id: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[MESSAGE_SEED, channel.as_ref(), sender.as_ref(), message_id.as_bytes()],
+        &crate::ID,
+    )
+}
+
+/// Reallocs `account` up to `new_size`, topping up rent from `payer`. Never shrinks.
+pub fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    new_size: usize,
+) -> Result<()> {
+    if new_size <= account.data_len() {
+        return Ok(());
+    }
+    let rent_due = Rent::get()?
+        .minimum_balance(new_size)
+        .saturating_sub(account.lamports());
+    if rent_due > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer {
+                    from: payer.clone(),
+                    to: account.clone(),
+                },
+            ),
+            rent_due,
+        )?;
+    }
+    account.realloc(new_size, false)?;
+    Ok(())
+}
+
+/// Bytes `account` takes once serialized, discriminator included.
+pub fn serialized_len<T: AccountSerialize>(account: &T) -> Result<usize> {
+    let mut data = Vec::new();
+    account.try_serialize(&mut data)?;
+    Ok(data.len())
+}
+
+/// Grows an account being migrated so its re-serialized form, new fields included, fits.
+pub fn grow_to_fit<'info, T>(
+    account: &Account<'info, T>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+) -> Result<()>
+where
+    T: AccountSerialize + AccountDeserialize + Owner + Clone,
+{
+    grow_account(&account.to_account_info(), payer, system_program, serialized_len(&**account)?)
+}
+
+/// Creates a program-owned PDA the way Anchor's `init` does (top up, allocate, assign), so
+/// lamports sent to the address ahead of time can't block its creation.
+pub fn create_pda_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    space: usize,
+    signer_seeds: &[&[u8]],
+) -> Result<()> {
+    let top_up = Rent::get()?.minimum_balance(space).saturating_sub(account.lamports());
+    if top_up > 0 {
+        anchor_lang::system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                anchor_lang::system_program::Transfer { from: payer.clone(), to: account.clone() },
+            ),
+            top_up,
+        )?;
+    }
+    anchor_lang::system_program::allocate(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Allocate { account_to_allocate: account.clone() },
+            &[signer_seeds],
+        ),
+        space as u64,
+    )?;
+    anchor_lang::system_program::assign(
+        CpiContext::new_with_signer(
+            system_program.clone(),
+            anchor_lang::system_program::Assign { account_to_assign: account.clone() },
+            &[signer_seeds],
+        ),
+        &crate::ID,
+    )
+}
+
+/// Counts `participant` into one more channel, creating their ParticipantChannelCount
+/// PDA on first use. Fails once they're in `max_channels` channels (0 = unlimited).
+pub fn increment_channel_count<'info>(
+    count_account: &AccountInfo<'info>,
+    participant: &Pubkey,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    max_channels: u64,
+) -> Result<()> {
+    let (expected, bump) = Pubkey::find_program_address(
+        &[PARTICIPANT_CHANNELS_SEED, participant.as_ref()],
+        &crate::ID,
+    );
+    require_keys_eq!(count_account.key(), expected, ErrorCode::InvalidChannelCountAccount);
+    
+    let mut count = if count_account.owner == &crate::ID {
+        ParticipantChannelCount::try_deserialize(&mut &count_account.try_borrow_data()?[..])?
+    } else {
+        create_pda_account(
+            count_account,
+            payer,
+            system_program,
+            size_of::<ParticipantChannelCount>() + 8,
+            &[PARTICIPANT_CHANNELS_SEED, participant.as_ref(), &[bump]],
+        )?;
+        ParticipantChannelCount { participant: *participant, channel_count: 0, bump }
+    };
+    
+    require!(
+        max_channels == 0 || count.channel_count < max_channels,
+        ErrorCode::ParticipantChannelLimitReached
+    );
+    count.channel_count = count.channel_count.checked_add(1).unwrap();
+    count.try_serialize(&mut &mut count_account.try_borrow_mut_data()?[..])?;
+    Ok(())
+}
+
+/// Enforces `readd_cooldown_seconds` against the participant's ParticipantRemovalRecord,
+/// which is uninitialized if they were never removed from the channel.
+pub fn check_readd_cooldown(removal_record: &AccountInfo, readd_cooldown_seconds: i64) -> Result<()> {
+    if readd_cooldown_seconds == 0 || removal_record.data_is_empty() {
+        return Ok(());
+    }
+    let record = ParticipantRemovalRecord::try_deserialize(&mut &removal_record.try_borrow_data()?[..])?;
+    require!(
+        Clock::get()?.unix_timestamp - record.removed_at >= readd_cooldown_seconds,
+        ErrorCode::ReaddTooSoon
+    );
+    Ok(())
+}
+
+/// keccak256 of channel metadata, stored alongside it to detect corruption.
+pub fn metadata_hash(encrypted_metadata: &[u8]) -> [u8; 32] {
+    anchor_lang::solana_program::keccak::hash(encrypted_metadata).to_bytes()
+}
+
+// How clients should parse a channel's encrypted_metadata
+pub const METADATA_OPAQUE: u8 = 0;
+pub const METADATA_STRUCTURED_V1: u8 = 1;
+// Structured-v1 header: the format tag byte, then the wrapped-key count
+pub const STRUCTURED_V1_HEADER_LEN: usize = 2;
+
+/// Opaque metadata is unchecked; structured-v1 bytes must at least carry their header.
+pub fn check_metadata_format(metadata_format: u8, encrypted_metadata: &[u8]) -> Result<()> {
+    match metadata_format {
+        METADATA_OPAQUE => Ok(()),
+        METADATA_STRUCTURED_V1 => {
+            require!(
+                encrypted_metadata.len() >= STRUCTURED_V1_HEADER_LEN
+                    && encrypted_metadata[0] == METADATA_STRUCTURED_V1,
+                ErrorCode::InvalidMetadataFormat
+            );
+            Ok(())
+        }
+        _ => err!(ErrorCode::InvalidMetadataFormat),
+    }
+}
+
+// Protocol-wide message size cap: the initial value and the most the authority can set
+pub const DEFAULT_MAX_MESSAGE_SIZE: u16 = 2048;
+pub const MAX_MESSAGE_SIZE_CEILING: u16 = 8192;
+
+// Capabilities per agent: the initial cap and the most the authority can raise it to
+pub const DEFAULT_MAX_CAPABILITIES: u8 = 10;
+pub const MAX_CAPABILITIES_CEILING: u8 = 32;
+
+// Smallest channel membership, creator included, min_participants may be set to
+pub const MIN_PARTICIPANTS_FLOOR: u8 = 2;
+
+// Bytes of text a broadcast_notice may carry
+pub const MAX_NOTICE_LEN: usize = 256;
+
+/// Enforces the channel creation policy at the program-derived PDA whenever the authority has
+/// created it, so a creator can't dodge it by leaving it out. Returns the policy address for
+/// the channel to record, or the default key when there is no policy.
+pub fn check_channel_policy(policy_info: &AccountInfo, creator_agent: Option<&Agent>) -> Result<Pubkey> {
+    if policy_info.data_is_empty() {
+        return Ok(Pubkey::default());
+    }
+    let policy = ChannelPolicy::try_deserialize(&mut &policy_info.try_borrow_data()?[..])?;
+    let creator_agent = creator_agent.ok_or(ErrorCode::PolicyNotSatisfied)?;
+    require!(
+        policy.is_satisfied_by(&creator_agent.capabilities),
+        ErrorCode::PolicyNotSatisfied
+    );
+    Ok(policy_info.key())
+}
+
+/// Validates and fills in a new PrivateChannel. Every instruction that creates a channel goes
+/// through here, so none can skip a bound, role, policy or membership check. `count_accounts`
+/// holds the ParticipantChannelCount PDA of every member, creator included, in the sorted
+/// order the channel stores participants. A direct channel must hold exactly creator and peer.
+#[allow(clippy::too_many_arguments)]
+pub fn init_private_channel<'info>(
+    channel: &mut Account<'info, PrivateChannel>,
+    bump: u8,
+    protocol: &mut ProtocolConfig,
+    creator: &AccountInfo<'info>,
+    creator_agent: Option<&Agent>,
+    channel_policy: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    count_accounts: &[AccountInfo<'info>],
+    args: ChannelArgs,
+    is_direct: bool,
+) -> Result<()> {
+    let ChannelArgs { channel_id, participants, encrypted_metadata, participant_roles, metadata_format } = args;
+    require!(channel_id.len() <= 128, ErrorCode::ChannelIdTooLong);
+    require!(participants.len() <= 10, ErrorCode::InvalidParticipants);
+    require!(encrypted_metadata.len() <= 512, ErrorCode::MetadataTooLarge);
+    // Deployments can insist every channel publishes its wrapped key material
+    require!(
+        !protocol.require_metadata || !encrypted_metadata.is_empty(),
+        ErrorCode::EncryptedMetadataRequired
+    );
+    check_metadata_format(metadata_format, &encrypted_metadata)?;
+    
+    // Roles are optional and aligned to the participants passed in; empty means everyone is a member
+    require!(
+        participant_roles.is_empty() || participant_roles.len() == participants.len(),
+        ErrorCode::InvalidRole
+    );
+    require!(
+        participant_roles.iter().all(|role| *role <= ROLE_OBSERVER),
+        ErrorCode::InvalidRole
+    );
+    // The creator's capabilities must satisfy the protocol's creation policy, if one is set
+    let policy = check_channel_policy(channel_policy, creator_agent)?;
+    
+    let mut members: Vec<(Pubkey, u8)> = participants
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (*p, participant_roles.get(i).copied().unwrap_or(ROLE_MEMBER)))
+        .collect();
+    
+    // SECURITY: Creator must be in participants list (prevent orphaned channels)
+    // The creator always administers their own channel
+    let creator_key = creator.key();
+    members.retain(|(p, _)| *p != creator_key);
+    members.push((creator_key, ROLE_ADMIN));
+    
+    // Remove duplicates to prevent manipulation
+    members.sort_by_key(|(p, _)| *p);
+    members.dedup_by_key(|(p, _)| *p);
+    // Only the creator left means the list named nobody else
+    require!(members.len() > 1, ErrorCode::NeedAtLeastOnePeer);
+    require!(!is_direct || members.len() == 2, ErrorCode::InvalidParticipants);
+    require!(
+        members.len() >= protocol.min_participants as usize,
+        ErrorCode::TooFewParticipants
+    );
+    
+    // Cap how many channels anyone can be conscripted into
+    require!(
+        count_accounts.len() == members.len(),
+        ErrorCode::InvalidChannelCountAccount
+    );
+    for ((member, _), count_account) in members.iter().zip(count_accounts.iter()) {
+        increment_channel_count(
+            count_account,
+            member,
+            creator,
+            system_program,
+            protocol.max_channels_per_participant,
+        )?;
+    }
+    
+    channel.creator = creator_key;
+    channel.channel_id = channel_id;
+    channel.participants = members.iter().map(|(p, _)| *p).collect();
+    channel.roles = members.iter().map(|(_, r)| *r).collect();
+    channel.metadata_hash = metadata_hash(&encrypted_metadata);
+    channel.encrypted_metadata = encrypted_metadata;
+    channel.message_count = 0;
+    channel.created_at = Clock::get()?.unix_timestamp;
+    channel.is_active = true;
+    channel.message_fee = 0;
+    channel.fee_mint = Pubkey::default();
+    channel.policy = policy;
+    channel.next_sequence = 0;
+    channel.metadata_format = metadata_format;
+    channel.bump = bump;
+    channel.is_direct = is_direct;
+    
+    protocol.total_channels = protocol.total_channels.checked_add(1).unwrap();
+    Ok(())
+}
+
+// Combined ciphertext bytes a single group message may carry
+pub const MAX_GROUP_MESSAGE_CONTENT: usize = 8192;
+
+// Poly1305 tag secretbox appends; real ciphertext carries it plus at least one byte
+pub const SECRETBOX_TAG_LEN: usize = 16;
+// Ciphertext this long should show most byte values; shorter content isn't judged on spread
+pub const ENTROPY_SAMPLE_LEN: usize = 32;
+
+/// Best-effort plaintext heuristic, not proof of encryption: flags content too short to hold
+/// a tag or, past ENTROPY_SAMPLE_LEN, using fewer distinct byte values than ciphertext would.
+pub fn looks_unencrypted(encrypted_content: &[u8]) -> bool {
+    if encrypted_content.len() <= SECRETBOX_TAG_LEN {
+        return true;
+    }
+    if encrypted_content.len() < ENTROPY_SAMPLE_LEN {
+        return false;
+    }
+    let mut seen = [false; 256];
+    for byte in encrypted_content {
+        seen[*byte as usize] = true;
+    }
+    let distinct = seen.iter().filter(|s| **s).count();
+    distinct < encrypted_content.len().min(256) / 4
+}
+
+/// Checks that `ix` is an ed25519 program instruction verifying a single signature by
+/// `pubkey` over `message`, with signature, key and message all inline in `ix`.
+pub fn check_ed25519_ix(ix: &Instruction, pubkey: &[u8], message: &[u8]) -> Result<()> {
+    let data = &ix.data;
+    require!(
+        ix.program_id == ed25519_program::ID && data.len() >= 16 && data[0] == 1,
+        ErrorCode::InvalidContentSignature
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+    // An instruction index of u16::MAX points at the verify instruction's own data
+    let inline = u16::MAX as usize;
+    require!(
+        read_u16(4) == inline && read_u16(8) == inline && read_u16(14) == inline,
+        ErrorCode::InvalidContentSignature
+    );
+    let (key_offset, message_offset, message_len) = (read_u16(6), read_u16(10), read_u16(12));
+    require!(
+        data.get(key_offset..key_offset + 32) == Some(pubkey)
+            && data.get(message_offset..message_offset + message_len) == Some(message),
+        ErrorCode::InvalidContentSignature
+    );
+    Ok(())
+}
+
+/// Checks that `ix`, at `ix_index` in the transaction, is a secp256k1 program instruction
+/// recovering a single signature by `eth_address` over `message`, with everything inline.
+pub fn check_secp256k1_ix(
+    ix: &Instruction,
+    ix_index: usize,
+    eth_address: &[u8; 20],
+    message: &[u8],
+) -> Result<()> {
+    let data = &ix.data;
+    require!(
+        ix.program_id == secp256k1_program::ID && data.len() >= 12 && data[0] == 1,
+        ErrorCode::InvalidContentSignature
+    );
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]) as usize;
+    require!(
+        [data[3], data[6], data[11]].iter().all(|index| *index as usize == ix_index),
+        ErrorCode::InvalidContentSignature
+    );
+    let (address_offset, message_offset, message_len) = (read_u16(4), read_u16(7), read_u16(9));
+    require!(
+        data.get(address_offset..address_offset + 20) == Some(&eth_address[..])
+            && data.get(message_offset..message_offset + message_len) == Some(message),
+        ErrorCode::InvalidContentSignature
+    );
+    Ok(())
+}
+
+/// Respects the recipient's contact preferences when they are a registered agent, and
+/// rejects deactivated or silent recipients under `block_inactive_recipients`.
+/// A two-party channel counts as a direct message.
+pub fn check_recipient_agent(
+    recipient_agent_info: &AccountInfo,
+    protocol: &ProtocolConfig,
+    participant_count: usize,
+) -> Result<()> {
+    if recipient_agent_info.data_is_empty() || *recipient_agent_info.owner != crate::ID {
+        require!(!protocol.block_inactive_recipients, ErrorCode::RecipientInactive);
+        return Ok(());
+    }
+    let recipient_agent = Agent::try_deserialize(&mut &recipient_agent_info.data.borrow()[..])?;
+    let required = if participant_count == 2 { CONTACT_DM } else { CONTACT_CHANNEL };
+    require!(
+        recipient_agent.contact_prefs & required != 0,
+        ErrorCode::ContactMethodNotAccepted
+    );
+    
+    // Don't spend rent on messages a deactivated or silent agent won't pick up
+    if protocol.block_inactive_recipients {
+        let stale = protocol.heartbeat_stale_seconds > 0
+            && Clock::get()?.unix_timestamp - recipient_agent.last_heartbeat
+                > protocol.heartbeat_stale_seconds;
+        require!(recipient_agent.is_active && !stale, ErrorCode::RecipientInactive);
+    }
+    Ok(())
+}
+
+/// Who-may-message-whom checks shared by every send and by `can_send`, so the pre-flight
+/// can't drift from the send. Checks on the payload itself are in `check_message_args`.
+pub fn check_send_gates(
+    protocol: &ProtocolConfig,
+    channel: &PrivateChannel,
+    sender: &Pubkey,
+    recipient: &Pubkey,
+    sender_agent: Option<&Agent>,
+    recipient_agent_info: &AccountInfo,
+) -> Result<()> {
+    // A zeroed config has every gate below switched off
+    require!(protocol.initialized, ErrorCode::ProtocolNotInitialized);
+    
+    // CRITICAL: Prevent sending messages to yourself
+    require!(recipient != sender, ErrorCode::InvalidRecipient);
+    
+    require!(channel.is_active, ErrorCode::ChannelInactive);
+    require!(!channel.archived, ErrorCode::ChannelArchived);
+    
+    let sender_index = channel
+        .participants
+        .iter()
+        .position(|p| p == sender)
+        .ok_or(ErrorCode::NotAParticipant)?;
+    require!(channel.participants.contains(recipient), ErrorCode::InvalidRecipient);
+    
+    // Observers can read the channel but not post to it
+    require!(
+        channel.role_at(sender_index) != ROLE_OBSERVER,
+        ErrorCode::InsufficientRole
+    );
+    
+    protocol.check_sender_reputation(sender_agent)?;
+    check_recipient_agent(recipient_agent_info, protocol, channel.participants.len())?;
+    protocol.check_message_quota(sender_agent, Clock::get()?.unix_timestamp)
+}
+
+/// Payload checks shared by every instruction that writes an EncryptedMessage.
+pub fn check_message_args(protocol: &ProtocolConfig, args: &MessageArgs) -> Result<()> {
+    require!(args.message_id.len() <= 128, ErrorCode::MessageIdTooLong);
+    require!(args.compression <= COMPRESSION_GZIP, ErrorCode::UnsupportedCompression);
+    require!(
+        args.encrypted_content.len() <= protocol.max_message_size as usize,
+        ErrorCode::MessageTooLarge
+    );
+    // 0 means no acknowledgement is required; otherwise the deadline must be in the future
+    require!(
+        args.require_ack_by == 0 || args.require_ack_by > Clock::get()?.unix_timestamp,
+        ErrorCode::InvalidAckDeadline
+    );
+    Ok(())
+}
+
+/// Writes a message that has passed `check_send_gates` and paid any fee: counts it against
+/// the sender's quota, assigns its sequences, flags apparent plaintext and emits the send.
+#[allow(clippy::too_many_arguments)]
+pub fn write_message<'info>(
+    protocol: &mut ProtocolConfig,
+    channel: &mut Account<'info, PrivateChannel>,
+    sender_state: &mut SenderChannelState,
+    message: &mut Account<'info, EncryptedMessage>,
+    mut sender_agent: Option<&mut Agent>,
+    sender: Pubkey,
+    args: MessageArgs,
+    fee_paid: u64,
+) -> Result<()> {
+    let MessageArgs { message_id, encrypted_content, recipient, nonce, require_ack_by, compression } = args;
+    
+    // Unix time ties within a second; slot then per-sender sequence give a strict order
+    let clock = Clock::get()?;
+    protocol.record_message(sender_agent.as_deref_mut(), clock.unix_timestamp)?;
+    protocol.message_seq = protocol.message_seq.checked_add(1).ok_or(ErrorCode::NonceOverflow)?;
+    let message_seq = protocol.message_seq;
+    
+    if sender_state.sender == Pubkey::default() {
+        sender_state.channel = channel.key();
+        sender_state.sender = sender;
+        sender_state.next_sequence = 0;
+    } else {
+        // Catches trivial AEAD nonce reuse; not a substitute for random nonces client-side
+        require!(sender_state.last_nonce != nonce, ErrorCode::NonceReused);
+    }
+    sender_state.last_nonce = nonce;
+    let sequence = sender_state.next_sequence;
+    sender_state.next_sequence = sequence.checked_add(1).ok_or(ErrorCode::NonceOverflow)?;
+    
+    // Per-channel total order, so recipients can spot a dropped or censored message
+    let channel_sequence = channel.next_sequence;
+    channel.next_sequence = channel_sequence.checked_add(1).ok_or(ErrorCode::NonceOverflow)?;
+    
+    // The clock is the only time source; readers rely on it never predating the channel
+    require!(clock.unix_timestamp >= channel.created_at, ErrorCode::TimestampAnomaly);
+    
+    // Audit trail for security teams; the message is still delivered
+    if looks_unencrypted(&encrypted_content) {
+        let suspicious_messages = match sender_agent {
+            Some(agent) => {
+                agent.suspicious_messages = agent.suspicious_messages.saturating_add(1);
+                agent.suspicious_messages
+            }
+            None => 0,
+        };
+        emit!(SuspiciousMessageDetected {
+            sender,
+            channel: channel.key(),
+            content_len: encrypted_content.len() as u32,
+            suspicious_messages,
+            unix_timestamp: clock.unix_timestamp,
+            slot: clock.slot,
+        });
+    }
+    
+    message.channel = channel.key();
+    message.message_id = message_id;
+    message.sender = sender;
+    message.recipient = recipient;
+    message.encrypted_content = encrypted_content;
+    message.timestamp = clock.unix_timestamp;
+    message.delivered = false;
+    message.slot = clock.slot;
+    message.sequence = sequence;
+    message.channel_sequence = channel_sequence;
+    message.nonce = nonce;
+    message.require_ack_by = require_ack_by;
+    message.compression = compression;
+    
+    emit!(EncryptedMessageSent {
+        message: message.key(),
+        channel: channel.key(),
+        sender,
+        recipient,
+        message_id: message.message_id.clone(),
+        unix_timestamp: message.timestamp,
+        slot: message.slot,
+        sequence,
+        channel_sequence,
+        fee_paid,
+        message_seq,
+        compression,
+    });
+    Ok(())
+}
+
+/// Validates the fee accounts for a channel charging a message fee; free channels need none.
+pub fn check_message_fee(
+    channel: &PrivateChannel,
+    sender_fee_account: Option<&TokenAccount>,
+    creator_fee_account: Option<&TokenAccount>,
+) -> Result<()> {
+    if channel.message_fee == 0 {
+        return Ok(());
+    }
+    let (Some(sender_fee_account), Some(creator_fee_account)) = (sender_fee_account, creator_fee_account) else {
+        return err!(ErrorCode::MissingFeeAccounts);
+    };
+    require!(sender_fee_account.mint == channel.fee_mint, ErrorCode::MintMismatch);
+    require!(creator_fee_account.mint == channel.fee_mint, ErrorCode::MintMismatch);
+    require!(creator_fee_account.owner == channel.creator, ErrorCode::Unauthorized);
+    require!(sender_fee_account.amount >= channel.message_fee, ErrorCode::InsufficientBalance);
+    Ok(())
+}
+
+/// Agent account size for `capability_count` capabilities of up to 32 bytes each.
+pub fn agent_space(capability_count: usize) -> usize {
+    size_of::<Agent>() + 64 + (capability_count * 32) + 8
+}
+
+/// keccak256 of a capability name, used as the attestation PDA seed so names of any
+/// length fit in a single seed.
+pub fn capability_hash(capability: &str) -> [u8; 32] {
+    anchor_lang::solana_program::keccak::hash(capability.as_bytes()).to_bytes()
+}
+
+/// Derives the channel PDA for a creator and channel id, as used by `create_private_channel`.
+pub fn channel_pda(creator: &Pubkey, channel_id: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[CHANNEL_SEED, creator.as_ref(), channel_id.as_bytes()],
+        &crate::ID,
+    )
+}
+
 #[program]
 pub mod openclaw_privacy_protocol {
     use super::*;
 
     pub fn initialize_protocol(ctx: Context<InitializeProtocol>) -> Result<()> {
         let protocol = &mut ctx.accounts.protocol_config;
+        // init_if_needed lets a repeat call reach this check instead of failing on the raw
+        // account collision, and stops anyone re-initializing over the live config
+        require!(!protocol.initialized, ErrorCode::AlreadyInitialized);
         protocol.authority = ctx.accounts.authority.key();
         protocol.initialized = true;
         protocol.total_agents = 0;
         protocol.total_channels = 0;
         protocol.paused = false;
+        protocol.minimal_events = false;
+        protocol.restrict_shield_mints = false;
+        protocol.min_channel_lifetime_seconds = 0;
+        protocol.min_token_transfer = 0;
+        protocol.admins = Vec::new();
+        protocol.key_rotation_cooldown_seconds = 0;
+        protocol.readd_cooldown_seconds = 0;
+        protocol.max_dark_pools = 0;
+        protocol.pad_pending = false;
+        protocol.max_channels_per_participant = 0;
+        protocol.block_inactive_recipients = false;
+        protocol.heartbeat_stale_seconds = 0;
+        protocol.max_message_size = DEFAULT_MAX_MESSAGE_SIZE;
+        protocol.inco_program_id = Pubkey::default();
+        protocol.message_seq = 0;
+        protocol.max_capabilities = DEFAULT_MAX_CAPABILITIES;
+        protocol.module_pause_flags = 0;
+        protocol.min_reputation_to_send = i64::MIN;
+        protocol.require_metadata = false;
+        protocol.restrict_registration = false;
+        protocol.reputation_per_task = 0;
+        protocol.min_participants = MIN_PARTICIPANTS_FLOOR;
+        protocol.emit_events = true;
+        protocol.message_quota = 0;
+        protocol.message_quota_window_seconds = 0;
+        protocol.rate_multiplier_capability = [0u8; 32];
+        protocol.rate_multiplier = 1;
+        protocol.light_system_program_id = Pubkey::default();
+        protocol.total_dark_pools = 0;
         
         emit!(ProtocolInitialized {
             authority: ctx.accounts.authority.key(),
-            timestamp: Clock::get()?.unix_timestamp,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
@@ -32,9 +713,16 @@ pub mod openclaw_privacy_protocol {
         encryption_pubkey: [u8; 32],
         capabilities: Vec<String>,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
         require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
         require!(agent_name.len() <= 64, ErrorCode::NameTooLong);
-        require!(capabilities.len() <= 10, ErrorCode::TooManyCapabilities);
+        if ctx.accounts.protocol_config.restrict_registration {
+            require!(ctx.accounts.agent_registrant.is_some(), ErrorCode::RegistrationNotAllowed);
+        }
+        require!(
+            capabilities.len() <= ctx.accounts.protocol_config.max_capabilities as usize,
+            ErrorCode::TooManyCapabilities
+        );
         
         let agent = &mut ctx.accounts.agent;
         agent.owner = ctx.accounts.owner.key();
@@ -46,59 +734,237 @@ pub mod openclaw_privacy_protocol {
         agent.total_tasks_completed = 0;
         agent.registered_at = Clock::get()?.unix_timestamp;
         agent.is_active = true;
+        agent.capabilities_locked = false;
+        agent.contact_prefs = CONTACT_ALL;
+        agent.last_key_rotation = 0;
+        agent.last_heartbeat = agent.registered_at;
+        agent.recovery_key = Pubkey::default();
+        agent.recovery_delay_seconds = 0;
+        agent.pending_owner = Pubkey::default();
+        agent.recovery_initiated_at = 0;
+        agent.bump = ctx.bumps.agent;
+        agent.suspicious_messages = 0;
+        agent.quota_window_start = 0;
+        agent.messages_in_window = 0;
         
         let protocol = &mut ctx.accounts.protocol_config;
         protocol.total_agents = protocol.total_agents.checked_add(1).unwrap();
         
+        // Minimal events keep the agent name out of the logs
+        let name = if protocol.minimal_events { String::new() } else { agent.name.clone() };
         emit!(AgentRegistered {
             agent: ctx.accounts.agent.key(),
             owner: ctx.accounts.owner.key(),
-            name: agent.name.clone(),
-            timestamp: agent.registered_at,
+            name,
+            unix_timestamp: agent.registered_at,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
     }
 
-    pub fn create_private_channel(
-        ctx: Context<CreatePrivateChannel>,
+    /// `remaining_accounts` must hold the ParticipantChannelCount PDA of every member,
+    /// creator included, in the same sorted order the channel stores participants.
+    pub fn create_private_channel<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreatePrivateChannel<'info>>,
         channel_id: String,
-        mut participants: Vec<Pubkey>,
+        participants: Vec<Pubkey>,
         encrypted_metadata: Vec<u8>,
+        participant_roles: Vec<u8>,
+        metadata_format: u8,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
         require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
-        require!(channel_id.len() <= 128, ErrorCode::ChannelIdTooLong);
-        require!(participants.len() >= 2 && participants.len() <= 10, ErrorCode::InvalidParticipants);
-        require!(encrypted_metadata.len() <= 512, ErrorCode::MetadataTooLarge);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
         
-        // SECURITY: Creator must be in participants list (prevent orphaned channels)
-        let creator_key = ctx.accounts.creator.key();
-        if !participants.contains(&creator_key) {
-            participants.push(creator_key);
-        }
+        init_private_channel(
+            &mut ctx.accounts.channel,
+            ctx.bumps.channel,
+            &mut ctx.accounts.protocol_config,
+            &ctx.accounts.creator.to_account_info(),
+            ctx.accounts.creator_agent.as_deref(),
+            &ctx.accounts.channel_policy.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.remaining_accounts,
+            ChannelArgs { channel_id, participants, encrypted_metadata, participant_roles, metadata_format },
+            false,
+        )?;
         
-        // Remove duplicates to prevent manipulation
-        participants.sort();
-        participants.dedup();
-        require!(participants.len() >= 2, ErrorCode::InvalidParticipants);
+        // Minimal events publish only the participant count so the channel graph isn't broadcast
+        let channel = &ctx.accounts.channel;
+        let participants = if ctx.accounts.protocol_config.minimal_events {
+            Vec::new()
+        } else {
+            channel.participants.clone()
+        };
+        emit!(PrivateChannelCreated {
+            channel: channel.key(),
+            creator: channel.creator,
+            participants,
+            participant_count: channel.participants.len() as u32,
+            metadata_format: channel.metadata_format,
+            unix_timestamp: channel.created_at,
+            slot: Clock::get()?.slot,
+        });
         
-        let channel = &mut ctx.accounts.channel;
-        channel.creator = ctx.accounts.creator.key();
-        channel.channel_id = channel_id;
-        channel.participants = participants;
-        channel.encrypted_metadata = encrypted_metadata;
-        channel.message_count = 0;
-        channel.created_at = Clock::get()?.unix_timestamp;
-        channel.is_active = true;
+        Ok(())
+    }
+
+    /// Creates a channel and sends its first message in one instruction, so a conversation
+    /// can't be left half-open. Takes the same member count PDAs as `create_private_channel`
+    /// in `remaining_accounts`. Channels charging a fee use the separate flow.
+    pub fn create_channel_and_send<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreateChannelAndSend<'info>>,
+        channel_args: ChannelArgs,
+        message_args: MessageArgs,
+    ) -> Result<()> {
+        let protocol = &ctx.accounts.protocol_config;
+        require!(protocol.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!protocol.paused, ErrorCode::ProtocolPaused);
+        require!(!protocol.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        require!(!protocol.is_module_paused(MODULE_MESSAGING), ErrorCode::ModulePaused);
+        check_message_args(protocol, &message_args)?;
         
-        let protocol = &mut ctx.accounts.protocol_config;
-        protocol.total_channels = protocol.total_channels.checked_add(1).unwrap();
+        // Same creation and send paths as create_private_channel and send_encrypted_message
+        init_private_channel(
+            &mut ctx.accounts.channel,
+            ctx.bumps.channel,
+            &mut ctx.accounts.protocol_config,
+            &ctx.accounts.creator.to_account_info(),
+            ctx.accounts.creator_agent.as_deref(),
+            &ctx.accounts.channel_policy.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.remaining_accounts,
+            channel_args,
+            false,
+        )?;
+        let creator = ctx.accounts.creator.key();
+        check_send_gates(
+            &ctx.accounts.protocol_config,
+            &ctx.accounts.channel,
+            &creator,
+            &message_args.recipient,
+            ctx.accounts.creator_agent.as_deref(),
+            &ctx.accounts.recipient_agent.to_account_info(),
+        )?;
         
+        let channel = &ctx.accounts.channel;
+        let participants = if ctx.accounts.protocol_config.minimal_events {
+            Vec::new()
+        } else {
+            channel.participants.clone()
+        };
         emit!(PrivateChannelCreated {
+            channel: channel.key(),
+            creator,
+            participants,
+            participant_count: channel.participants.len() as u32,
+            metadata_format: channel.metadata_format,
+            unix_timestamp: channel.created_at,
+            slot: Clock::get()?.slot,
+        });
+        
+        write_message(
+            &mut ctx.accounts.protocol_config,
+            &mut ctx.accounts.channel,
+            &mut ctx.accounts.sender_state,
+            &mut ctx.accounts.message,
+            ctx.accounts.creator_agent.as_deref_mut(),
+            creator,
+            message_args,
+            0,
+        )
+    }
+
+    /// `rules` are AND-ed groups of OR-ed capabilities: `[["x"], ["y", "z"]]` means
+    /// "x AND (y OR z)".
+    pub fn create_channel_policy(
+        ctx: Context<CreateChannelPolicy>,
+        policy_id: String,
+        rules: Vec<Vec<String>>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        require!(policy_id.len() <= 32, ErrorCode::InvalidPolicy);
+        require!(!rules.is_empty() && rules.len() <= 8, ErrorCode::InvalidPolicy);
+        require!(
+            rules.iter().all(|group| !group.is_empty() && group.len() <= 10),
+            ErrorCode::InvalidPolicy
+        );
+        
+        let policy = &mut ctx.accounts.channel_policy;
+        policy.authority = ctx.accounts.authority.key();
+        policy.policy_id = policy_id;
+        policy.rules = rules;
+        
+        emit!(ChannelPolicyCreated {
+            policy: policy.key(),
+            authority: policy.authority,
+            rules: policy.rules.clone(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Retires a policy, returning its rent. Closing the creation policy lifts it for new channels.
+    pub fn close_channel_policy(ctx: Context<CloseChannelPolicy>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        
+        emit!(ChannelPolicyClosed {
+            policy: ctx.accounts.channel_policy.key(),
+            authority: ctx.accounts.authority.key(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// A 1:1 channel: a PrivateChannel flagged direct, holding exactly the creator and `peer`,
+    /// whose membership can't change afterwards. Takes the same count PDAs as `create_private_channel`.
+    ///
+    /// Not a fixed `[Pubkey; 2]` account: over a two-entry Vec that saves only the length
+    /// prefixes, and a separate layout would need its own send, fee and close paths.
+    pub fn create_direct_channel<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreateDirectChannel<'info>>,
+        channel_id: String,
+        peer: Pubkey,
+        encrypted_metadata: Vec<u8>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        require!(peer != ctx.accounts.creator.key(), ErrorCode::InvalidParticipants);
+        
+        init_private_channel(
+            &mut ctx.accounts.channel,
+            ctx.bumps.channel,
+            &mut ctx.accounts.protocol_config,
+            &ctx.accounts.creator.to_account_info(),
+            ctx.accounts.creator_agent.as_deref(),
+            &ctx.accounts.channel_policy.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.remaining_accounts,
+            ChannelArgs {
+                channel_id,
+                participants: vec![peer],
+                encrypted_metadata,
+                participant_roles: Vec::new(),
+                metadata_format: METADATA_OPAQUE,
+            },
+            true,
+        )?;
+        
+        let channel = &ctx.accounts.channel;
+        emit!(DirectChannelCreated {
             channel: channel.key(),
             creator: channel.creator,
-            participants: channel.participants.clone(),
-            timestamp: channel.created_at,
+            peer,
+            unix_timestamp: channel.created_at,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
@@ -109,40 +975,175 @@ pub mod openclaw_privacy_protocol {
         message_id: String,
         encrypted_content: Vec<u8>,
         recipient: Pubkey,
+        nonce: [u8; 24],
+        require_ack_by: i64,
+        compression: u8,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
         require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
-        require!(message_id.len() <= 128, ErrorCode::MessageIdTooLong);
-        require!(encrypted_content.len() <= 2048, ErrorCode::MessageTooLarge);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_MESSAGING), ErrorCode::ModulePaused);
+        let args = MessageArgs { message_id, encrypted_content, recipient, nonce, require_ack_by, compression };
+        check_message_args(&ctx.accounts.protocol_config, &args)?;
         
-        // CRITICAL: Prevent sending messages to yourself
-        require!(
-            recipient != ctx.accounts.sender.key(),
-            ErrorCode::InvalidRecipient
-        );
+        // Authoritative: each instruction is handed the channel's current state, so a close
+        // landed earlier in this transaction or slot is already visible and a queued send fails
+        let channel = &ctx.accounts.channel;
+        let sender = ctx.accounts.sender.key();
+        check_send_gates(
+            &ctx.accounts.protocol_config,
+            channel,
+            &sender,
+            &recipient,
+            ctx.accounts.sender_agent.as_deref(),
+            &ctx.accounts.recipient_agent.to_account_info(),
+        )?;
+        
+        // Channels may charge a per-message token fee, paid to the creator, to deter spam
+        let message_fee = channel.message_fee;
+        check_message_fee(
+            channel,
+            ctx.accounts.sender_fee_account.as_deref(),
+            ctx.accounts.creator_fee_account.as_deref(),
+        )?;
+        if message_fee > 0 {
+            let (Some(sender_fee_account), Some(creator_fee_account), Some(token_program)) = (
+                &ctx.accounts.sender_fee_account,
+                &ctx.accounts.creator_fee_account,
+                &ctx.accounts.token_program,
+            ) else {
+                return err!(ErrorCode::MissingFeeAccounts);
+            };
+            
+            let cpi_accounts = Transfer {
+                from: sender_fee_account.to_account_info(),
+                to: creator_fee_account.to_account_info(),
+                authority: ctx.accounts.sender.to_account_info(),
+            };
+            anchor_spl::token::transfer(
+                CpiContext::new(token_program.to_account_info(), cpi_accounts),
+                message_fee,
+            )?;
+        }
+        
+        write_message(
+            &mut ctx.accounts.protocol_config,
+            &mut ctx.accounts.channel,
+            &mut ctx.accounts.sender_state,
+            &mut ctx.accounts.message,
+            ctx.accounts.sender_agent.as_deref_mut(),
+            sender,
+            args,
+            message_fee,
+        )
+    }
+
+    /// Pre-flight for send_encrypted_message: fails with the error a send from `sender` to
+    /// `recipient` would hit, without creating or changing anything. Meant to be simulated.
+    pub fn can_send(ctx: Context<CanSend>, recipient: Pubkey) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_MESSAGING), ErrorCode::ModulePaused);
         
         let channel = &ctx.accounts.channel;
-        require!(channel.is_active, ErrorCode::ChannelInactive);
+        check_send_gates(
+            &ctx.accounts.protocol_config,
+            channel,
+            &ctx.accounts.sender.key(),
+            &recipient,
+            ctx.accounts.sender_agent.as_deref(),
+            &ctx.accounts.recipient_agent.to_account_info(),
+        )?;
+        check_message_fee(
+            channel,
+            ctx.accounts.sender_fee_account.as_deref(),
+            ctx.accounts.creator_fee_account.as_deref(),
+        )
+    }
+
+    pub fn resolve_message(ctx: Context<ReadMessage>) -> Result<()> {
+        let message = &ctx.accounts.message;
         
-        let sender = ctx.accounts.sender.key();
-        require!(channel.participants.contains(&sender), ErrorCode::NotAParticipant);
-        require!(channel.participants.contains(&recipient), ErrorCode::InvalidRecipient);
+        let (expected, _) = message_pda(&message.channel, &message.sender, &message.message_id);
+        require!(message.key() == expected, ErrorCode::InvalidMessageAddress);
+        
+        emit!(MessageResolved {
+            message: message.key(),
+            channel: message.channel,
+            sender: message.sender,
+            message_id: message.message_id.clone(),
+        });
         
+        Ok(())
+    }
+
+    pub fn acknowledge_message(ctx: Context<AcknowledgeMessage>) -> Result<()> {
         let message = &mut ctx.accounts.message;
-        message.channel = channel.key();
-        message.message_id = message_id;
-        message.sender = sender;
-        message.recipient = recipient;
-        message.encrypted_content = encrypted_content;
-        message.timestamp = Clock::get()?.unix_timestamp;
-        message.delivered = false;
+        require!(!message.delivered, ErrorCode::MessageAlreadyAcknowledged);
+        message.delivered = true;
         
-        emit!(EncryptedMessageSent {
+        emit!(MessageAcknowledged {
             message: message.key(),
-            channel: channel.key(),
-            sender,
-            recipient,
-            message_id: message.message_id.clone(),
-            timestamp: message.timestamp,
+            channel: message.channel,
+            recipient: message.recipient,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Emits `AckDeadlineMissed` when a message requiring acknowledgement is past its deadline.
+    pub fn check_ack_deadline(ctx: Context<ReadMessage>) -> Result<()> {
+        let message = &ctx.accounts.message;
+        let now = Clock::get()?.unix_timestamp;
+        
+        if message.require_ack_by != 0 && !message.delivered && now > message.require_ack_by {
+            emit!(AckDeadlineMissed {
+                message: message.key(),
+                channel: message.channel,
+                sender: message.sender,
+                recipient: message.recipient,
+                require_ack_by: message.require_ack_by,
+                unix_timestamp: now,
+                slot: Clock::get()?.slot,
+            });
+        }
+        
+        Ok(())
+    }
+
+    /// Proves a message's content came from its sender. The preceding instruction must verify
+    /// a signature over keccak256 of the content: by the sender's wallet for ed25519, or by
+    /// the sender agent's registered secp256k1 signer.
+    pub fn authenticate_message(ctx: Context<AuthenticateMessage>, sig_scheme: u8) -> Result<()> {
+        let message = &ctx.accounts.message;
+        let content_hash = anchor_lang::solana_program::keccak::hash(&message.encrypted_content).to_bytes();
+        
+        let instructions = ctx.accounts.instructions.to_account_info();
+        let current_index = sysvar::instructions::load_current_index_checked(&instructions)? as usize;
+        require!(current_index > 0, ErrorCode::InvalidContentSignature);
+        let verify_index = current_index - 1;
+        let verify_ix = sysvar::instructions::load_instruction_at_checked(verify_index, &instructions)?;
+        
+        match sig_scheme {
+            SIG_SCHEME_ED25519 => check_ed25519_ix(&verify_ix, message.sender.as_ref(), &content_hash)?,
+            SIG_SCHEME_SECP256K1 => {
+                let signer = ctx.accounts.sender_agent.as_ref()
+                    .map(|agent| agent.secp256k1_signer)
+                    .unwrap_or_default();
+                require!(signer != [0u8; 20], ErrorCode::SigningKeyNotSet);
+                check_secp256k1_ix(&verify_ix, verify_index, &signer, &content_hash)?
+            }
+            _ => return err!(ErrorCode::UnsupportedSignatureScheme),
+        }
+        
+        emit!(MessageAuthenticated {
+            message: message.key(),
+            channel: message.channel,
+            sender: message.sender,
+            sig_scheme,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
@@ -152,7 +1153,22 @@ pub mod openclaw_privacy_protocol {
         ctx: Context<InitializeShieldedBalance>,
         mint: Pubkey,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
         require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
+        require!(mint == ctx.accounts.mint.key(), ErrorCode::MintMismatch);
+        // A balance against a non-mint account would break deposits later
+        let mint_info = ctx.accounts.mint.to_account_info();
+        require!(
+            *mint_info.owner == anchor_spl::token::ID
+                && Mint::try_deserialize(&mut &mint_info.try_borrow_data()?[..]).is_ok(),
+            ErrorCode::InvalidMint
+        );
+        
+        // Regulated deployments only allow shielding of allowlisted mints
+        if ctx.accounts.protocol_config.restrict_shield_mints {
+            require!(ctx.accounts.allowed_mint.is_some(), ErrorCode::MintNotAllowed);
+        }
         
         let balance = &mut ctx.accounts.shielded_balance;
         balance.owner = ctx.accounts.owner.key();
@@ -160,12 +1176,16 @@ pub mod openclaw_privacy_protocol {
         balance.commitment = [0u8; 32];
         balance.pending_transfers = Vec::new();
         balance.nonce = 0;
+        balance.bump = ctx.bumps.shielded_balance;
+        balance.seed_owner = balance.owner;
+        balance.pending_owner = Pubkey::default();
         
         emit!(ShieldedBalanceInitialized {
             balance_account: balance.key(),
             owner: balance.owner,
             mint,
-            timestamp: Clock::get()?.unix_timestamp,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
@@ -176,8 +1196,11 @@ pub mod openclaw_privacy_protocol {
         amount_commitment: [u8; 32],
         nullifier: [u8; 32],
         proof: Vec<u8>,
+        expected_nonce: Option<u64>,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
         require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
         require!(proof.len() <= 1024, ErrorCode::ProofTooLarge);
         
         // CRITICAL: Check for duplicate accounts to prevent self-transfers and double-spending
@@ -195,272 +1218,4116 @@ pub mod openclaw_privacy_protocol {
             ErrorCode::InvalidBalanceOwner
         );
         
+        // A client still on the nonce from before a dropped transaction gets a retryable error
+        if let Some(expected_nonce) = expected_nonce {
+            require!(sender_balance.nonce == expected_nonce, ErrorCode::NonceMismatch);
+        }
+        
         require!(sender_balance.nonce < u64::MAX, ErrorCode::NonceOverflow);
         require!(recipient_balance.nonce < u64::MAX, ErrorCode::NonceOverflow);
         
+        // Never credit a zeroed balance that was allocated but never initialized
+        require!(
+            recipient_balance.mint != Pubkey::default(),
+            ErrorCode::UninitializedBalance
+        );
+        
         // Validate mint tokens match
         require!(
             sender_balance.mint == recipient_balance.mint,
             ErrorCode::MintMismatch
         );
+        require!(
+            recipient_balance.real_pending_count() < MAX_PENDING_TRANSFERS,
+            ErrorCode::TooManyPendingTransfers
+        );
         
         sender_balance.nonce = sender_balance.nonce.checked_add(1).unwrap();
-        recipient_balance.pending_transfers.push(ShieldedTransferRecord {
+        // Bind the transfer to the recipient commitment it was created against
+        let to_commitment = recipient_balance.commitment;
+        recipient_balance.push_pending(ShieldedTransferRecord {
             amount_commitment,
             nullifier,
             from: ctx.accounts.sender.key(),
             timestamp: Clock::get()?.unix_timestamp,
+            to_commitment,
+            is_dummy: false,
         });
+        if ctx.accounts.protocol_config.pad_pending {
+            recipient_balance.pad_pending();
+        }
         
-        emit!(ShieldedTransferExecuted {
-            sender_balance: sender_balance.key(),
-            recipient_balance: recipient_balance.key(),
-            nullifier,
-            amount_commitment,
-            timestamp: Clock::get()?.unix_timestamp,
-        });
+        if ctx.accounts.protocol_config.emit_events {
+            emit!(ShieldedTransferExecuted {
+                sender_balance: sender_balance.key(),
+                recipient_balance: recipient_balance.key(),
+                nullifier,
+                amount_commitment,
+                unix_timestamp: Clock::get()?.unix_timestamp,
+                slot: Clock::get()?.slot,
+            });
+        }
         
         Ok(())
     }
 
-    pub fn update_agent_capabilities(
-        ctx: Context<UpdateAgentCapabilities>,
-        new_capabilities: Vec<String>,
+    pub fn broadcast_encrypted_message(
+        ctx: Context<BroadcastEncryptedMessage>,
+        message_id: String,
+        per_recipient_ciphertexts: Vec<RecipientCiphertext>,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
         require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
-        require!(new_capabilities.len() <= 10, ErrorCode::TooManyCapabilities);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_MESSAGING), ErrorCode::ModulePaused);
+        require!(message_id.len() <= 128, ErrorCode::MessageIdTooLong);
+        require!(!per_recipient_ciphertexts.is_empty(), ErrorCode::InvalidRecipient);
         
-        let agent = &mut ctx.accounts.agent;
-        agent.capabilities = new_capabilities;
-        agent.encryption_nonce = agent.encryption_nonce.checked_add(1).unwrap();
+        let total_size: usize = per_recipient_ciphertexts.iter().map(|c| c.ciphertext.len()).sum();
+        require!(total_size <= MAX_GROUP_MESSAGE_CONTENT, ErrorCode::MessageTooLarge);
         
-        emit!(AgentCapabilitiesUpdated {
-            agent: agent.key(),
-            owner: agent.owner,
-            capabilities: agent.capabilities.clone(),
-            timestamp: Clock::get()?.unix_timestamp,
+        // remaining_accounts holds each recipient's agent PDA, in ciphertext order; an
+        // unregistered recipient's is simply uninitialized
+        require!(
+            ctx.remaining_accounts.len() == per_recipient_ciphertexts.len(),
+            ErrorCode::InvalidRecipientAgentAccount
+        );
+        
+        // Each slice is a send: it passes the same gates and counts against the same quota
+        let channel = &ctx.accounts.channel;
+        let sender = ctx.accounts.sender.key();
+        let now = Clock::get()?.unix_timestamp;
+        for (i, (entry, recipient_agent)) in per_recipient_ciphertexts
+            .iter()
+            .zip(ctx.remaining_accounts.iter())
+            .enumerate()
+        {
+            let (expected, _) = Pubkey::find_program_address(&[b"agent", entry.recipient.as_ref()], &crate::ID);
+            require_keys_eq!(recipient_agent.key(), expected, ErrorCode::InvalidRecipientAgentAccount);
+            require!(
+                !per_recipient_ciphertexts[..i].iter().any(|e| e.recipient == entry.recipient),
+                ErrorCode::InvalidRecipient
+            );
+            check_send_gates(
+                &ctx.accounts.protocol_config,
+                channel,
+                &sender,
+                &entry.recipient,
+                ctx.accounts.sender_agent.as_deref(),
+                recipient_agent,
+            )?;
+            ctx.accounts.protocol_config.record_message(ctx.accounts.sender_agent.as_deref_mut(), now)?;
+        }
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.message_seq = protocol.message_seq.checked_add(1).ok_or(ErrorCode::NonceOverflow)?;
+        let message_seq = protocol.message_seq;
+        
+        let recipient_count = per_recipient_ciphertexts.len() as u32;
+        let message = &mut ctx.accounts.group_message;
+        message.channel = channel.key();
+        message.message_id = message_id;
+        message.sender = sender;
+        message.ciphertexts = per_recipient_ciphertexts;
+        message.timestamp = Clock::get()?.unix_timestamp;
+        
+        emit!(GroupMessageSent {
+            message: message.key(),
+            channel: channel.key(),
+            sender,
+            message_id: message.message_id.clone(),
+            recipient_count,
+            unix_timestamp: message.timestamp,
+            slot: Clock::get()?.slot,
+            message_seq,
         });
         
         Ok(())
     }
 
-    pub fn close_private_channel(ctx: Context<ClosePrivateChannel>) -> Result<()> {
+    pub fn update_channel_metadata(
+        ctx: Context<UpdateChannelMetadata>,
+        encrypted_metadata: Vec<u8>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
         require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        require!(encrypted_metadata.len() <= 512, ErrorCode::MetadataTooLarge);
+        require!(!ctx.accounts.channel.archived, ErrorCode::ChannelArchived);
+        require!(ctx.accounts.channel.is_active, ErrorCode::ChannelInactive);
+        check_metadata_format(ctx.accounts.channel.metadata_format, &encrypted_metadata)?;
+        
+        let channel_info = ctx.accounts.channel.to_account_info();
+        let new_size = channel_info.data_len() + encrypted_metadata.len()
+            - ctx.accounts.channel.encrypted_metadata.len().min(encrypted_metadata.len());
+        grow_account(
+            &channel_info,
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            new_size,
+        )?;
+        
         let channel = &mut ctx.accounts.channel;
-        require!(channel.creator == ctx.accounts.creator.key(), ErrorCode::Unauthorized);
-        require!(channel.is_active, ErrorCode::ChannelInactive);
+        channel.metadata_hash = metadata_hash(&encrypted_metadata);
+        channel.encrypted_metadata = encrypted_metadata;
         
-        channel.is_active = false;
+        emit!(ChannelMetadataUpdated {
+            channel: channel.key(),
+            metadata_hash: channel.metadata_hash,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
         
-        emit!(PrivateChannelClosed {
+        Ok(())
+    }
+
+    /// Recomputes the metadata hash so clients can detect tampering or corruption.
+    pub fn verify_metadata_integrity(ctx: Context<ResolveChannel>) -> Result<()> {
+        let channel = &ctx.accounts.channel;
+        require!(
+            metadata_hash(&channel.encrypted_metadata) == channel.metadata_hash,
+            ErrorCode::MetadataCorrupted
+        );
+        Ok(())
+    }
+
+    pub fn resolve_channel(ctx: Context<ResolveChannel>) -> Result<()> {
+        let channel = &ctx.accounts.channel;
+        
+        // Deep-link handlers use this to confirm the account is the canonical channel for its creator/id
+        let (expected, _) = channel_pda(&channel.creator, &channel.channel_id);
+        require!(channel.key() == expected, ErrorCode::InvalidChannelAddress);
+        
+        emit!(ChannelResolved {
             channel: channel.key(),
             creator: channel.creator,
-            timestamp: Clock::get()?.unix_timestamp,
+            channel_id: channel.channel_id.clone(),
+            is_active: channel.is_active,
         });
         
         Ok(())
     }
-    
-    pub fn set_protocol_pause(
-        ctx: Context<SetProtocolPause>,
-        paused: bool,
+
+    /// Emits the balance's current nonce. Clients should call this after a dropped
+    /// `shielded_transfer` to learn whether the nonce advanced before retrying.
+    pub fn get_expected_nonce(ctx: Context<GetExpectedNonce>) -> Result<()> {
+        let balance = &ctx.accounts.shielded_balance;
+        
+        emit!(BalanceNonce {
+            balance_account: balance.key(),
+            owner: balance.owner,
+            nonce: balance.nonce,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn settle_shielded_balance(
+        ctx: Context<SettleShieldedBalance>,
+        new_commitment: [u8; 32],
+        proof: Vec<u8>,
     ) -> Result<()> {
-        let protocol = &mut ctx.accounts.protocol_config;
-        require!(ctx.accounts.authority.key() == protocol.authority, ErrorCode::Unauthorized);
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
+        require!(proof.len() <= 1024, ErrorCode::ProofTooLarge);
         
-        protocol.paused = paused;
+        let balance = &mut ctx.accounts.shielded_balance;
         
-        emit!(ProtocolPauseChanged {
-            authority: ctx.accounts.authority.key(),
-            paused,
-            timestamp: Clock::get()?.unix_timestamp,
+        // Every pending transfer must have been made against the commitment being replaced
+        require!(
+            balance
+                .pending_transfers
+                .iter()
+                .filter(|record| !record.is_dummy)
+                .all(|record| record.to_commitment == balance.commitment),
+            ErrorCode::TransferCommitmentMismatch
+        );
+        
+        let settled_count = balance.real_pending_count() as u64;
+        balance.commitment = new_commitment;
+        balance.pending_transfers.clear();
+        if ctx.accounts.protocol_config.pad_pending {
+            balance.pad_pending();
+        }
+        
+        emit!(ShieldedBalanceSettled {
+            balance_account: balance.key(),
+            owner: balance.owner,
+            new_commitment,
+            settled_count,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
     }
-    
-    pub fn send_devnet_tokens(
-        ctx: Context<SendDevnetTokens>,
-        amount: u64,
+
+    /// Settles only the pending records named by `nullifiers`, for partial reconciliation.
+    /// The proof covers the subset; records left pending carry over onto `new_commitment`.
+    pub fn settle_pending_subset(
+        ctx: Context<SettleShieldedBalance>,
+        nullifiers: Vec<[u8; 32]>,
+        new_commitment: [u8; 32],
+        proof: Vec<u8>,
     ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
         require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
-        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
+        require!(proof.len() <= 1024, ErrorCode::ProofTooLarge);
+        require!(!nullifiers.is_empty(), ErrorCode::PendingNotFound);
         
-        let sender_token_account = &ctx.accounts.sender_token_account;
-        let recipient_token_account = &ctx.accounts.recipient_token_account;
+        let balance = &mut ctx.accounts.shielded_balance;
+        for nullifier in &nullifiers {
+            let index = balance
+                .pending_transfers
+                .iter()
+                .position(|record| !record.is_dummy && record.nullifier == *nullifier)
+                .ok_or(ErrorCode::PendingNotFound)?;
+            require!(
+                balance.pending_transfers[index].to_commitment == balance.commitment,
+                ErrorCode::TransferCommitmentMismatch
+            );
+            balance.pending_transfers.remove(index);
+        }
         
-        // Validate token accounts have correct mint
-        require!(sender_token_account.mint == ctx.accounts.mint.key(), ErrorCode::MintMismatch);
-        require!(recipient_token_account.mint == ctx.accounts.mint.key(), ErrorCode::MintMismatch);
+        for record in balance.pending_transfers.iter_mut().filter(|record| !record.is_dummy) {
+            record.to_commitment = new_commitment;
+        }
+        balance.commitment = new_commitment;
+        if ctx.accounts.protocol_config.pad_pending {
+            balance.pad_pending();
+        }
         
-        // Validate sender has enough balance
-        require!(sender_token_account.amount >= amount, ErrorCode::InsufficientBalance);
+        emit!(ShieldedBalanceSettled {
+            balance_account: balance.key(),
+            owner: balance.owner,
+            new_commitment,
+            settled_count: nullifiers.len() as u64,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
         
-        // Validate sender is Signer
-        require!(ctx.accounts.sender.is_signer, ErrorCode::MissingRequiredSignature);
+        Ok(())
+    }
+
+    /// Recovery for a balance whose nonce has hit `u64::MAX`: with a proof that the
+    /// balance is fully settled, the owner restarts the nonce at 0.
+    pub fn reset_balance_nonce(
+        ctx: Context<SettleShieldedBalance>,
+        proof: Vec<u8>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
+        require!(!proof.is_empty() && proof.len() <= 1024, ErrorCode::ProofTooLarge);
         
-        // Perform the transfer
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.sender_token_account.to_account_info(),
-            to: ctx.accounts.recipient_token_account.to_account_info(),
-            authority: ctx.accounts.sender.to_account_info(),
-        };
+        let balance = &mut ctx.accounts.shielded_balance;
+        // The proof attests the commitment is consistent; pending credits must be settled into it first
+        require!(!balance.has_unsettled_transfers(), ErrorCode::PendingTransfersNotSettled);
         
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        let previous_nonce = balance.nonce;
+        balance.nonce = 0;
         
+        emit!(BalanceNonceReset {
+            balance_account: balance.key(),
+            owner: balance.owner,
+            previous_nonce,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn merge_shielded_balances(
+        ctx: Context<MergeShieldedBalances>,
+        proof: Vec<u8>,
+        merged_commitment: [u8; 32],
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
+        require!(!proof.is_empty() && proof.len() <= 1024, ErrorCode::ProofTooLarge);
+        require!(
+            ctx.accounts.target_balance.key() != ctx.accounts.source_balance.key(),
+            ErrorCode::DuplicateBalanceAccounts
+        );
+        
+        let source_balance = &ctx.accounts.source_balance;
+        let target_balance = &mut ctx.accounts.target_balance;
+        require!(target_balance.mint == source_balance.mint, ErrorCode::MintMismatch);
+        // Pending transfers would be lost when the source account is closed
+        require!(
+            !source_balance.has_unsettled_transfers(),
+            ErrorCode::PendingTransfersNotSettled
+        );
+        
+        // The proof attests merged_commitment = target.commitment + source.commitment
+        target_balance.commitment = merged_commitment;
+        target_balance.nonce = target_balance.nonce.checked_add(1).ok_or(ErrorCode::NonceOverflow)?;
+        
+        emit!(ShieldedBalancesMerged {
+            target_balance: target_balance.key(),
+            source_balance: source_balance.key(),
+            owner: target_balance.owner,
+            mint: target_balance.mint,
+            merged_commitment,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Moves tokens out of the balance's vault to a public token account. This is the
+    /// only path that emits `ShieldedToPublic`, marking where funds leave the shielded pool.
+    pub fn withdraw_shielded(
+        ctx: Context<WithdrawShielded>,
+        amount: u64,
+        new_commitment: [u8; 32],
+        proof: Vec<u8>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
+        require!(!proof.is_empty() && proof.len() <= 1024, ErrorCode::ProofTooLarge);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        
+        let balance = &ctx.accounts.shielded_balance;
+        // The proof attests new_commitment = commitment - amount, so pending credits must be settled first
+        require!(!balance.has_unsettled_transfers(), ErrorCode::PendingTransfersNotSettled);
+        require!(ctx.accounts.vault.mint == balance.mint, ErrorCode::MintMismatch);
+        require!(ctx.accounts.destination.mint == balance.mint, ErrorCode::MintMismatch);
+        require!(ctx.accounts.vault.amount >= amount, ErrorCode::InsufficientBalance);
+        
+        let owner_key = ctx.accounts.owner.key();
+        let seed_owner = balance.seed_owner;
+        let mint = balance.mint;
+        let bump = [ctx.bumps.shielded_balance];
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            b"shielded_balance",
+            seed_owner.as_ref(),
+            mint.as_ref(),
+            &bump,
+        ]];
+        
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.destination.to_account_info(),
+            authority: ctx.accounts.shielded_balance.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            signer_seeds,
+        );
         anchor_spl::token::transfer(cpi_ctx, amount)?;
         
-        emit!(DevnetTokensSent {
-            sender: ctx.accounts.sender.key(),
-            recipient: recipient_token_account.owner,
-            mint: ctx.accounts.mint.key(),
+        let balance = &mut ctx.accounts.shielded_balance;
+        balance.commitment = new_commitment;
+        balance.nonce = balance.nonce.checked_add(1).ok_or(ErrorCode::NonceOverflow)?;
+        
+        emit!(ShieldedToPublic {
+            balance_account: balance.key(),
+            owner: owner_key,
+            mint,
+            destination: ctx.accounts.destination.key(),
             amount,
-            timestamp: Clock::get()?.unix_timestamp,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
         });
         
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct InitializeProtocol<'info> {
-    #[account(
-        init,
-        payer = authority,
-        space = size_of::<ProtocolConfig>() + 8,
-        // Rent-exempt: ensure account has minimum lamports
-        rent_exempt = enforce
-    )]
-    pub protocol_config: Account<'info, ProtocolConfig>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    pub system_program: Program<'info, System>,
-}
+    /// First step of moving a balance to a new key; `new_owner` completes it with
+    /// `accept_shielded_ownership`. Pending credits must be settled first so none are orphaned.
+    pub fn transfer_shielded_ownership(
+        ctx: Context<TransferShieldedOwnership>,
+        new_owner: Pubkey,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
+        require!(new_owner != ctx.accounts.owner.key(), ErrorCode::InvalidBalanceOwner);
+        
+        let balance = &mut ctx.accounts.shielded_balance;
+        require!(!balance.has_unsettled_transfers(), ErrorCode::PendingTransfersNotSettled);
+        balance.pending_owner = new_owner;
+        
+        emit!(ShieldedOwnershipTransferStarted {
+            balance_account: balance.key(),
+            owner: balance.owner,
+            pending_owner: new_owner,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(agent_name: String, encryption_pubkey: [u8; 32], capabilities: Vec<String>)]
-pub struct RegisterAgent<'info> {
-    #[account(
-        init,
-        payer = owner,
-        space = size_of::<Agent>() + 64 + (capabilities.len() * 32) + 8,
-        seeds = [b"agent", owner.key().as_ref()],
-        bump
-    )]
-    pub agent: Account<'info, Agent>,
-    #[account(mut)]
-    pub owner: Signer<'info>,
-    #[account(mut)]
-    pub protocol_config: Account<'info, ProtocolConfig>,
-    pub system_program: Program<'info, System>,
-}
+    pub fn accept_shielded_ownership(ctx: Context<AcceptShieldedOwnership>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_SHIELDED), ErrorCode::ModulePaused);
+        
+        let balance = &mut ctx.accounts.shielded_balance;
+        // Credits may have arrived since the transfer started
+        require!(!balance.has_unsettled_transfers(), ErrorCode::PendingTransfersNotSettled);
+        let previous_owner = balance.owner;
+        balance.owner = balance.pending_owner;
+        balance.pending_owner = Pubkey::default();
+        
+        emit!(ShieldedOwnershipTransferred {
+            balance_account: balance.key(),
+            previous_owner,
+            new_owner: balance.owner,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(channel_id: String, participants: Vec<Pubkey>, encrypted_metadata: Vec<u8>)]
-pub struct CreatePrivateChannel<'info> {
-    #[account(
-        init,
-        payer = creator,
-        space = size_of::<PrivateChannel>() + 48 + channel_id.len() + encrypted_metadata.len() + (participants.len() * 32) + 8,
-        seeds = [b"channel", creator.key().as_ref(), channel_id.as_bytes()],
-        bump
-    )]
-    pub channel: Account<'info, PrivateChannel>,
-    #[account(mut)]
-    pub creator: Signer<'info>,
-    #[account(mut)]
-    pub protocol_config: Account<'info, ProtocolConfig>,
-    pub system_program: Program<'info, System>,
-}
+    pub fn update_agent_capabilities(
+        ctx: Context<UpdateAgentCapabilities>,
+        new_capabilities: Vec<String>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(
+            new_capabilities.len() <= ctx.accounts.protocol_config.max_capabilities as usize,
+            ErrorCode::TooManyCapabilities
+        );
+        require!(!ctx.accounts.agent.capabilities_locked, ErrorCode::CapabilitiesLocked);
+        
+        // Agents registered with fewer capabilities grow to fit the new set
+        grow_account(
+            &ctx.accounts.agent.to_account_info(),
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            agent_space(new_capabilities.len()),
+        )?;
+        
+        let agent = &mut ctx.accounts.agent;
+        agent.capabilities = new_capabilities;
+        agent.encryption_nonce = agent.encryption_nonce.checked_add(1).unwrap();
+        // Fail here rather than leave an account that no longer deserializes
+        require!(
+            serialized_len(&**agent)? <= agent.to_account_info().data_len(),
+            ErrorCode::AccountSizeInconsistent
+        );
+        
+        emit!(AgentCapabilitiesUpdated {
+            agent: agent.key(),
+            owner: agent.owner,
+            capabilities: agent.capabilities.clone(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(message_id: String, encrypted_content: Vec<u8>, recipient: Pubkey)]
-pub struct SendEncryptedMessage<'info> {
-    #[account(
-        init,
-        payer = sender,
-        space = size_of::<EncryptedMessage>() + 48 + message_id.len() + encrypted_content.len() + 8,
-        seeds = [b"message", channel.key().as_ref(), sender.key().as_ref(), message_id.as_bytes()],
-        bump
-    )]
-    pub message: Account<'info, EncryptedMessage>,
-    #[account(mut)]
-    pub sender: Signer<'info>,
-    pub channel: Account<'info, PrivateChannel>,
-    pub system_program: Program<'info, System>,
-}
+    pub fn set_contact_prefs(
+        ctx: Context<UpdateAgentCapabilities>,
+        contact_prefs: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(contact_prefs & !CONTACT_ALL == 0, ErrorCode::InvalidContactPrefs);
+        
+        let agent = &mut ctx.accounts.agent;
+        agent.contact_prefs = contact_prefs;
+        
+        emit!(ContactPrefsUpdated {
+            agent: agent.key(),
+            owner: agent.owner,
+            contact_prefs,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct InitializeShieldedBalance<'info> {
+    /// Registers the Ethereum-style address (last 20 bytes of keccak256 of the public key)
+    /// whose secp256k1 signatures authenticate_message accepts. Zero clears it.
+    pub fn set_secp256k1_signer(
+        ctx: Context<UpdateAgentCapabilities>,
+        secp256k1_signer: [u8; 20],
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        
+        ctx.accounts.agent.secp256k1_signer = secp256k1_signer;
+        // Agents created before the field existed need the extra bytes
+        grow_to_fit(
+            &ctx.accounts.agent,
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        let agent = &ctx.accounts.agent;
+        emit!(Secp256k1SignerUpdated {
+            agent: agent.key(),
+            owner: agent.owner,
+            secp256k1_signer,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn rotate_encryption_key(
+        ctx: Context<UpdateAgentCapabilities>,
+        new_encryption_pubkey: [u8; 32],
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        
+        let now = Clock::get()?.unix_timestamp;
+        let agent = &mut ctx.accounts.agent;
+        // Rapid rotations would desync every sender; the cooldown is 0 (off) by default
+        require!(
+            agent.last_key_rotation == 0
+                || now - agent.last_key_rotation >= ctx.accounts.protocol_config.key_rotation_cooldown_seconds,
+            ErrorCode::RotationTooSoon
+        );
+        
+        agent.encryption_pubkey = new_encryption_pubkey;
+        agent.last_key_rotation = now;
+        
+        emit!(EncryptionKeyRotated {
+            agent: agent.key(),
+            owner: agent.owner,
+            encryption_pubkey: new_encryption_pubkey,
+            unix_timestamp: now,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Marks the agent as live for `block_inactive_recipients`.
+    pub fn heartbeat(ctx: Context<UpdateAgentCapabilities>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        
+        let agent = &mut ctx.accounts.agent;
+        agent.last_heartbeat = Clock::get()?.unix_timestamp;
+        
+        Ok(())
+    }
+
+    pub fn deactivate_agent(ctx: Context<UpdateAgentCapabilities>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        
+        let agent = &mut ctx.accounts.agent;
+        agent.is_active = false;
+        
+        emit!(AgentDeactivated {
+            agent: agent.key(),
+            owner: agent.owner,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn remove_capabilities(
+        ctx: Context<UpdateAgentCapabilities>,
+        capabilities: Vec<String>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(
+            capabilities.len() <= ctx.accounts.protocol_config.max_capabilities as usize,
+            ErrorCode::TooManyCapabilities
+        );
+        
+        let agent = &mut ctx.accounts.agent;
+        require!(!agent.capabilities_locked, ErrorCode::CapabilitiesLocked);
+        agent.capabilities.retain(|c| !capabilities.contains(c));
+        agent.encryption_nonce = agent.encryption_nonce.checked_add(1).unwrap();
+        
+        emit!(AgentCapabilitiesUpdated {
+            agent: agent.key(),
+            owner: agent.owner,
+            capabilities: agent.capabilities.clone(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// One-way: once locked, an agent's capabilities can never change again.
+    pub fn lock_capabilities(ctx: Context<UpdateAgentCapabilities>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        
+        let agent = &mut ctx.accounts.agent;
+        require!(!agent.capabilities_locked, ErrorCode::CapabilitiesLocked);
+        agent.capabilities_locked = true;
+        
+        emit!(CapabilitiesLocked {
+            agent: agent.key(),
+            owner: agent.owner,
+            capabilities: agent.capabilities.clone(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Pass `Pubkey::default()` to disable recovery. Changing the key cancels any
+    /// pending recovery.
+    pub fn set_recovery_key(
+        ctx: Context<UpdateAgentCapabilities>,
+        recovery_key: Pubkey,
+        recovery_delay_seconds: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(recovery_delay_seconds >= 0, ErrorCode::InvalidConfigValue);
+        
+        let agent = &mut ctx.accounts.agent;
+        agent.recovery_key = recovery_key;
+        agent.recovery_delay_seconds = recovery_delay_seconds;
+        agent.pending_owner = Pubkey::default();
+        agent.recovery_initiated_at = 0;
+        
+        emit!(RecoveryKeyUpdated {
+            agent: agent.key(),
+            owner: agent.owner,
+            recovery_key,
+            recovery_delay_seconds,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Signed by the recovery key. Starts the delay after which `complete_recovery`
+    /// hands the agent to `new_owner`; the current owner can cancel until then.
+    pub fn initiate_recovery(ctx: Context<AgentRecovery>, new_owner: Pubkey) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(new_owner != Pubkey::default(), ErrorCode::InvalidRecoveryOwner);
+        
+        let agent = &mut ctx.accounts.agent;
+        require!(agent.recovery_initiated_at == 0, ErrorCode::RecoveryPending);
+        
+        let clock = Clock::get()?;
+        agent.pending_owner = new_owner;
+        agent.recovery_initiated_at = clock.unix_timestamp;
+        
+        emit!(RecoveryInitiated {
+            agent: agent.key(),
+            owner: agent.owner,
+            new_owner,
+            completes_at: clock.unix_timestamp + agent.recovery_delay_seconds,
+            unix_timestamp: clock.unix_timestamp,
+            slot: clock.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn complete_recovery(ctx: Context<AgentRecovery>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        
+        let clock = Clock::get()?;
+        let agent = &mut ctx.accounts.agent;
+        require!(agent.recovery_initiated_at != 0, ErrorCode::NoPendingRecovery);
+        require!(
+            clock.unix_timestamp - agent.recovery_initiated_at >= agent.recovery_delay_seconds,
+            ErrorCode::RecoveryDelayNotElapsed
+        );
+        
+        // The agent PDA stays derived from the original owner; only the owner field moves
+        let previous_owner = agent.owner;
+        agent.owner = agent.pending_owner;
+        agent.pending_owner = Pubkey::default();
+        agent.recovery_initiated_at = 0;
+        
+        emit!(RecoveryCompleted {
+            agent: agent.key(),
+            previous_owner,
+            new_owner: agent.owner,
+            unix_timestamp: clock.unix_timestamp,
+            slot: clock.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn cancel_recovery(ctx: Context<UpdateAgentCapabilities>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        
+        let agent = &mut ctx.accounts.agent;
+        require!(agent.recovery_initiated_at != 0, ErrorCode::NoPendingRecovery);
+        
+        let cancelled_owner = agent.pending_owner;
+        agent.pending_owner = Pubkey::default();
+        agent.recovery_initiated_at = 0;
+        
+        emit!(RecoveryCancelled {
+            agent: agent.key(),
+            owner: agent.owner,
+            cancelled_owner,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn adjust_reputation(
+        ctx: Context<AdjustReputation>,
+        delta: i64,
+        reason_code: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let agent = &mut ctx.accounts.agent;
+        agent.reputation_score = agent.reputation_score.saturating_add(delta);
+        
+        let timestamp = Clock::get()?.unix_timestamp;
+        let log = &mut ctx.accounts.reputation_log;
+        log.agent = agent.key();
+        log.push(ReputationEntry { delta, reason_code, timestamp });
+        
+        emit!(ReputationAdjusted {
+            agent: agent.key(),
+            delta,
+            reason_code,
+            new_score: agent.reputation_score,
+            unix_timestamp: timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// An admin records a completed task for an agent, crediting the configured
+    /// `reputation_per_task` reward (0 by default).
+    pub fn complete_task(ctx: Context<CompleteTask>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        
+        let reward = ctx.accounts.protocol_config.reputation_per_task;
+        let agent = &mut ctx.accounts.agent;
+        require!(agent.is_active, ErrorCode::AgentInactive);
+        agent.total_tasks_completed = agent.total_tasks_completed.checked_add(1).unwrap();
+        agent.reputation_score = agent.reputation_score.saturating_add(reward);
+        
+        emit!(TaskCompleted {
+            agent: agent.key(),
+            total_tasks_completed: agent.total_tasks_completed,
+            reputation_reward: reward,
+            new_score: agent.reputation_score,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn emit_reputation_history(ctx: Context<EmitReputationHistory>) -> Result<()> {
+        let log = &ctx.accounts.reputation_log;
+        
+        emit!(ReputationHistory {
+            agent: log.agent,
+            entries: log.chronological(),
+        });
+        
+        Ok(())
+    }
+
+    /// Read-only task routing: picks the active agent in `remaining_accounts` with the
+    /// highest reputation that holds `capability` and meets `min_reputation`.
+    /// Ties go to the earliest candidate. Accounts that aren't agents are skipped.
+    pub fn match_agent_for_task(
+        ctx: Context<MatchAgentForTask>,
+        capability: String,
+        min_reputation: i64,
+    ) -> Result<()> {
+        let mut best: Option<(Pubkey, i64)> = None;
+        for candidate in ctx.remaining_accounts.iter() {
+            if *candidate.owner != crate::ID {
+                continue;
+            }
+            let Ok(agent) = Agent::try_deserialize(&mut &candidate.data.borrow()[..]) else {
+                continue;
+            };
+            if !agent.is_active
+                || agent.reputation_score < min_reputation
+                || !agent.capabilities.contains(&capability)
+            {
+                continue;
+            }
+            if best.map_or(true, |(_, reputation)| agent.reputation_score > reputation) {
+                best = Some((candidate.key(), agent.reputation_score));
+            }
+        }
+        
+        let (agent, reputation) = best.ok_or(ErrorCode::NoMatchingAgent)?;
+        emit!(AgentMatched { agent, reputation });
+        
+        Ok(())
+    }
+
+    /// A third party (e.g. a benchmark authority) vouches that an agent really has a
+    /// capability. Which attesters to trust is up to clients.
+    pub fn attest_capability(
+        ctx: Context<AttestCapability>,
+        capability: String,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!capability.is_empty() && capability.len() <= 32, ErrorCode::InvalidCapability);
+        
+        let attestation = &mut ctx.accounts.attestation;
+        attestation.agent = ctx.accounts.agent.key();
+        attestation.attester = ctx.accounts.attester.key();
+        attestation.capability = capability;
+        attestation.attested_at = Clock::get()?.unix_timestamp;
+        
+        emit!(CapabilityAttested {
+            attestation: attestation.key(),
+            agent: attestation.agent,
+            attester: attestation.attester,
+            capability: attestation.capability.clone(),
+            unix_timestamp: attestation.attested_at,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// The attester withdraws an attestation, reclaiming its rent.
+    pub fn revoke_attestation(ctx: Context<RevokeAttestation>) -> Result<()> {
+        let attestation = &ctx.accounts.attestation;
+        
+        emit!(AttestationRevoked {
+            attestation: attestation.key(),
+            agent: attestation.agent,
+            attester: attestation.attester,
+            capability: attestation.capability.clone(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    /// Emits every field of an agent in one log, for migration tooling that needs a
+    /// consistent view of the account.
+    pub fn export_agent_snapshot(ctx: Context<ExportAgentSnapshot>) -> Result<()> {
+        let agent = &ctx.accounts.agent;
+        
+        emit!(AgentSnapshot {
+            agent: agent.key(),
+            owner: agent.owner,
+            name: agent.name.clone(),
+            encryption_pubkey: agent.encryption_pubkey,
+            encryption_nonce: agent.encryption_nonce,
+            capabilities: agent.capabilities.clone(),
+            reputation_score: agent.reputation_score,
+            total_tasks_completed: agent.total_tasks_completed,
+            registered_at: agent.registered_at,
+            is_active: agent.is_active,
+            capabilities_locked: agent.capabilities_locked,
+            contact_prefs: agent.contact_prefs,
+            last_key_rotation: agent.last_key_rotation,
+            last_heartbeat: agent.last_heartbeat,
+            recovery_key: agent.recovery_key,
+            recovery_delay_seconds: agent.recovery_delay_seconds,
+            pending_owner: agent.pending_owner,
+            recovery_initiated_at: agent.recovery_initiated_at,
+            bump: agent.bump,
+            suspicious_messages: agent.suspicious_messages,
+            quota_window_start: agent.quota_window_start,
+            messages_in_window: agent.messages_in_window,
+            secp256k1_signer: agent.secp256k1_signer,
+        });
+        
+        Ok(())
+    }
+
+    pub fn export_channel_snapshot(ctx: Context<ExportChannelSnapshot>) -> Result<()> {
+        let channel = &ctx.accounts.channel;
+        
+        emit!(ChannelSnapshot {
+            channel: channel.key(),
+            creator: channel.creator,
+            channel_id: channel.channel_id.clone(),
+            participants: channel.participants.clone(),
+            encrypted_metadata: channel.encrypted_metadata.clone(),
+            message_count: channel.message_count,
+            created_at: channel.created_at,
+            is_active: channel.is_active,
+            roles: channel.roles.clone(),
+            metadata_hash: channel.metadata_hash,
+            message_fee: channel.message_fee,
+            fee_mint: channel.fee_mint,
+            policy: channel.policy,
+            next_sequence: channel.next_sequence,
+            metadata_format: channel.metadata_format,
+            bump: channel.bump,
+            archived: channel.archived,
+            is_direct: channel.is_direct,
+        });
+        
+        Ok(())
+    }
+
+    /// At-a-glance view of incoming transfers, so wallets needn't decode the whole list.
+    /// Dummy padding records are left out; timestamps are 0 when nothing is pending.
+    pub fn emit_pending_summary(ctx: Context<EmitPendingSummary>) -> Result<()> {
+        let balance = &ctx.accounts.shielded_balance;
+        
+        let pending: Vec<&ShieldedTransferRecord> =
+            balance.pending_transfers.iter().filter(|record| !record.is_dummy).collect();
+        let mut senders: Vec<Pubkey> = pending.iter().map(|record| record.from).collect();
+        senders.sort();
+        senders.dedup();
+        
+        emit!(PendingSummary {
+            balance_account: balance.key(),
+            owner: balance.owner,
+            pending_count: pending.len() as u32,
+            record_count: balance.pending_transfers.len() as u32,
+            senders,
+            earliest_timestamp: pending.iter().map(|record| record.timestamp).min().unwrap_or(0),
+            latest_timestamp: pending.iter().map(|record| record.timestamp).max().unwrap_or(0),
+        });
+        
+        Ok(())
+    }
+
+    pub fn export_balance_snapshot(ctx: Context<ExportBalanceSnapshot>) -> Result<()> {
+        let balance = &ctx.accounts.shielded_balance;
+        
+        emit!(BalanceSnapshot {
+            balance_account: balance.key(),
+            owner: balance.owner,
+            mint: balance.mint,
+            commitment: balance.commitment,
+            pending_transfers: balance.pending_transfers.clone(),
+            nonce: balance.nonce,
+            bump: balance.bump,
+            seed_owner: balance.seed_owner,
+            pending_owner: balance.pending_owner,
+        });
+        
+        Ok(())
+    }
+
+    /// Writes the canonical bump into an agent created before agents stored one.
+    /// `seed_owner` is the owner the PDA was derived from, which differs from the
+    /// current owner after a recovery.
+    pub fn migrate_agent_bump(ctx: Context<MigrateAgentBump>, seed_owner: Pubkey) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        
+        let (expected, bump) = Pubkey::find_program_address(&[b"agent", seed_owner.as_ref()], &crate::ID);
+        require_keys_eq!(ctx.accounts.agent.key(), expected, ErrorCode::InvalidAccountAddress);
+        ctx.accounts.agent.bump = bump;
+        grow_to_fit(
+            &ctx.accounts.agent,
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(AccountMigrated {
+            account: expected,
+            bump,
+            migrated_by: ctx.accounts.signer.key(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn migrate_channel_bump(ctx: Context<MigrateChannelBump>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        
+        let channel = &ctx.accounts.channel;
+        let (expected, bump) = channel_pda(&channel.creator, &channel.channel_id);
+        require_keys_eq!(channel.key(), expected, ErrorCode::InvalidAccountAddress);
+        ctx.accounts.channel.bump = bump;
+        grow_to_fit(
+            &ctx.accounts.channel,
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(AccountMigrated {
+            account: expected,
+            bump,
+            migrated_by: ctx.accounts.signer.key(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn migrate_balance_bump(ctx: Context<MigrateBalanceBump>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        
+        // Balances that predate seed_owner can't have changed owner, so owner is the seed
+        let balance = &ctx.accounts.shielded_balance;
+        let seed_owner = if balance.seed_owner == Pubkey::default() { balance.owner } else { balance.seed_owner };
+        let (expected, bump) = Pubkey::find_program_address(
+            &[b"shielded_balance", seed_owner.as_ref(), balance.mint.as_ref()],
+            &crate::ID,
+        );
+        require_keys_eq!(balance.key(), expected, ErrorCode::InvalidAccountAddress);
+        ctx.accounts.shielded_balance.bump = bump;
+        ctx.accounts.shielded_balance.seed_owner = seed_owner;
+        grow_to_fit(
+            &ctx.accounts.shielded_balance,
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+        )?;
+        
+        emit!(AccountMigrated {
+            account: expected,
+            bump,
+            migrated_by: ctx.accounts.signer.key(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    pub fn close_private_channel(ctx: Context<ClosePrivateChannel>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        let channel = &mut ctx.accounts.channel;
+        require!(channel.creator == ctx.accounts.creator.key(), ErrorCode::Unauthorized);
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        require!(channel.is_active, ErrorCode::ChannelInactive);
+        
+        // Prevent spin-up/tear-down griefing; the authority force-close path is exempt
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            now.saturating_sub(channel.created_at) >= ctx.accounts.protocol_config.min_channel_lifetime_seconds,
+            ErrorCode::ChannelTooYoung
+        );
+        
+        // Final stats, so archivers needn't race the close with an account fetch
+        let message_count = channel.message_count;
+        let participant_count = channel.participants.len() as u32;
+        let created_at = channel.created_at;
+        channel.is_active = false;
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.total_channels = protocol.total_channels.saturating_sub(1);
+        
+        emit!(PrivateChannelClosed {
+            channel: channel.key(),
+            creator: channel.creator,
+            message_count,
+            participant_count,
+            created_at,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Lets the creator reactivate a closed channel. Archived channels stay frozen.
+    pub fn reopen_channel(ctx: Context<ClosePrivateChannel>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        let channel = &mut ctx.accounts.channel;
+        require!(channel.creator == ctx.accounts.creator.key(), ErrorCode::Unauthorized);
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        require!(!channel.is_active, ErrorCode::ChannelAlreadyActive);
+        
+        channel.is_active = true;
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.total_channels = protocol.total_channels.checked_add(1).unwrap();
+        
+        emit!(PrivateChannelReopened {
+            channel: channel.key(),
+            creator: channel.creator,
+            message_count: channel.message_count,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Recovery path for channels whose creator can no longer sign. Use sparingly.
+    pub fn force_close_channel(ctx: Context<ForceCloseChannel>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let channel = &mut ctx.accounts.channel;
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        require!(channel.is_active, ErrorCode::ChannelInactive);
+        
+        channel.is_active = false;
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.total_channels = protocol.total_channels.saturating_sub(1);
+        
+        emit!(ChannelForceClosed {
+            channel: channel.key(),
+            creator: channel.creator,
+            authority: ctx.accounts.authority.key(),
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Freezes a channel for compliance archival. Unlike closing, this is permanent: messages
+    /// stay readable but no instruction may change the channel afterwards.
+    pub fn archive_channel(ctx: Context<ArchiveChannel>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        let channel = &mut ctx.accounts.channel;
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        
+        channel.archived = true;
+        
+        emit!(ChannelArchived {
+            channel: channel.key(),
+            creator: channel.creator,
+            archived_by: ctx.accounts.signer.key(),
+            message_count: channel.message_count,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Remediation for channels with stale message counts: sets the count an off-chain
+    /// indexer obtained by counting the channel's message PDAs.
+    pub fn repair_channel_count(
+        ctx: Context<RepairChannelCount>,
+        actual_count: u64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let channel = &mut ctx.accounts.channel;
+        let previous_count = channel.message_count;
+        channel.message_count = actual_count;
+        
+        emit!(ChannelCountRepaired {
+            channel: channel.key(),
+            authority: ctx.accounts.authority.key(),
+            previous_count,
+            message_count: actual_count,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn add_channel_participant(
+        ctx: Context<AddChannelParticipant>,
+        participant: Pubkey,
+        role: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        require!(role <= ROLE_OBSERVER, ErrorCode::InvalidRole);
+        
+        let channel = &ctx.accounts.channel;
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        require!(channel.is_active, ErrorCode::ChannelInactive);
+        require!(!channel.is_direct, ErrorCode::DirectChannelMembershipFixed);
+        require!(
+            !channel.participants.contains(&participant),
+            ErrorCode::ParticipantAlreadyInChannel
+        );
+        check_readd_cooldown(
+            &ctx.accounts.removal_record.to_account_info(),
+            ctx.accounts.protocol_config.readd_cooldown_seconds,
+        )?;
+        
+        increment_channel_count(
+            &ctx.accounts.participant_channel_count.to_account_info(),
+            &participant,
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.protocol_config.max_channels_per_participant,
+        )?;
+        
+        // Reject before growing the account rather than letting the realloc fail opaquely
+        let channel_info = ctx.accounts.channel.to_account_info();
+        let projected_size = channel_info.data_len() + PARTICIPANT_ENTRY_SIZE;
+        require!(
+            projected_size <= MAX_CHANNEL_ACCOUNT_SIZE,
+            ErrorCode::AccountSizeLimitReached
+        );
+        
+        grow_account(
+            &channel_info,
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            projected_size,
+        )?;
+        
+        let channel = &mut ctx.accounts.channel;
+        channel.insert_participant(participant, role);
+        
+        emit!(ChannelParticipantAdded {
+            channel: channel.key(),
+            participant,
+            role,
+            participant_count: channel.participants.len() as u32,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// The creator can't remove themselves, and the channel can't drop below
+    /// `min_participants`. The account keeps its size; the next add reuses the space.
+    pub fn remove_channel_participant(
+        ctx: Context<RemoveChannelParticipant>,
+        participant: Pubkey,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        
+        let channel = &mut ctx.accounts.channel;
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        require!(channel.is_active, ErrorCode::ChannelInactive);
+        require!(participant != channel.creator, ErrorCode::InvalidParticipants);
+        let index = channel
+            .participants
+            .iter()
+            .position(|p| *p == participant)
+            .ok_or(ErrorCode::NotAParticipant)?;
+        require!(
+            channel.participants.len() > ctx.accounts.protocol_config.min_participants as usize,
+            ErrorCode::TooFewParticipants
+        );
+        
+        channel.participants.remove(index);
+        if index < channel.roles.len() {
+            channel.roles.remove(index);
+        }
+        
+        // Members of channels created before counts were kept may have no count PDA
+        let count_info = ctx.accounts.participant_channel_count.to_account_info();
+        if count_info.owner == &crate::ID {
+            let mut count = ParticipantChannelCount::try_deserialize(&mut &count_info.try_borrow_data()?[..])?;
+            count.channel_count = count.channel_count.saturating_sub(1);
+            count.try_serialize(&mut &mut count_info.try_borrow_mut_data()?[..])?;
+        }
+        
+        let record = &mut ctx.accounts.removal_record;
+        record.channel = channel.key();
+        record.participant = participant;
+        record.removed_at = Clock::get()?.unix_timestamp;
+        
+        emit!(ChannelParticipantRemoved {
+            channel: channel.key(),
+            participant,
+            participant_count: channel.participants.len() as u32,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Opt-in alternative to `add_channel_participant`: the invitee joins by accepting.
+    pub fn invite_to_channel(
+        ctx: Context<InviteToChannel>,
+        invitee: Pubkey,
+        role: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        require!(role <= ROLE_OBSERVER, ErrorCode::InvalidRole);
+        
+        let channel = &ctx.accounts.channel;
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        require!(channel.is_active, ErrorCode::ChannelInactive);
+        require!(!channel.is_direct, ErrorCode::DirectChannelMembershipFixed);
+        require!(
+            !channel.participants.contains(&invitee),
+            ErrorCode::ParticipantAlreadyInChannel
+        );
+        
+        let invite = &mut ctx.accounts.invite;
+        invite.channel = channel.key();
+        invite.invitee = invitee;
+        invite.role = role;
+        invite.invited_at = Clock::get()?.unix_timestamp;
+        
+        emit!(ChannelInviteCreated {
+            channel: invite.channel,
+            invitee,
+            role,
+            unix_timestamp: invite.invited_at,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// The invitee pays to grow the channel and for their channel count PDA. The invite is
+    /// closed to the creator, so a participant removed later can be invited again.
+    pub fn accept_channel_invite(ctx: Context<AcceptChannelInvite>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        
+        let invitee = ctx.accounts.invitee.key();
+        let channel = &ctx.accounts.channel;
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        require!(channel.is_active, ErrorCode::ChannelInactive);
+        require!(!channel.is_direct, ErrorCode::DirectChannelMembershipFixed);
+        require!(
+            !channel.participants.contains(&invitee),
+            ErrorCode::ParticipantAlreadyInChannel
+        );
+        check_readd_cooldown(
+            &ctx.accounts.removal_record.to_account_info(),
+            ctx.accounts.protocol_config.readd_cooldown_seconds,
+        )?;
+        
+        increment_channel_count(
+            &ctx.accounts.participant_channel_count.to_account_info(),
+            &invitee,
+            &ctx.accounts.invitee.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.protocol_config.max_channels_per_participant,
+        )?;
+        
+        let channel_info = ctx.accounts.channel.to_account_info();
+        let projected_size = channel_info.data_len() + PARTICIPANT_ENTRY_SIZE;
+        require!(
+            projected_size <= MAX_CHANNEL_ACCOUNT_SIZE,
+            ErrorCode::AccountSizeLimitReached
+        );
+        
+        grow_account(
+            &channel_info,
+            &ctx.accounts.invitee.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            projected_size,
+        )?;
+        
+        let role = ctx.accounts.invite.role;
+        let channel = &mut ctx.accounts.channel;
+        channel.insert_participant(invitee, role);
+        
+        emit!(ChannelParticipantAdded {
+            channel: channel.key(),
+            participant: invitee,
+            role,
+            participant_count: channel.participants.len() as u32,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Rescinds a pending invite, refunding its rent to the creator.
+    pub fn cancel_channel_invite(ctx: Context<CancelChannelInvite>, invitee: Pubkey) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(
+            !ctx.accounts.channel.participants.contains(&invitee),
+            ErrorCode::InviteAlreadyAccepted
+        );
+        
+        emit!(ChannelInviteCancelled {
+            channel: ctx.accounts.channel.key(),
+            invitee,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_channel_message_fee(
+        ctx: Context<SetChannelMessageFee>,
+        message_fee: u64,
+        fee_mint: Pubkey,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        
+        let channel = &mut ctx.accounts.channel;
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        channel.message_fee = message_fee;
+        channel.fee_mint = fee_mint;
+        
+        emit!(ChannelMessageFeeChanged {
+            channel: channel.key(),
+            message_fee,
+            fee_mint,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_participant_role(
+        ctx: Context<SetParticipantRole>,
+        participant: Pubkey,
+        role: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_CHANNELS), ErrorCode::ModulePaused);
+        require!(role <= ROLE_OBSERVER, ErrorCode::InvalidRole);
+        
+        let channel = &mut ctx.accounts.channel;
+        require!(!channel.archived, ErrorCode::ChannelArchived);
+        require!(channel.is_active, ErrorCode::ChannelInactive);
+        // The creator cannot demote themselves out of their own channel
+        require!(participant != channel.creator, ErrorCode::InvalidRole);
+        
+        let index = channel
+            .participants
+            .iter()
+            .position(|p| *p == participant)
+            .ok_or(ErrorCode::NotAParticipant)?;
+        
+        // Channels created before roles existed may carry a short roles list
+        if channel.roles.len() < channel.participants.len() {
+            channel.roles.resize(channel.participants.len(), ROLE_MEMBER);
+        }
+        channel.roles[index] = role;
+        
+        emit!(ParticipantRoleUpdated {
+            channel: channel.key(),
+            participant,
+            role,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_protocol_pause(
+        ctx: Context<SetProtocolPause>,
+        paused: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        require!(protocol.is_admin(&ctx.accounts.authority.key()), ErrorCode::Unauthorized);
+        
+        protocol.paused = paused;
+        
+        emit!(ProtocolPauseChanged {
+            authority: ctx.accounts.authority.key(),
+            paused,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Pauses or resumes the modules in `flag` while leaving the rest running.
+    /// The global `paused` switch still overrides every module.
+    pub fn set_module_pause(
+        ctx: Context<SetProtocolPause>,
+        flag: u16,
+        paused: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(flag != 0 && flag & !MODULE_ALL == 0, ErrorCode::InvalidConfigValue);
+        let protocol = &mut ctx.accounts.protocol_config;
+        require!(protocol.is_admin(&ctx.accounts.authority.key()), ErrorCode::Unauthorized);
+        
+        if paused {
+            protocol.module_pause_flags |= flag;
+        } else {
+            protocol.module_pause_flags &= !flag;
+        }
+        
+        emit!(ModulePauseChanged {
+            authority: ctx.accounts.authority.key(),
+            flag,
+            paused,
+            module_pause_flags: protocol.module_pause_flags,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Publishes an authority-signed maintenance or incident notice for clients to surface.
+    pub fn broadcast_notice(
+        ctx: Context<UpdateProtocolConfig>,
+        notice: String,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(notice.len() <= MAX_NOTICE_LEN, ErrorCode::NoticeTooLong);
+        
+        emit!(ProtocolNotice {
+            authority: ctx.accounts.authority.key(),
+            notice,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn add_admin(ctx: Context<UpdateProtocolConfig>, admin: Pubkey) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        require!(!protocol.is_admin(&admin), ErrorCode::AdminAlreadyExists);
+        require!(protocol.admins.len() < MAX_PROTOCOL_ADMINS, ErrorCode::TooManyAdmins);
+        
+        protocol.admins.push(admin);
+        
+        emit!(AdminAdded {
+            authority: ctx.accounts.authority.key(),
+            admin,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn remove_admin(ctx: Context<UpdateProtocolConfig>, admin: Pubkey) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        let index = protocol.admins.iter().position(|a| *a == admin)
+            .ok_or(ErrorCode::AdminNotFound)?;
+        protocol.admins.remove(index);
+        
+        emit!(AdminRemoved {
+            authority: ctx.accounts.authority.key(),
+            admin,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_minimal_events(
+        ctx: Context<UpdateProtocolConfig>,
+        minimal_events: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.minimal_events = minimal_events;
+        
+        emit!(MinimalEventsChanged {
+            authority: ctx.accounts.authority.key(),
+            minimal_events,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Only gates high-volume per-transfer events; admin events are always emitted.
+    pub fn set_emit_events(
+        ctx: Context<UpdateProtocolConfig>,
+        emit_events: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.emit_events = emit_events;
+        
+        emit!(EmitEventsChanged {
+            authority: ctx.accounts.authority.key(),
+            emit_events,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_restrict_shield_mints(
+        ctx: Context<UpdateProtocolConfig>,
+        restrict_shield_mints: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.restrict_shield_mints = restrict_shield_mints;
+        
+        emit!(RestrictShieldMintsChanged {
+            authority: ctx.accounts.authority.key(),
+            restrict_shield_mints,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_restrict_registration(
+        ctx: Context<UpdateProtocolConfig>,
+        restrict_registration: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.restrict_registration = restrict_registration;
+        
+        emit!(RestrictRegistrationChanged {
+            authority: ctx.accounts.authority.key(),
+            restrict_registration,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_reputation_per_task(
+        ctx: Context<UpdateProtocolConfig>,
+        reputation_per_task: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.reputation_per_task = reputation_per_task;
+        
+        emit!(ReputationPerTaskChanged {
+            authority: ctx.accounts.authority.key(),
+            reputation_per_task,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_min_participants(
+        ctx: Context<UpdateProtocolConfig>,
+        min_participants: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        // Creation takes at most 10 participants plus the creator, so cap it there
+        require!(
+            (MIN_PARTICIPANTS_FLOOR..=11).contains(&min_participants),
+            ErrorCode::InvalidConfigValue
+        );
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.min_participants = min_participants;
+        
+        emit!(MinParticipantsChanged {
+            authority: ctx.accounts.authority.key(),
+            min_participants,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_message_quota(
+        ctx: Context<UpdateProtocolConfig>,
+        message_quota: u32,
+        message_quota_window_seconds: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(
+            message_quota == 0 || message_quota_window_seconds > 0,
+            ErrorCode::InvalidConfigValue
+        );
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.message_quota = message_quota;
+        protocol.message_quota_window_seconds = message_quota_window_seconds;
+        
+        emit!(MessageQuotaChanged {
+            authority: ctx.accounts.authority.key(),
+            message_quota,
+            message_quota_window_seconds,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Agents holding `capability` get `rate_multiplier` times the message quota (1 = off).
+    pub fn set_rate_multiplier(
+        ctx: Context<UpdateProtocolConfig>,
+        capability: String,
+        rate_multiplier: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!capability.is_empty() && capability.len() <= 32, ErrorCode::InvalidCapability);
+        require!(rate_multiplier >= 1, ErrorCode::InvalidConfigValue);
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.rate_multiplier_capability = capability_hash(&capability);
+        protocol.rate_multiplier = rate_multiplier;
+        
+        emit!(RateMultiplierChanged {
+            authority: ctx.accounts.authority.key(),
+            capability,
+            rate_multiplier,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_min_channel_lifetime(
+        ctx: Context<UpdateProtocolConfig>,
+        min_channel_lifetime_seconds: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(min_channel_lifetime_seconds >= 0, ErrorCode::InvalidConfigValue);
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.min_channel_lifetime_seconds = min_channel_lifetime_seconds;
+        
+        emit!(MinChannelLifetimeChanged {
+            authority: ctx.accounts.authority.key(),
+            min_channel_lifetime_seconds,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_min_token_transfer(
+        ctx: Context<UpdateProtocolConfig>,
+        min_token_transfer: u64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.min_token_transfer = min_token_transfer;
+        
+        emit!(MinTokenTransferChanged {
+            authority: ctx.accounts.authority.key(),
+            min_token_transfer,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_key_rotation_cooldown(
+        ctx: Context<UpdateProtocolConfig>,
+        key_rotation_cooldown_seconds: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(key_rotation_cooldown_seconds >= 0, ErrorCode::InvalidConfigValue);
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.key_rotation_cooldown_seconds = key_rotation_cooldown_seconds;
+        
+        emit!(KeyRotationCooldownChanged {
+            authority: ctx.accounts.authority.key(),
+            key_rotation_cooldown_seconds,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    /// Seconds a removed participant must wait before being re-added to the channel (0 = off).
+    pub fn set_readd_cooldown(
+        ctx: Context<UpdateProtocolConfig>,
+        readd_cooldown_seconds: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(readd_cooldown_seconds >= 0, ErrorCode::InvalidConfigValue);
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.readd_cooldown_seconds = readd_cooldown_seconds;
+        
+        emit!(ReaddCooldownChanged {
+            authority: ctx.accounts.authority.key(),
+            readd_cooldown_seconds,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_max_dark_pools(
+        ctx: Context<UpdateProtocolConfig>,
+        max_dark_pools: u64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.max_dark_pools = max_dark_pools;
+        
+        emit!(MaxDarkPoolsChanged {
+            authority: ctx.accounts.authority.key(),
+            max_dark_pools,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_pad_pending(
+        ctx: Context<UpdateProtocolConfig>,
+        pad_pending: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.pad_pending = pad_pending;
+        
+        emit!(PadPendingChanged {
+            authority: ctx.accounts.authority.key(),
+            pad_pending,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_require_metadata(
+        ctx: Context<UpdateProtocolConfig>,
+        require_metadata: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.require_metadata = require_metadata;
+        
+        emit!(RequireMetadataChanged {
+            authority: ctx.accounts.authority.key(),
+            require_metadata,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_max_channels_per_participant(
+        ctx: Context<UpdateProtocolConfig>,
+        max_channels_per_participant: u64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.max_channels_per_participant = max_channels_per_participant;
+        
+        emit!(MaxChannelsPerParticipantChanged {
+            authority: ctx.accounts.authority.key(),
+            max_channels_per_participant,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_block_inactive_recipients(
+        ctx: Context<UpdateProtocolConfig>,
+        block_inactive_recipients: bool,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.block_inactive_recipients = block_inactive_recipients;
+        
+        emit!(BlockInactiveRecipientsChanged {
+            authority: ctx.accounts.authority.key(),
+            block_inactive_recipients,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_heartbeat_stale_seconds(
+        ctx: Context<UpdateProtocolConfig>,
+        heartbeat_stale_seconds: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(heartbeat_stale_seconds >= 0, ErrorCode::InvalidConfigValue);
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.heartbeat_stale_seconds = heartbeat_stale_seconds;
+        
+        emit!(HeartbeatStaleSecondsChanged {
+            authority: ctx.accounts.authority.key(),
+            heartbeat_stale_seconds,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_max_message_size(
+        ctx: Context<UpdateProtocolConfig>,
+        max_message_size: u16,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(
+            max_message_size > 0 && max_message_size <= MAX_MESSAGE_SIZE_CEILING,
+            ErrorCode::InvalidConfigValue
+        );
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.max_message_size = max_message_size;
+        
+        emit!(MaxMessageSizeChanged {
+            authority: ctx.accounts.authority.key(),
+            max_message_size,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_max_capabilities(
+        ctx: Context<UpdateProtocolConfig>,
+        max_capabilities: u8,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(
+            max_capabilities > 0 && max_capabilities <= MAX_CAPABILITIES_CEILING,
+            ErrorCode::InvalidConfigValue
+        );
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.max_capabilities = max_capabilities;
+        
+        emit!(MaxCapabilitiesChanged {
+            authority: ctx.accounts.authority.key(),
+            max_capabilities,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_min_reputation_to_send(
+        ctx: Context<UpdateProtocolConfig>,
+        min_reputation_to_send: i64,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.min_reputation_to_send = min_reputation_to_send;
+        
+        emit!(MinReputationToSendChanged {
+            authority: ctx.accounts.authority.key(),
+            min_reputation_to_send,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn set_dark_pool_program_ids(
+        ctx: Context<UpdateProtocolConfig>,
+        inco_program_id: Pubkey,
+        light_system_program_id: Pubkey,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        
+        let protocol = &mut ctx.accounts.protocol_config;
+        protocol.inco_program_id = inco_program_id;
+        protocol.light_system_program_id = light_system_program_id;
+        
+        emit!(DarkPoolProgramIdsChanged {
+            authority: ctx.accounts.authority.key(),
+            inco_program_id,
+            light_system_program_id,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn add_shield_mint(ctx: Context<AddShieldMint>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let allowed_mint = &mut ctx.accounts.allowed_mint;
+        allowed_mint.mint = ctx.accounts.mint.key();
+        allowed_mint.added_at = Clock::get()?.unix_timestamp;
+        
+        emit!(ShieldMintAllowed {
+            authority: ctx.accounts.authority.key(),
+            mint: allowed_mint.mint,
+            unix_timestamp: allowed_mint.added_at,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn remove_shield_mint(ctx: Context<RemoveShieldMint>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        emit!(ShieldMintRemoved {
+            authority: ctx.accounts.authority.key(),
+            mint: ctx.accounts.allowed_mint.mint,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn add_agent_registrant(ctx: Context<AddAgentRegistrant>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        let registrant = &mut ctx.accounts.agent_registrant;
+        registrant.owner = ctx.accounts.owner.key();
+        registrant.added_at = Clock::get()?.unix_timestamp;
+        
+        emit!(AgentRegistrantAdded {
+            authority: ctx.accounts.authority.key(),
+            owner: registrant.owner,
+            unix_timestamp: registrant.added_at,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn remove_agent_registrant(ctx: Context<RemoveAgentRegistrant>) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        emit!(AgentRegistrantRemoved {
+            authority: ctx.accounts.authority.key(),
+            owner: ctx.accounts.agent_registrant.owner,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+    
+    pub fn send_devnet_tokens(
+        ctx: Context<SendDevnetTokens>,
+        amount: u64,
+        idempotency_key: Option<[u8; 16]>,
+    ) -> Result<()> {
+        require!(ctx.accounts.protocol_config.initialized, ErrorCode::ProtocolNotInitialized);
+        require!(!ctx.accounts.protocol_config.paused, ErrorCode::ProtocolPaused);
+        require!(!ctx.accounts.protocol_config.is_module_paused(MODULE_FAUCET), ErrorCode::ModulePaused);
+        require!(amount > 0, ErrorCode::InvalidAmount);
+        require!(
+            amount >= ctx.accounts.protocol_config.min_token_transfer,
+            ErrorCode::AmountBelowDust
+        );
+        
+        let sender_token_account = &ctx.accounts.sender_token_account;
+        let recipient_token_account = &ctx.accounts.recipient_token_account;
+        
+        // Validate token accounts have correct mint
+        require!(sender_token_account.mint == ctx.accounts.mint.key(), ErrorCode::MintMismatch);
+        require!(recipient_token_account.mint == ctx.accounts.mint.key(), ErrorCode::MintMismatch);
+        
+        // Validate sender has enough balance
+        require!(sender_token_account.amount >= amount, ErrorCode::InsufficientBalance);
+        
+        // Validate sender is Signer
+        require!(ctx.accounts.sender.is_signer, ErrorCode::MissingRequiredSignature);
+        
+        // A retried transaction with the same key finds the marker PDA and fails instead of re-sending
+        if let Some(key) = idempotency_key {
+            let marker = ctx
+                .accounts
+                .transfer_idempotency
+                .as_ref()
+                .ok_or(ErrorCode::MissingIdempotencyAccount)?;
+            let sender_key = ctx.accounts.sender.key();
+            let (expected, bump) = Pubkey::find_program_address(
+                &[TRANSFER_IDEMPOTENCY_SEED, sender_key.as_ref(), key.as_ref()],
+                &crate::ID,
+            );
+            require_keys_eq!(marker.key(), expected, ErrorCode::MissingIdempotencyAccount);
+            require!(marker.data_is_empty(), ErrorCode::DuplicateTransfer);
+            
+            let space = size_of::<TransferIdempotency>() + 8;
+            anchor_lang::system_program::create_account(
+                CpiContext::new_with_signer(
+                    ctx.accounts.system_program.to_account_info(),
+                    anchor_lang::system_program::CreateAccount {
+                        from: ctx.accounts.sender.to_account_info(),
+                        to: marker.to_account_info(),
+                    },
+                    &[&[TRANSFER_IDEMPOTENCY_SEED, sender_key.as_ref(), key.as_ref(), &[bump]]],
+                ),
+                Rent::get()?.minimum_balance(space),
+                space as u64,
+                &crate::ID,
+            )?;
+            let record = TransferIdempotency {
+                sender: sender_key,
+                idempotency_key: key,
+                amount,
+                created_at: Clock::get()?.unix_timestamp,
+            };
+            record.try_serialize(&mut &mut marker.try_borrow_mut_data()?[..])?;
+        }
+        
+        // Perform the transfer
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.sender_token_account.to_account_info(),
+            to: ctx.accounts.recipient_token_account.to_account_info(),
+            authority: ctx.accounts.sender.to_account_info(),
+        };
+        
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        
+        anchor_spl::token::transfer(cpi_ctx, amount)?;
+        
+        emit!(DevnetTokensSent {
+            sender: ctx.accounts.sender.key(),
+            recipient: recipient_token_account.owner,
+            mint: ctx.accounts.mint.key(),
+            amount,
+            unix_timestamp: Clock::get()?.unix_timestamp,
+            slot: Clock::get()?.slot,
+        });
+        
+        Ok(())
+    }
+
+    // --- Dark pool (handlers live in darkpool.rs) ---
+
+    #[cfg(feature = "darkpool")]
+    pub fn initialize_dark_pool(
+        ctx: Context<InitializeDarkPool>,
+        pool_id: String,
+        min_transfer_amount: u64,
+        max_transfer_amount: u64,
+        registration_fee_lamports: u64,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::initialize_dark_pool(
+            ctx,
+            pool_id,
+            min_transfer_amount,
+            max_transfer_amount,
+            registration_fee_lamports,
+        )
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn register_to_pool(
+        ctx: Context<RegisterToPool>,
+        nullifier: [u8; 32],
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::register_to_pool(ctx, nullifier)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn register_to_pool_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, RegisterToPoolBatch<'info>>,
+        registrations: Vec<PoolRegInit>,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::register_to_pool_batch(ctx, registrations)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn deregister_from_pool(ctx: Context<DeregisterFromPool>) -> Result<()> {
+        darkpool::agent_dark_pool::deregister_from_pool(ctx)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn refresh_registration(ctx: Context<RefreshRegistration>) -> Result<()> {
+        darkpool::agent_dark_pool::refresh_registration(ctx)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn dark_pool_transfer(
+        ctx: Context<DarkPoolTransfer>,
+        amount_ciphertext: Vec<u8>,
+        sender_nullifier: [u8; 32],
+        recipient_commitment: Pubkey,
+        zk_proof: Vec<u8>,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::dark_pool_transfer(
+            ctx,
+            amount_ciphertext,
+            sender_nullifier,
+            recipient_commitment,
+            zk_proof,
+        )
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn request_volume_decryption(ctx: Context<RequestVolumeDecryption>) -> Result<()> {
+        darkpool::agent_dark_pool::request_volume_decryption(ctx)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn fulfill_volume_decryption(
+        ctx: Context<FulfillVolumeDecryption>,
+        decrypted_volume: u64,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::fulfill_volume_decryption(ctx, decrypted_volume)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn set_max_pending_decryptions(
+        ctx: Context<UpdateDarkPool>,
+        max_pending_decryptions: u32,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::set_max_pending_decryptions(ctx, max_pending_decryptions)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn rotate_verification_key(
+        ctx: Context<UpdateDarkPool>,
+        new_vk: Pubkey,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::rotate_verification_key(ctx, new_vk)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn set_min_transfer_interval_slots(
+        ctx: Context<UpdateDarkPool>,
+        min_transfer_interval_slots: u64,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::set_min_transfer_interval_slots(ctx, min_transfer_interval_slots)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn set_registration_ttl(
+        ctx: Context<UpdateDarkPool>,
+        registration_ttl_seconds: i64,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::set_registration_ttl(ctx, registration_ttl_seconds)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn set_min_claim_delay_slots(
+        ctx: Context<UpdateDarkPool>,
+        min_claim_delay_slots: u64,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::set_min_claim_delay_slots(ctx, min_claim_delay_slots)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn set_velocity_limit(
+        ctx: Context<UpdateDarkPool>,
+        max_volume_per_window: u64,
+        window_slots: u64,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::set_velocity_limit(ctx, max_volume_per_window, window_slots)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn set_allowed_denominations(
+        ctx: Context<UpdateDarkPool>,
+        allowed_denominations: Vec<u64>,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::set_allowed_denominations(ctx, allowed_denominations)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn set_max_total_volume(
+        ctx: Context<UpdateDarkPool>,
+        max_total_volume: u64,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::set_max_total_volume(ctx, max_total_volume)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn set_max_transfers_per_slot(
+        ctx: Context<UpdateDarkPool>,
+        max_transfers_per_slot: u32,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::set_max_transfers_per_slot(ctx, max_transfers_per_slot)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn reconcile_pool(ctx: Context<ReconcilePool>) -> Result<()> {
+        darkpool::agent_dark_pool::reconcile_pool(ctx)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn check_nullifiers_unspent(
+        ctx: Context<CheckNullifiersUnspent>,
+        nullifiers: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::check_nullifiers_unspent(ctx, nullifiers)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn close_dark_pool(ctx: Context<CloseDarkPool>) -> Result<()> {
+        darkpool::agent_dark_pool::close_dark_pool(ctx)
+    }
+
+    #[cfg(feature = "darkpool")]
+    pub fn claim_private_transfer(
+        ctx: Context<ClaimPrivateTransfer>,
+        decryption_key: [u8; 32],
+        transfer_slot: u64,
+    ) -> Result<()> {
+        darkpool::agent_dark_pool::claim_private_transfer(ctx, decryption_key, transfer_slot)
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeProtocol<'info> {
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = size_of::<ProtocolConfig>() + 8 + MAX_PROTOCOL_ADMINS * 32,
+        seeds = [b"protocol_config"],
+        bump,
+        // Rent-exempt: ensure account has minimum lamports
+        rent_exempt = enforce
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(agent_name: String, encryption_pubkey: [u8; 32], capabilities: Vec<String>)]
+pub struct RegisterAgent<'info> {
+    #[account(
+        init,
+        payer = fee_payer,
+        space = agent_space(capabilities.len()),
+        seeds = [b"agent", owner.key().as_ref()],
+        bump
+    )]
+    pub agent: Account<'info, Agent>,
+    // Authorizes the registration; rent is paid by fee_payer, which may be a relayer
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub fee_payer: Signer<'info>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    // Required when restrict_registration is on
+    #[account(
+        seeds = [b"registrant", owner.key().as_ref()],
+        bump
+    )]
+    pub agent_registrant: Option<Account<'info, AgentRegistrant>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(channel_id: String, participants: Vec<Pubkey>, encrypted_metadata: Vec<u8>)]
+pub struct CreatePrivateChannel<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = size_of::<PrivateChannel>() + 48 + channel_id.len() + encrypted_metadata.len() + (participants.len() * 33) + 8,
+        seeds = [CHANNEL_SEED, creator.key().as_ref(), channel_id.as_bytes()],
+        bump
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: The protocol's channel creation policy PDA; enforced when initialized
+    #[account(
+        seeds = [CHANNEL_POLICY_SEED, protocol_config.authority.as_ref(), CHANNEL_CREATION_POLICY_ID],
+        bump
+    )]
+    pub channel_policy: UncheckedAccount<'info>,
+    // Required while a channel creation policy exists
+    #[account(seeds = [b"agent", creator.key().as_ref()], bump)]
+    pub creator_agent: Option<Account<'info, Agent>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(channel_args: ChannelArgs, message_args: MessageArgs)]
+pub struct CreateChannelAndSend<'info> {
+    // Declared before the message and sender state, whose seeds use its address
+    #[account(
+        init,
+        payer = creator,
+        space = size_of::<PrivateChannel>() + 48 + channel_args.channel_id.len()
+            + channel_args.encrypted_metadata.len() + (channel_args.participants.len() * 33) + 8,
+        seeds = [CHANNEL_SEED, creator.key().as_ref(), channel_args.channel_id.as_bytes()],
+        bump
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(
+        init,
+        payer = creator,
+        space = size_of::<EncryptedMessage>() + 48 + message_args.message_id.len()
+            + message_args.encrypted_content.len() + 8,
+        seeds = [MESSAGE_SEED, channel.key().as_ref(), creator.key().as_ref(), message_args.message_id.as_bytes()],
+        bump
+    )]
+    pub message: Account<'info, EncryptedMessage>,
+    #[account(
+        init,
+        payer = creator,
+        space = size_of::<SenderChannelState>() + 8,
+        seeds = [b"sender_state", channel.key().as_ref(), creator.key().as_ref()],
+        bump
+    )]
+    pub sender_state: Account<'info, SenderChannelState>,
+    /// CHECK: Recipient's agent PDA; may be uninitialized if the recipient never registered
+    #[account(
+        seeds = [b"agent", message_args.recipient.as_ref()],
+        bump
+    )]
+    pub recipient_agent: UncheckedAccount<'info>,
+    // Required once a reputation floor, message quota or channel creation policy applies
+    #[account(mut, seeds = [b"agent", creator.key().as_ref()], bump)]
+    pub creator_agent: Option<Account<'info, Agent>>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: The protocol's channel creation policy PDA; enforced when initialized
+    #[account(
+        seeds = [CHANNEL_POLICY_SEED, protocol_config.authority.as_ref(), CHANNEL_CREATION_POLICY_ID],
+        bump
+    )]
+    pub channel_policy: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(policy_id: String, rules: Vec<Vec<String>>)]
+pub struct CreateChannelPolicy<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = size_of::<ChannelPolicy>() + 8 + policy_id.len()
+            + rules.iter().map(|group| 4 + group.iter().map(|c| 4 + c.len()).sum::<usize>()).sum::<usize>(),
+        seeds = [CHANNEL_POLICY_SEED, authority.key().as_ref(), policy_id.as_bytes()],
+        bump
+    )]
+    pub channel_policy: Account<'info, ChannelPolicy>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseChannelPolicy<'info> {
+    #[account(mut, has_one = authority @ ErrorCode::Unauthorized, close = authority)]
+    pub channel_policy: Account<'info, ChannelPolicy>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(channel_id: String, peer: Pubkey, encrypted_metadata: Vec<u8>)]
+pub struct CreateDirectChannel<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = size_of::<PrivateChannel>() + 48 + channel_id.len() + encrypted_metadata.len()
+            + (2 * PARTICIPANT_ENTRY_SIZE) + 8,
+        seeds = [CHANNEL_SEED, creator.key().as_ref(), channel_id.as_bytes()],
+        bump
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: The protocol's channel creation policy PDA; enforced when initialized
+    #[account(
+        seeds = [CHANNEL_POLICY_SEED, protocol_config.authority.as_ref(), CHANNEL_CREATION_POLICY_ID],
+        bump
+    )]
+    pub channel_policy: UncheckedAccount<'info>,
+    // Required while a channel creation policy exists
+    #[account(seeds = [b"agent", creator.key().as_ref()], bump)]
+    pub creator_agent: Option<Account<'info, Agent>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(message_id: String, encrypted_content: Vec<u8>, recipient: Pubkey)]
+pub struct SendEncryptedMessage<'info> {
+    #[account(
+        init,
+        payer = fee_payer,
+        space = size_of::<EncryptedMessage>() + 48 + message_id.len() + encrypted_content.len() + 8,
+        seeds = [MESSAGE_SEED, channel.key().as_ref(), sender.key().as_ref(), message_id.as_bytes()],
+        bump
+    )]
+    pub message: Account<'info, EncryptedMessage>,
+    #[account(
+        init_if_needed,
+        payer = fee_payer,
+        space = size_of::<SenderChannelState>() + 8,
+        seeds = [b"sender_state", channel.key().as_ref(), sender.key().as_ref()],
+        bump
+    )]
+    pub sender_state: Account<'info, SenderChannelState>,
+    /// CHECK: Recipient's agent PDA; may be uninitialized if the recipient never registered
+    #[account(
+        seeds = [b"agent", recipient.as_ref()],
+        bump
+    )]
+    pub recipient_agent: UncheckedAccount<'info>,
+    // Required once a reputation floor is configured
+    #[account(
+        mut,
+        seeds = [b"agent", sender.key().as_ref()],
+        bump
+    )]
+    pub sender_agent: Option<Account<'info, Agent>>,
+    // Authorizes the send; rent is paid by fee_payer, which may be a relayer
+    pub sender: Signer<'info>,
+    #[account(mut)]
+    pub fee_payer: Signer<'info>,
+    #[account(mut)]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    // Required only when the channel charges a message fee
+    #[account(mut)]
+    pub sender_fee_account: Option<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub creator_fee_account: Option<Account<'info, TokenAccount>>,
+    pub token_program: Option<Program<'info, Token>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(recipient: Pubkey)]
+pub struct CanSend<'info> {
+    /// CHECK: Recipient's agent PDA; may be uninitialized if the recipient never registered
+    #[account(
+        seeds = [b"agent", recipient.as_ref()],
+        bump
+    )]
+    pub recipient_agent: UncheckedAccount<'info>,
+    #[account(
+        seeds = [b"agent", sender.key().as_ref()],
+        bump
+    )]
+    pub sender_agent: Option<Account<'info, Agent>>,
+    /// CHECK: The would-be sender; a pre-flight needn't be signed by them
+    pub sender: UncheckedAccount<'info>,
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub sender_fee_account: Option<Account<'info, TokenAccount>>,
+    pub creator_fee_account: Option<Account<'info, TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct AcknowledgeMessage<'info> {
+    #[account(
+        mut,
+        has_one = recipient @ ErrorCode::Unauthorized
+    )]
+    pub message: Account<'info, EncryptedMessage>,
+    pub recipient: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ReadMessage<'info> {
+    pub message: Account<'info, EncryptedMessage>,
+}
+
+#[derive(Accounts)]
+pub struct AuthenticateMessage<'info> {
+    pub message: Account<'info, EncryptedMessage>,
+    // Required for secp256k1, which checks against the agent's registered signer
+    #[account(
+        seeds = [b"agent", message.sender.as_ref()],
+        bump
+    )]
+    pub sender_agent: Option<Account<'info, Agent>>,
+    /// CHECK: Instructions sysvar, read for the preceding signature verification
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(message_id: String, per_recipient_ciphertexts: Vec<RecipientCiphertext>)]
+pub struct BroadcastEncryptedMessage<'info> {
+    #[account(
+        init,
+        payer = sender,
+        space = size_of::<GroupMessage>() + 48 + message_id.len()
+            + per_recipient_ciphertexts.iter().map(|c| 36 + c.ciphertext.len()).sum::<usize>() + 8,
+        seeds = [b"group_message", channel.key().as_ref(), sender.key().as_ref(), message_id.as_bytes()],
+        bump
+    )]
+    pub group_message: Account<'info, GroupMessage>,
+    #[account(mut)]
+    pub sender: Signer<'info>,
+    pub channel: Account<'info, PrivateChannel>,
+    // Required once a reputation floor or message quota is configured
+    #[account(
+        mut,
+        seeds = [b"agent", sender.key().as_ref()],
+        bump
+    )]
+    pub sender_agent: Option<Account<'info, Agent>>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateChannelMetadata<'info> {
+    #[account(
+        mut,
+        has_one = creator @ ErrorCode::Unauthorized
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveChannel<'info> {
+    pub channel: Account<'info, PrivateChannel>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeShieldedBalance<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = size_of::<ShieldedBalance>() + (MAX_PENDING_TRANSFERS * ShieldedTransferRecord::SIZE) + 8,
+        seeds = [b"shielded_balance", owner.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    /// CHECK: Verified in the handler to be an SPL Mint, so a bad account gets InvalidMint
+    pub mint: UncheckedAccount<'info>,
+    #[account(
+        seeds = [b"shieldmint", mint.key().as_ref()],
+        bump
+    )]
+    pub allowed_mint: Option<Account<'info, AllowedShieldMint>>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ShieldedTransfer<'info> {
+    #[account(
+        mut,
+        constraint = sender_balance.owner == sender.key() @ ErrorCode::InvalidBalanceOwner
+    )]
+    pub sender_balance: Account<'info, ShieldedBalance>,
+    #[account(mut)]
+    pub recipient_balance: Account<'info, ShieldedBalance>,
+    #[account(mut)]
+    pub sender: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct GetExpectedNonce<'info> {
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+}
+
+#[derive(Accounts)]
+pub struct SettleShieldedBalance<'info> {
+    #[account(
+        mut,
+        has_one = owner @ ErrorCode::InvalidBalanceOwner
+    )]
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+    pub owner: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct MergeShieldedBalances<'info> {
+    #[account(
+        mut,
+        has_one = owner @ ErrorCode::InvalidBalanceOwner
+    )]
+    pub target_balance: Account<'info, ShieldedBalance>,
+    #[account(
+        mut,
+        has_one = owner @ ErrorCode::InvalidBalanceOwner,
+        close = owner
+    )]
+    pub source_balance: Account<'info, ShieldedBalance>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawShielded<'info> {
+    #[account(
+        mut,
+        seeds = [b"shielded_balance", shielded_balance.seed_owner.as_ref(), shielded_balance.mint.as_ref()],
+        bump,
+        has_one = owner @ ErrorCode::InvalidBalanceOwner
+    )]
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+    // Token account holding the shielded funds, owned by the balance PDA
+    #[account(
+        mut,
+        constraint = vault.owner == shielded_balance.key() @ ErrorCode::InvalidBalanceOwner
+    )]
+    pub vault: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub destination: Account<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct TransferShieldedOwnership<'info> {
+    #[account(
+        mut,
+        has_one = owner @ ErrorCode::InvalidBalanceOwner
+    )]
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+    pub owner: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptShieldedOwnership<'info> {
+    #[account(
+        mut,
+        constraint = shielded_balance.pending_owner != Pubkey::default() @ ErrorCode::NoPendingOwnershipTransfer,
+        constraint = shielded_balance.pending_owner == new_owner.key() @ ErrorCode::Unauthorized
+    )]
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+    pub new_owner: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAgentCapabilities<'info> {
+    #[account(
+        mut,
+        has_one = owner @ ErrorCode::Unauthorized
+    )]
+    pub agent: Account<'info, Agent>,
+    // Pays rent when update_agent_capabilities grows the agent
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AgentRecovery<'info> {
+    #[account(
+        mut,
+        constraint = agent.recovery_key != Pubkey::default() @ ErrorCode::RecoveryNotConfigured,
+        constraint = agent.recovery_key == recovery_key.key() @ ErrorCode::Unauthorized
+    )]
+    pub agent: Account<'info, Agent>,
+    pub recovery_key: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct AdjustReputation<'info> {
+    #[account(mut)]
+    pub agent: Account<'info, Agent>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = size_of::<ReputationLog>() + 8,
+        seeds = [b"reputation_log", agent.key().as_ref()],
+        bump
+    )]
+    pub reputation_log: Account<'info, ReputationLog>,
+    #[account(
+        seeds = [b"protocol_config"],
+        bump,
+        constraint = protocol_config.is_admin(&authority.key()) @ ErrorCode::Unauthorized
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteTask<'info> {
+    #[account(mut)]
+    pub agent: Account<'info, Agent>,
+    #[account(
+        seeds = [b"protocol_config"],
+        bump,
+        constraint = protocol_config.is_admin(&authority.key()) @ ErrorCode::Unauthorized
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EmitReputationHistory<'info> {
+    pub reputation_log: Account<'info, ReputationLog>,
+}
+
+#[derive(Accounts)]
+#[instruction(capability: String)]
+pub struct AttestCapability<'info> {
+    #[account(
+        init,
+        payer = attester,
+        space = size_of::<CapabilityAttestation>() + 8 + capability.len(),
+        seeds = [
+            b"attest",
+            agent.key().as_ref(),
+            attester.key().as_ref(),
+            capability_hash(&capability).as_ref(),
+        ],
+        bump
+    )]
+    pub attestation: Account<'info, CapabilityAttestation>,
+    pub agent: Account<'info, Agent>,
+    #[account(mut)]
+    pub attester: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAttestation<'info> {
+    #[account(
+        mut,
+        has_one = attester @ ErrorCode::Unauthorized,
+        close = attester
+    )]
+    pub attestation: Account<'info, CapabilityAttestation>,
+    #[account(mut)]
+    pub attester: Signer<'info>,
+}
+
+// Candidates are passed as remaining_accounts
+#[derive(Accounts)]
+pub struct MatchAgentForTask {}
+
+#[derive(Accounts)]
+pub struct ExportAgentSnapshot<'info> {
+    pub agent: Account<'info, Agent>,
+}
+
+#[derive(Accounts)]
+pub struct ExportChannelSnapshot<'info> {
+    pub channel: Account<'info, PrivateChannel>,
+}
+
+#[derive(Accounts)]
+pub struct EmitPendingSummary<'info> {
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+}
+
+#[derive(Accounts)]
+pub struct ExportBalanceSnapshot<'info> {
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+}
+
+// Bump migrations may be run by the account's owner or the protocol authority; the
+// signer pays for any growth
+#[derive(Accounts)]
+pub struct MigrateAgentBump<'info> {
+    #[account(
+        mut,
+        constraint = agent.owner == signer.key() || protocol_config.authority == signer.key()
+            @ ErrorCode::Unauthorized
+    )]
+    pub agent: Account<'info, Agent>,
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateChannelBump<'info> {
+    #[account(
+        mut,
+        constraint = channel.creator == signer.key() || protocol_config.authority == signer.key()
+            @ ErrorCode::Unauthorized
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateBalanceBump<'info> {
+    #[account(
+        mut,
+        constraint = shielded_balance.owner == signer.key() || protocol_config.authority == signer.key()
+            @ ErrorCode::Unauthorized
+    )]
+    pub shielded_balance: Account<'info, ShieldedBalance>,
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClosePrivateChannel<'info> {
+    #[account(mut)]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct ForceCloseChannel<'info> {
+    #[account(mut)]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(
+        mut,
+        seeds = [b"protocol_config"],
+        bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ArchiveChannel<'info> {
+    #[account(
+        mut,
+        constraint = channel.creator == signer.key() || protocol_config.authority == signer.key()
+            @ ErrorCode::Unauthorized
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    pub signer: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct RepairChannelCount<'info> {
+    #[account(mut)]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = authority @ ErrorCode::Unauthorized)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddChannelParticipant<'info> {
+    #[account(
+        mut,
+        has_one = creator @ ErrorCode::Unauthorized
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    /// CHECK: The participant's ParticipantChannelCount PDA, verified and created if needed in the handler
+    #[account(mut)]
+    pub participant_channel_count: UncheckedAccount<'info>,
+    /// CHECK: The participant's ParticipantRemovalRecord PDA; uninitialized if never removed
+    #[account(
+        seeds = [REMOVAL_RECORD_SEED, channel.key().as_ref(), participant.as_ref()],
+        bump
+    )]
+    pub removal_record: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(participant: Pubkey)]
+pub struct RemoveChannelParticipant<'info> {
+    #[account(
+        mut,
+        has_one = creator @ ErrorCode::Unauthorized
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    /// CHECK: The participant's ParticipantChannelCount PDA; uninitialized for members of
+    /// channels created before counts were kept
+    #[account(
+        mut,
+        seeds = [PARTICIPANT_CHANNELS_SEED, participant.as_ref()],
+        bump
+    )]
+    pub participant_channel_count: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = size_of::<ParticipantRemovalRecord>() + 8,
+        seeds = [REMOVAL_RECORD_SEED, channel.key().as_ref(), participant.as_ref()],
+        bump
+    )]
+    pub removal_record: Account<'info, ParticipantRemovalRecord>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(invitee: Pubkey)]
+pub struct InviteToChannel<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = size_of::<ChannelInvite>() + 8,
+        seeds = [b"invite", channel.key().as_ref(), invitee.as_ref()],
+        bump
+    )]
+    pub invite: Account<'info, ChannelInvite>,
+    #[account(has_one = creator @ ErrorCode::Unauthorized)]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptChannelInvite<'info> {
+    #[account(
+        mut,
+        seeds = [b"invite", channel.key().as_ref(), invitee.key().as_ref()],
+        bump,
+        close = creator
+    )]
+    pub invite: Account<'info, ChannelInvite>,
+    #[account(mut)]
+    pub channel: Account<'info, PrivateChannel>,
+    /// CHECK: Paid for the invite and gets its rent back
+    #[account(mut, address = channel.creator @ ErrorCode::Unauthorized)]
+    pub creator: UncheckedAccount<'info>,
+    /// CHECK: The invitee's ParticipantChannelCount PDA, verified and created if needed in the handler
+    #[account(mut)]
+    pub participant_channel_count: UncheckedAccount<'info>,
+    /// CHECK: The invitee's ParticipantRemovalRecord PDA; uninitialized if never removed
     #[account(
-        init,
-        payer = owner,
-        space = size_of::<ShieldedBalance>() + 8,
-        seeds = [b"shielded_balance", owner.key().as_ref(), mint.key().as_ref()],
+        seeds = [REMOVAL_RECORD_SEED, channel.key().as_ref(), invitee.key().as_ref()],
         bump
     )]
-    pub shielded_balance: Account<'info, ShieldedBalance>,
+    pub removal_record: UncheckedAccount<'info>,
     #[account(mut)]
-    pub owner: Signer<'info>,
+    pub invitee: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(invitee: Pubkey)]
+pub struct CancelChannelInvite<'info> {
+    #[account(
+        mut,
+        seeds = [b"invite", channel.key().as_ref(), invitee.as_ref()],
+        bump,
+        close = creator
+    )]
+    pub invite: Account<'info, ChannelInvite>,
+    #[account(has_one = creator @ ErrorCode::Unauthorized)]
+    pub channel: Account<'info, PrivateChannel>,
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetChannelMessageFee<'info> {
+    #[account(
+        mut,
+        has_one = creator @ ErrorCode::Unauthorized
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    pub creator: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetParticipantRole<'info> {
+    #[account(
+        mut,
+        has_one = creator @ ErrorCode::Unauthorized
+    )]
+    pub channel: Account<'info, PrivateChannel>,
+    pub creator: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetProtocolPause<'info> {
+    #[account(mut, seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SendDevnetTokens<'info> {
+    #[account(mut)]
+    pub sender_token_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub recipient_token_account: Account<'info, TokenAccount>,
+    pub mint: Account<'info, Mint>,
+    #[account(mut)]
+    pub sender: Signer<'info>,
+    #[account(seeds = [b"protocol_config"], bump)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    /// CHECK: TransferIdempotency PDA for `idempotency_key`; verified and created in the handler
+    #[account(mut)]
+    pub transfer_idempotency: Option<UncheckedAccount<'info>>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateProtocolConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"protocol_config"],
+        bump,
+        has_one = authority @ ErrorCode::Unauthorized
+    )]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AddShieldMint<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = size_of::<AllowedShieldMint>() + 8,
+        seeds = [b"shieldmint", mint.key().as_ref()],
+        bump
+    )]
+    pub allowed_mint: Account<'info, AllowedShieldMint>,
     /// CHECK: Mint account
     pub mint: UncheckedAccount<'info>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = authority @ ErrorCode::Unauthorized)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AddAgentRegistrant<'info> {
+    #[account(
+        init,
+        payer = authority,
+        space = size_of::<AgentRegistrant>() + 8,
+        seeds = [b"registrant", owner.key().as_ref()],
+        bump
+    )]
+    pub agent_registrant: Account<'info, AgentRegistrant>,
+    /// CHECK: Owner being allowlisted to register an agent
+    pub owner: UncheckedAccount<'info>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = authority @ ErrorCode::Unauthorized)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
     pub system_program: Program<'info, System>,
 }
 
-#[derive(Accounts)]
-pub struct ShieldedTransfer<'info> {
-    #[account(
-        mut,
-        constraint = sender_balance.owner == sender.key() @ ErrorCode::InvalidBalanceOwner
-    )]
-    pub sender_balance: Account<'info, ShieldedBalance>,
-    #[account(mut)]
-    pub recipient_balance: Account<'info, ShieldedBalance>,
-    #[account(mut)]
-    pub sender: Signer<'info>,
-    pub protocol_config: Account<'info, ProtocolConfig>,
+#[derive(Accounts)]
+pub struct RemoveAgentRegistrant<'info> {
+    #[account(
+        mut,
+        seeds = [b"registrant", agent_registrant.owner.as_ref()],
+        bump,
+        close = authority
+    )]
+    pub agent_registrant: Account<'info, AgentRegistrant>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = authority @ ErrorCode::Unauthorized)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveShieldMint<'info> {
+    #[account(
+        mut,
+        seeds = [b"shieldmint", allowed_mint.mint.as_ref()],
+        bump,
+        close = authority
+    )]
+    pub allowed_mint: Account<'info, AllowedShieldMint>,
+    #[account(seeds = [b"protocol_config"], bump, has_one = authority @ ErrorCode::Unauthorized)]
+    pub protocol_config: Account<'info, ProtocolConfig>,
+    #[account(mut)]
+    pub authority: Signer<'info>,
+}
+
+#[account]
+pub struct ProtocolConfig {
+    pub authority: Pubkey,
+    pub initialized: bool,
+    pub paused: bool,
+    pub total_agents: u64,
+    // Channels currently active: creates and reopens count up, closes and force-closes down
+    pub total_channels: u64,
+    pub minimal_events: bool,
+    pub restrict_shield_mints: bool,
+    pub min_channel_lifetime_seconds: i64,
+    pub min_token_transfer: u64,
+    // Co-admins accepted alongside the authority for pause and reputation adjustments
+    pub admins: Vec<Pubkey>,
+    pub key_rotation_cooldown_seconds: i64,
+    // Seconds before a removed participant can be re-added to the same channel (0 = off)
+    pub readd_cooldown_seconds: i64,
+    // Dark pool cap (0 = unlimited) and the number of open pools
+    pub max_dark_pools: u64,
+    pub total_dark_pools: u64,
+    // Pad pending transfer lists with dummy records so their length doesn't reveal activity
+    pub pad_pending: bool,
+    // Channels a single participant can belong to (0 = unlimited)
+    pub max_channels_per_participant: u64,
+    // Reject messages to deactivated agents, or ones without a heartbeat within
+    // heartbeat_stale_seconds (0 = never stale)
+    pub block_inactive_recipients: bool,
+    pub heartbeat_stale_seconds: i64,
+    // Largest encrypted_content send_encrypted_message accepts
+    pub max_message_size: u16,
+    // Programs the dark pool may CPI into; unset until the authority configures them
+    pub inco_program_id: Pubkey,
+    pub light_system_program_id: Pubkey,
+    // Global ordinal of the last message sent, giving messages a total order
+    pub message_seq: u64,
+    // Capabilities an agent may hold, at most MAX_CAPABILITIES_CEILING
+    pub max_capabilities: u8,
+    // MODULE_* bits for subsystems paused independently of the global switch
+    pub module_pause_flags: u16,
+    // Reputation a sender's agent needs to send messages (i64::MIN = no floor)
+    pub min_reputation_to_send: i64,
+    // Reject channels created without encrypted metadata
+    pub require_metadata: bool,
+    // Only allowlisted owners (AgentRegistrant PDAs) may register agents
+    pub restrict_registration: bool,
+    // Reputation complete_task credits per task (0 = no reward)
+    pub reputation_per_task: i64,
+    // Fewest members, creator included, a channel may be created with or shrink to
+    pub min_participants: u8,
+    // When off, per-transfer events are skipped for deployments that index account state
+    pub emit_events: bool,
+    // Messages an agent may send per window of message_quota_window_seconds (0 = unlimited)
+    pub message_quota: u32,
+    pub message_quota_window_seconds: i64,
+    // capability_hash of the capability whose holders get rate_multiplier times the quota
+    pub rate_multiplier_capability: [u8; 32],
+    pub rate_multiplier: u8,
+}
+
+impl ProtocolConfig {
+    pub fn is_admin(&self, key: &Pubkey) -> bool {
+        *key == self.authority || self.admins.contains(key)
+    }
+    
+    pub fn is_module_paused(&self, module: u16) -> bool {
+        self.module_pause_flags & module != 0
+    }
+    
+    /// Reputation floor against spam; a sender without an agent has no reputation to meet it.
+    pub fn check_sender_reputation(&self, sender_agent: Option<&Agent>) -> Result<()> {
+        if self.min_reputation_to_send > i64::MIN {
+            let sender_agent = sender_agent.ok_or(ErrorCode::ReputationTooLow)?;
+            require!(
+                sender_agent.reputation_score >= self.min_reputation_to_send,
+                ErrorCode::ReputationTooLow
+            );
+        }
+        Ok(())
+    }
+    
+    /// Per-agent quota over a fixed window; like the reputation floor, a sender without an
+    /// agent has nothing to meter. Read-only: the send counts itself once through.
+    pub fn check_message_quota(&self, sender_agent: Option<&Agent>, now: i64) -> Result<()> {
+        if self.message_quota > 0 {
+            let agent = sender_agent.ok_or(ErrorCode::MessageQuotaExceeded)?;
+            let window_elapsed = now - agent.quota_window_start >= self.message_quota_window_seconds;
+            require!(
+                window_elapsed || agent.messages_in_window < self.message_quota_for(agent),
+                ErrorCode::MessageQuotaExceeded
+            );
+        }
+        Ok(())
+    }
+    
+    /// Counts a send that `check_message_quota` let through against the sender's window.
+    pub fn record_message(&self, sender_agent: Option<&mut Agent>, now: i64) -> Result<()> {
+        if self.message_quota > 0 {
+            if let Some(agent) = sender_agent {
+                if now - agent.quota_window_start >= self.message_quota_window_seconds {
+                    agent.quota_window_start = now;
+                    agent.messages_in_window = 0;
+                }
+                agent.messages_in_window = agent
+                    .messages_in_window
+                    .checked_add(1)
+                    .ok_or(ErrorCode::MessageQuotaExceeded)?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// The base message quota, scaled for holders of the rate-multiplier capability.
+    pub fn message_quota_for(&self, agent: &Agent) -> u32 {
+        let multiplied = self.rate_multiplier > 1
+            && agent
+                .capabilities
+                .iter()
+                .any(|c| capability_hash(c) == self.rate_multiplier_capability);
+        if multiplied {
+            self.message_quota.saturating_mul(self.rate_multiplier as u32)
+        } else {
+            self.message_quota
+        }
+    }
+}
+
+/// Marks an idempotency key as used by a sender's `send_devnet_tokens`.
+#[account]
+pub struct TransferIdempotency {
+    pub sender: Pubkey,
+    pub idempotency_key: [u8; 16],
+    pub amount: u64,
+    pub created_at: i64,
+}
+
+/// Presence of this PDA allowlists a mint for shielding when `restrict_shield_mints` is on.
+#[account]
+pub struct AllowedShieldMint {
+    pub mint: Pubkey,
+    pub added_at: i64,
+}
+
+/// Presence of this PDA allowlists an owner to register an agent when `restrict_registration` is on.
+#[account]
+pub struct AgentRegistrant {
+    pub owner: Pubkey,
+    pub added_at: i64,
+}
+
+#[account]
+pub struct Agent {
+    pub owner: Pubkey,
+    pub name: String,
+    pub encryption_pubkey: [u8; 32],
+    pub encryption_nonce: u64,
+    pub capabilities: Vec<String>,
+    pub reputation_score: i64,
+    pub total_tasks_completed: u64,
+    pub registered_at: i64,
+    pub is_active: bool,
+    pub capabilities_locked: bool,
+    pub contact_prefs: u8,
+    pub last_key_rotation: i64,
+    pub last_heartbeat: i64,
+    // Key allowed to take over ownership after recovery_delay_seconds; default means none
+    pub recovery_key: Pubkey,
+    pub recovery_delay_seconds: i64,
+    // Set by initiate_recovery; recovery_initiated_at is 0 when none is pending
+    pub pending_owner: Pubkey,
+    pub recovery_initiated_at: i64,
+    // Canonical PDA bump; accounts created before it existed get it from migrate_agent_bump
+    pub bump: u8,
+    // Sends flagged by looks_unencrypted
+    pub suspicious_messages: u32,
+    // Fixed message-quota window and the sends counted in it
+    pub quota_window_start: i64,
+    pub messages_in_window: u32,
+    // Address authenticate_message checks secp256k1 signatures against; zero when unset
+    pub secp256k1_signer: [u8; 20],
+}
+
+/// A third party's claim that an agent has a capability. Seeds are
+/// `[b"attest", agent, attester, keccak(capability)]`; revoking closes it.
+#[account]
+pub struct CapabilityAttestation {
+    pub agent: Pubkey,
+    pub attester: Pubkey,
+    pub capability: String,
+    pub attested_at: i64,
+}
+
+/// Capability requirements for channel creators: every group must contain at least one
+/// capability the creator's agent holds.
+#[account]
+pub struct ChannelPolicy {
+    pub authority: Pubkey,
+    pub policy_id: String,
+    pub rules: Vec<Vec<String>>,
+}
+
+impl ChannelPolicy {
+    pub fn is_satisfied_by(&self, capabilities: &[String]) -> bool {
+        self.rules
+            .iter()
+            .all(|group| group.iter().any(|capability| capabilities.contains(capability)))
+    }
+}
+
+/// Number of channels a participant has been added to, for `max_channels_per_participant`.
+#[account]
+pub struct ParticipantChannelCount {
+    pub participant: Pubkey,
+    pub channel_count: u64,
+    pub bump: u8,
+}
+
+/// Ring buffer of an agent's most recent reputation adjustments.
+#[account]
+pub struct ReputationLog {
+    pub agent: Pubkey,
+    pub entries: [ReputationEntry; REPUTATION_LOG_SIZE],
+    // Index the next entry is written to, and how many entries are filled
+    pub head: u8,
+    pub count: u8,
+}
+
+impl ReputationLog {
+    pub fn push(&mut self, entry: ReputationEntry) {
+        self.entries[self.head as usize] = entry;
+        self.head = ((self.head as usize + 1) % REPUTATION_LOG_SIZE) as u8;
+        if (self.count as usize) < REPUTATION_LOG_SIZE {
+            self.count += 1;
+        }
+    }
+    
+    /// Entries oldest first.
+    pub fn chronological(&self) -> Vec<ReputationEntry> {
+        let count = self.count as usize;
+        let start = (self.head as usize + REPUTATION_LOG_SIZE - count) % REPUTATION_LOG_SIZE;
+        (0..count)
+            .map(|i| self.entries[(start + i) % REPUTATION_LOG_SIZE])
+            .collect()
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct ReputationEntry {
+    pub delta: i64,
+    pub reason_code: u8,
+    pub timestamp: i64,
+}
+
+#[account]
+pub struct PrivateChannel {
+    pub creator: Pubkey,
+    pub channel_id: String,
+    pub participants: Vec<Pubkey>,
+    pub encrypted_metadata: Vec<u8>,
+    pub message_count: u64,
+    pub created_at: i64,
+    pub is_active: bool,
+    pub roles: Vec<u8>,
+    pub metadata_hash: [u8; 32],
+    // Token fee charged per message (0 = free), paid to the creator in fee_mint
+    pub message_fee: u64,
+    pub fee_mint: Pubkey,
+    // ChannelPolicy the creator satisfied, or the default key if none
+    pub policy: Pubkey,
+    // Channel-wide sequence the next message is stamped with, for gap detection
+    pub next_sequence: u64,
+    // METADATA_* tag telling clients how to parse encrypted_metadata
+    pub metadata_format: u8,
+    // Canonical PDA bump; set for older channels by migrate_channel_bump
+    pub bump: u8,
+    // Frozen for archival: readable, but no instruction may change it again
+    pub archived: bool,
+    // Created by create_direct_channel: exactly two participants, membership fixed
+    pub is_direct: bool,
+}
+
+impl PrivateChannel {
+    /// Role of the participant at `index`, treating missing entries as members.
+    pub fn role_at(&self, index: usize) -> u8 {
+        self.roles.get(index).copied().unwrap_or(ROLE_MEMBER)
+    }
+    
+    /// Inserts keeping participants sorted as they are at creation. The caller grows the
+    /// account by PARTICIPANT_ENTRY_SIZE first.
+    pub fn insert_participant(&mut self, participant: Pubkey, role: u8) {
+        if self.roles.len() < self.participants.len() {
+            self.roles.resize(self.participants.len(), ROLE_MEMBER);
+        }
+        let index = self.participants.partition_point(|p| *p < participant);
+        self.participants.insert(index, participant);
+        self.roles.insert(index, role);
+    }
+}
+
+/// A pending offer to join a channel, created by its creator and closed once the invitee
+/// accepts it or the creator cancels it. Seeds are `[b"invite", channel, invitee]`.
+#[account]
+pub struct ChannelInvite {
+    pub channel: Pubkey,
+    pub invitee: Pubkey,
+    pub role: u8,
+    pub invited_at: i64,
+}
+
+/// When a participant was last removed from a channel, for the re-add cooldown.
+/// Seeds are `[b"removal", channel, participant]`.
+#[account]
+pub struct ParticipantRemovalRecord {
+    pub channel: Pubkey,
+    pub participant: Pubkey,
+    pub removed_at: i64,
+}
+
+#[account]
+pub struct EncryptedMessage {
+    pub channel: Pubkey,
+    pub message_id: String,
+    pub sender: Pubkey,
+    pub recipient: Pubkey,
+    pub encrypted_content: Vec<u8>,
+    pub timestamp: i64,
+    pub delivered: bool,
+    pub slot: u64,
+    pub sequence: u64,
+    pub nonce: [u8; 24],
+    pub require_ack_by: i64,
+    // Position in the channel's total order across all senders
+    pub channel_sequence: u64,
+    // COMPRESSION_* tag; recipients decompress with it after decrypting
+    pub compression: u8,
+}
+
+/// Per-sender state within a channel, created on the sender's first message.
+#[account]
+pub struct SenderChannelState {
+    pub channel: Pubkey,
+    pub sender: Pubkey,
+    pub next_sequence: u64,
+    pub last_nonce: [u8; 24],
+}
+
+/// A single message fanned out to several participants, one ciphertext per recipient.
+#[account]
+pub struct GroupMessage {
+    pub channel: Pubkey,
+    pub message_id: String,
+    pub sender: Pubkey,
+    pub ciphertexts: Vec<RecipientCiphertext>,
+    pub timestamp: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ChannelArgs {
+    pub channel_id: String,
+    pub participants: Vec<Pubkey>,
+    pub encrypted_metadata: Vec<u8>,
+    pub participant_roles: Vec<u8>,
+    pub metadata_format: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct MessageArgs {
+    pub message_id: String,
+    pub encrypted_content: Vec<u8>,
+    pub recipient: Pubkey,
+    pub nonce: [u8; 24],
+    pub require_ack_by: i64,
+    pub compression: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct RecipientCiphertext {
+    pub recipient: Pubkey,
+    pub ciphertext: Vec<u8>,
+}
+
+#[account]
+pub struct ShieldedBalance {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub commitment: [u8; 32],
+    pub pending_transfers: Vec<ShieldedTransferRecord>,
+    pub nonce: u64,
+    // Canonical PDA bump; set for older balances by migrate_balance_bump
+    pub bump: u8,
+    // Owner the PDA was derived from; stays fixed when ownership is transferred
+    pub seed_owner: Pubkey,
+    // Set by transfer_shielded_ownership until the new owner accepts (default = none)
+    pub pending_owner: Pubkey,
+}
+
+impl ShieldedBalance {
+    pub fn real_pending_count(&self) -> usize {
+        self.pending_transfers.iter().filter(|record| !record.is_dummy).count()
+    }
+    
+    pub fn has_unsettled_transfers(&self) -> bool {
+        self.real_pending_count() > 0
+    }
+    
+    /// Adds a real record, taking the place of a dummy if the list is padded.
+    pub fn push_pending(&mut self, record: ShieldedTransferRecord) {
+        match self.pending_transfers.iter().position(|r| r.is_dummy) {
+            Some(index) => self.pending_transfers[index] = record,
+            None => self.pending_transfers.push(record),
+        }
+    }
+    
+    pub fn pad_pending(&mut self) {
+        while self.pending_transfers.len() < MAX_PENDING_TRANSFERS {
+            self.pending_transfers.push(ShieldedTransferRecord::dummy());
+        }
+    }
 }
 
-#[derive(Accounts)]
-pub struct UpdateAgentCapabilities<'info> {
-    #[account(
-        mut,
-        has_one = owner @ ErrorCode::Unauthorized
-    )]
-    pub agent: Account<'info, Agent>,
-    pub owner: Signer<'info>,
-    pub protocol_config: Account<'info, ProtocolConfig>,
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ShieldedTransferRecord {
+    pub amount_commitment: [u8; 32],
+    pub nullifier: [u8; 32],
+    pub from: Pubkey,
+    pub timestamp: i64,
+    pub to_commitment: [u8; 32],
+    pub is_dummy: bool,
 }
 
-#[derive(Accounts)]
-pub struct ClosePrivateChannel<'info> {
-    #[account(mut)]
-    pub channel: Account<'info, PrivateChannel>,
-    #[account(mut)]
-    pub creator: Signer<'info>,
-    pub protocol_config: Account<'info, ProtocolConfig>,
+impl ShieldedTransferRecord {
+    pub const SIZE: usize = 32 + 32 + 32 + 8 + 32 + 1;
+    
+    pub fn dummy() -> Self {
+        Self {
+            amount_commitment: [0u8; 32],
+            nullifier: [0u8; 32],
+            from: Pubkey::default(),
+            timestamp: 0,
+            to_commitment: [0u8; 32],
+            is_dummy: true,
+        }
+    }
 }
 
-#[derive(Accounts)]
-pub struct SetProtocolPause<'info> {
-    #[account(mut)]
-    pub protocol_config: Account<'info, ProtocolConfig>,
-    #[account(mut)]
-    pub authority: Signer<'info>,
+#[event]
+pub struct ProtocolInitialized {
+    pub authority: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AgentRegistered {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub name: String,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct PrivateChannelCreated {
+    pub channel: Pubkey,
+    pub creator: Pubkey,
+    pub participants: Vec<Pubkey>,
+    pub participant_count: u32,
+    pub metadata_format: u8,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct DirectChannelCreated {
+    pub channel: Pubkey,
+    pub creator: Pubkey,
+    pub peer: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EncryptedMessageSent {
+    pub message: Pubkey,
+    pub channel: Pubkey,
+    pub sender: Pubkey,
+    pub recipient: Pubkey,
+    pub message_id: String,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+    pub sequence: u64,
+    pub channel_sequence: u64,
+    pub fee_paid: u64,
+    pub message_seq: u64,
+    pub compression: u8,
+}
+
+#[event]
+pub struct SuspiciousMessageDetected {
+    pub sender: Pubkey,
+    pub channel: Pubkey,
+    pub content_len: u32,
+    // The sender agent's running count, 0 when the sender isn't a registered agent
+    pub suspicious_messages: u32,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MessageResolved {
+    pub message: Pubkey,
+    pub channel: Pubkey,
+    pub sender: Pubkey,
+    pub message_id: String,
+}
+
+#[event]
+pub struct MessageAcknowledged {
+    pub message: Pubkey,
+    pub channel: Pubkey,
+    pub recipient: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AckDeadlineMissed {
+    pub message: Pubkey,
+    pub channel: Pubkey,
+    pub sender: Pubkey,
+    pub recipient: Pubkey,
+    pub require_ack_by: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct GroupMessageSent {
+    pub message: Pubkey,
+    pub channel: Pubkey,
+    pub sender: Pubkey,
+    pub message_id: String,
+    pub recipient_count: u32,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+    pub message_seq: u64,
+}
+
+#[event]
+pub struct ChannelMetadataUpdated {
+    pub channel: Pubkey,
+    pub metadata_hash: [u8; 32],
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ChannelResolved {
+    pub channel: Pubkey,
+    pub creator: Pubkey,
+    pub channel_id: String,
+    pub is_active: bool,
+}
+
+#[event]
+pub struct ShieldedBalanceInitialized {
+    pub balance_account: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ShieldedOwnershipTransferStarted {
+    pub balance_account: Pubkey,
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ShieldedOwnershipTransferred {
+    pub balance_account: Pubkey,
+    pub previous_owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ShieldedTransferExecuted {
+    pub sender_balance: Pubkey,
+    pub recipient_balance: Pubkey,
+    pub nullifier: [u8; 32],
+    pub amount_commitment: [u8; 32],
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct BalanceNonce {
+    pub balance_account: Pubkey,
+    pub owner: Pubkey,
+    pub nonce: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ShieldedBalanceSettled {
+    pub balance_account: Pubkey,
+    pub owner: Pubkey,
+    pub new_commitment: [u8; 32],
+    pub settled_count: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct BalanceNonceReset {
+    pub balance_account: Pubkey,
+    pub owner: Pubkey,
+    pub previous_nonce: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ShieldedBalancesMerged {
+    pub target_balance: Pubkey,
+    pub source_balance: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub merged_commitment: [u8; 32],
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ShieldedToPublic {
+    pub balance_account: Pubkey,
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AgentCapabilitiesUpdated {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub capabilities: Vec<String>,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ContactPrefsUpdated {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub contact_prefs: u8,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct Secp256k1SignerUpdated {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub secp256k1_signer: [u8; 20],
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MessageAuthenticated {
+    pub message: Pubkey,
+    pub channel: Pubkey,
+    pub sender: Pubkey,
+    pub sig_scheme: u8,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct CapabilitiesLocked {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub capabilities: Vec<String>,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AdminAdded {
+    pub authority: Pubkey,
+    pub admin: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AdminRemoved {
+    pub authority: Pubkey,
+    pub admin: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct TaskCompleted {
+    pub agent: Pubkey,
+    pub total_tasks_completed: u64,
+    pub reputation_reward: i64,
+    pub new_score: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MessageQuotaChanged {
+    pub authority: Pubkey,
+    pub message_quota: u32,
+    pub message_quota_window_seconds: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RateMultiplierChanged {
+    pub authority: Pubkey,
+    pub capability: String,
+    pub rate_multiplier: u8,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MinParticipantsChanged {
+    pub authority: Pubkey,
+    pub min_participants: u8,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ReputationPerTaskChanged {
+    pub authority: Pubkey,
+    pub reputation_per_task: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ReputationAdjusted {
+    pub agent: Pubkey,
+    pub delta: i64,
+    pub reason_code: u8,
+    pub new_score: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ReputationHistory {
+    pub agent: Pubkey,
+    pub entries: Vec<ReputationEntry>,
+}
+
+#[event]
+pub struct CapabilityAttested {
+    pub attestation: Pubkey,
+    pub agent: Pubkey,
+    pub attester: Pubkey,
+    pub capability: String,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AttestationRevoked {
+    pub attestation: Pubkey,
+    pub agent: Pubkey,
+    pub attester: Pubkey,
+    pub capability: String,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
-#[account]
-pub struct ProtocolConfig {
-    pub authority: Pubkey,
-    pub initialized: bool,
-    pub paused: bool,
-    pub total_agents: u64,
-    pub total_channels: u64,
+#[event]
+pub struct AgentMatched {
+    pub agent: Pubkey,
+    pub reputation: i64,
 }
 
-#[account]
-pub struct Agent {
+#[event]
+pub struct AgentSnapshot {
+    pub agent: Pubkey,
     pub owner: Pubkey,
     pub name: String,
     pub encryption_pubkey: [u8; 32],
@@ -470,10 +5337,24 @@ pub struct Agent {
     pub total_tasks_completed: u64,
     pub registered_at: i64,
     pub is_active: bool,
+    pub capabilities_locked: bool,
+    pub contact_prefs: u8,
+    pub last_key_rotation: i64,
+    pub last_heartbeat: i64,
+    pub recovery_key: Pubkey,
+    pub recovery_delay_seconds: i64,
+    pub pending_owner: Pubkey,
+    pub recovery_initiated_at: i64,
+    pub bump: u8,
+    pub suspicious_messages: u32,
+    pub quota_window_start: i64,
+    pub messages_in_window: u32,
+    pub secp256k1_signer: [u8; 20],
 }
 
-#[account]
-pub struct PrivateChannel {
+#[event]
+pub struct ChannelSnapshot {
+    pub channel: Pubkey,
     pub creator: Pubkey,
     pub channel_id: String,
     pub participants: Vec<Pubkey>,
@@ -481,105 +5362,428 @@ pub struct PrivateChannel {
     pub message_count: u64,
     pub created_at: i64,
     pub is_active: bool,
+    pub roles: Vec<u8>,
+    pub metadata_hash: [u8; 32],
+    pub message_fee: u64,
+    pub fee_mint: Pubkey,
+    pub policy: Pubkey,
+    pub next_sequence: u64,
+    pub metadata_format: u8,
+    pub bump: u8,
+    pub archived: bool,
+    pub is_direct: bool,
 }
 
-#[account]
-pub struct EncryptedMessage {
-    pub channel: Pubkey,
-    pub message_id: String,
-    pub sender: Pubkey,
-    pub recipient: Pubkey,
-    pub encrypted_content: Vec<u8>,
-    pub timestamp: i64,
-    pub delivered: bool,
+#[event]
+pub struct PendingSummary {
+    pub balance_account: Pubkey,
+    pub owner: Pubkey,
+    // Real pending transfers, and the stored record count including dummy padding
+    pub pending_count: u32,
+    pub record_count: u32,
+    pub senders: Vec<Pubkey>,
+    pub earliest_timestamp: i64,
+    pub latest_timestamp: i64,
 }
 
-#[account]
-pub struct ShieldedBalance {
+#[event]
+pub struct BalanceSnapshot {
+    pub balance_account: Pubkey,
     pub owner: Pubkey,
     pub mint: Pubkey,
     pub commitment: [u8; 32],
     pub pending_transfers: Vec<ShieldedTransferRecord>,
     pub nonce: u64,
+    pub bump: u8,
+    pub seed_owner: Pubkey,
+    pub pending_owner: Pubkey,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct ShieldedTransferRecord {
-    pub amount_commitment: [u8; 32],
-    pub nullifier: [u8; 32],
-    pub from: Pubkey,
-    pub timestamp: i64,
+#[event]
+pub struct ChannelMessageFeeChanged {
+    pub channel: Pubkey,
+    pub message_fee: u64,
+    pub fee_mint: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
-pub struct ProtocolInitialized {
+pub struct ChannelCountRepaired {
+    pub channel: Pubkey,
     pub authority: Pubkey,
-    pub timestamp: i64,
+    pub previous_count: u64,
+    pub message_count: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
-pub struct AgentRegistered {
-    pub agent: Pubkey,
-    pub owner: Pubkey,
-    pub name: String,
-    pub timestamp: i64,
+pub struct ChannelPolicyCreated {
+    pub policy: Pubkey,
+    pub authority: Pubkey,
+    pub rules: Vec<Vec<String>>,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
-pub struct PrivateChannelCreated {
+pub struct ChannelPolicyClosed {
+    pub policy: Pubkey,
+    pub authority: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct PrivateChannelClosed {
     pub channel: Pubkey,
     pub creator: Pubkey,
-    pub participants: Vec<Pubkey>,
-    pub timestamp: i64,
+    pub message_count: u64,
+    pub participant_count: u32,
+    pub created_at: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
-pub struct EncryptedMessageSent {
-    pub message: Pubkey,
+pub struct ChannelForceClosed {
     pub channel: Pubkey,
-    pub sender: Pubkey,
-    pub recipient: Pubkey,
-    pub message_id: String,
-    pub timestamp: i64,
+    pub creator: Pubkey,
+    pub authority: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
-pub struct ShieldedBalanceInitialized {
-    pub balance_account: Pubkey,
-    pub owner: Pubkey,
-    pub mint: Pubkey,
-    pub timestamp: i64,
+pub struct PrivateChannelReopened {
+    pub channel: Pubkey,
+    pub creator: Pubkey,
+    pub message_count: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
-pub struct ShieldedTransferExecuted {
-    pub sender_balance: Pubkey,
-    pub recipient_balance: Pubkey,
-    pub nullifier: [u8; 32],
-    pub amount_commitment: [u8; 32],
-    pub timestamp: i64,
+pub struct ChannelArchived {
+    pub channel: Pubkey,
+    pub creator: Pubkey,
+    pub archived_by: Pubkey,
+    pub message_count: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
-pub struct AgentCapabilitiesUpdated {
-    pub agent: Pubkey,
-    pub owner: Pubkey,
-    pub capabilities: Vec<String>,
-    pub timestamp: i64,
+pub struct ChannelParticipantRemoved {
+    pub channel: Pubkey,
+    pub participant: Pubkey,
+    pub participant_count: u32,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
-pub struct PrivateChannelClosed {
+pub struct ChannelInviteCreated {
     pub channel: Pubkey,
-    pub creator: Pubkey,
-    pub timestamp: i64,
+    pub invitee: Pubkey,
+    pub role: u8,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ChannelInviteCancelled {
+    pub channel: Pubkey,
+    pub invitee: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ChannelParticipantAdded {
+    pub channel: Pubkey,
+    pub participant: Pubkey,
+    pub role: u8,
+    pub participant_count: u32,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ParticipantRoleUpdated {
+    pub channel: Pubkey,
+    pub participant: Pubkey,
+    pub role: u8,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ProtocolNotice {
+    pub authority: Pubkey,
+    pub notice: String,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
 pub struct ProtocolPauseChanged {
     pub authority: Pubkey,
     pub paused: bool,
-    pub timestamp: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ModulePauseChanged {
+    pub authority: Pubkey,
+    pub flag: u16,
+    pub paused: bool,
+    pub module_pause_flags: u16,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AccountMigrated {
+    pub account: Pubkey,
+    pub bump: u8,
+    pub migrated_by: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EmitEventsChanged {
+    pub authority: Pubkey,
+    pub emit_events: bool,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MinimalEventsChanged {
+    pub authority: Pubkey,
+    pub minimal_events: bool,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RestrictRegistrationChanged {
+    pub authority: Pubkey,
+    pub restrict_registration: bool,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RestrictShieldMintsChanged {
+    pub authority: Pubkey,
+    pub restrict_shield_mints: bool,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MinChannelLifetimeChanged {
+    pub authority: Pubkey,
+    pub min_channel_lifetime_seconds: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ReaddCooldownChanged {
+    pub authority: Pubkey,
+    pub readd_cooldown_seconds: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct KeyRotationCooldownChanged {
+    pub authority: Pubkey,
+    pub key_rotation_cooldown_seconds: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RecoveryKeyUpdated {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub recovery_key: Pubkey,
+    pub recovery_delay_seconds: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RecoveryInitiated {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub completes_at: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RecoveryCompleted {
+    pub agent: Pubkey,
+    pub previous_owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RecoveryCancelled {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub cancelled_owner: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct EncryptionKeyRotated {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub encryption_pubkey: [u8; 32],
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MaxDarkPoolsChanged {
+    pub authority: Pubkey,
+    pub max_dark_pools: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct RequireMetadataChanged {
+    pub authority: Pubkey,
+    pub require_metadata: bool,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct PadPendingChanged {
+    pub authority: Pubkey,
+    pub pad_pending: bool,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MaxChannelsPerParticipantChanged {
+    pub authority: Pubkey,
+    pub max_channels_per_participant: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct BlockInactiveRecipientsChanged {
+    pub authority: Pubkey,
+    pub block_inactive_recipients: bool,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct HeartbeatStaleSecondsChanged {
+    pub authority: Pubkey,
+    pub heartbeat_stale_seconds: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AgentDeactivated {
+    pub agent: Pubkey,
+    pub owner: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MaxMessageSizeChanged {
+    pub authority: Pubkey,
+    pub max_message_size: u16,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MaxCapabilitiesChanged {
+    pub authority: Pubkey,
+    pub max_capabilities: u8,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MinReputationToSendChanged {
+    pub authority: Pubkey,
+    pub min_reputation_to_send: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct DarkPoolProgramIdsChanged {
+    pub authority: Pubkey,
+    pub inco_program_id: Pubkey,
+    pub light_system_program_id: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct MinTokenTransferChanged {
+    pub authority: Pubkey,
+    pub min_token_transfer: u64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ShieldMintAllowed {
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AgentRegistrantAdded {
+    pub authority: Pubkey,
+    pub owner: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct AgentRegistrantRemoved {
+    pub authority: Pubkey,
+    pub owner: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct ShieldMintRemoved {
+    pub authority: Pubkey,
+    pub mint: Pubkey,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[event]
@@ -588,7 +5792,8 @@ pub struct DevnetTokensSent {
     pub recipient: Pubkey,
     pub mint: Pubkey,
     pub amount: u64,
-    pub timestamp: i64,
+    pub unix_timestamp: i64,
+    pub slot: u64,
 }
 
 #[error_code]
@@ -633,4 +5838,184 @@ pub enum ErrorCode {
     InsufficientBalance,
     #[msg("Missing required signature")]
     MissingRequiredSignature,
+    #[msg("Invalid participant role")]
+    InvalidRole,
+    #[msg("Participant role does not permit this action")]
+    InsufficientRole,
+    #[msg("Too many pending transfers - settle the balance first")]
+    TooManyPendingTransfers,
+    #[msg("Pending transfer was not made against the current commitment")]
+    TransferCommitmentMismatch,
+    #[msg("Balance has pending transfers that must be settled first")]
+    PendingTransfersNotSettled,
+    #[msg("Participant is already in this channel")]
+    ParticipantAlreadyInChannel,
+    #[msg("Channel account cannot grow any further")]
+    AccountSizeLimitReached,
+    #[msg("Channel account does not match its derived address")]
+    InvalidChannelAddress,
+    #[msg("Agent capabilities are locked")]
+    CapabilitiesLocked,
+    #[msg("Mint is not allowed for shielded balances")]
+    MintNotAllowed,
+    #[msg("Balance account has not been initialized")]
+    UninitializedBalance,
+    #[msg("Invalid contact preference bits")]
+    InvalidContactPrefs,
+    #[msg("Recipient does not accept this contact method")]
+    ContactMethodNotAccepted,
+    #[msg("Invalid configuration value")]
+    InvalidConfigValue,
+    #[msg("Channel has not reached its minimum lifetime")]
+    ChannelTooYoung,
+    #[msg("Channel metadata does not match its stored hash")]
+    MetadataCorrupted,
+    #[msg("Encryption nonce reused by this sender")]
+    NonceReused,
+    #[msg("Acknowledgement deadline must be in the future")]
+    InvalidAckDeadline,
+    #[msg("Message has already been acknowledged")]
+    MessageAlreadyAcknowledged,
+    #[msg("Amount is below the minimum token transfer")]
+    AmountBelowDust,
+    #[msg("Message account does not match its derived address")]
+    InvalidMessageAddress,
+    #[msg("Admin list is full")]
+    TooManyAdmins,
+    #[msg("Key is already an admin")]
+    AdminAlreadyExists,
+    #[msg("Admin not found")]
+    AdminNotFound,
+    #[msg("Encryption key was rotated too recently")]
+    RotationTooSoon,
+    #[msg("Participant channel count account is missing or invalid")]
+    InvalidChannelCountAccount,
+    #[msg("Participant is already in the maximum number of channels")]
+    ParticipantChannelLimitReached,
+    #[msg("Protocol config is not initialized")]
+    ProtocolNotInitialized,
+    #[msg("Channel charges a message fee but fee accounts were not provided")]
+    MissingFeeAccounts,
+    #[msg("Idempotency account is missing or does not match the key")]
+    MissingIdempotencyAccount,
+    #[msg("A transfer with this idempotency key was already sent")]
+    DuplicateTransfer,
+    #[msg("Channel policy is malformed")]
+    InvalidPolicy,
+    #[msg("Creator's capabilities do not satisfy the channel policy")]
+    PolicyNotSatisfied,
+    #[msg("Recipient agent is inactive or has no recent heartbeat")]
+    RecipientInactive,
+    #[msg("This module is paused")]
+    ModulePaused,
+    #[msg("Sender reputation is below the protocol minimum")]
+    ReputationTooLow,
+    #[msg("No pending transfer matches the nullifier")]
+    PendingNotFound,
+    #[msg("No candidate agent has the capability and minimum reputation")]
+    NoMatchingAgent,
+    #[msg("Channels must be created with encrypted metadata")]
+    EncryptedMetadataRequired,
+    #[msg("Capability must be 1-32 bytes")]
+    InvalidCapability,
+    #[msg("Owner is not allowlisted to register an agent")]
+    RegistrationNotAllowed,
+    #[msg("Agent is inactive")]
+    AgentInactive,
+    #[msg("Protocol is already initialized")]
+    AlreadyInitialized,
+    #[msg("Unknown compression algorithm")]
+    UnsupportedCompression,
+    #[msg("Agent has no recovery key")]
+    RecoveryNotConfigured,
+    #[msg("A recovery is already pending")]
+    RecoveryPending,
+    #[msg("No recovery is pending")]
+    NoPendingRecovery,
+    #[msg("Recovery delay has not elapsed")]
+    RecoveryDelayNotElapsed,
+    #[msg("Invalid recovery owner")]
+    InvalidRecoveryOwner,
+    #[msg("Clock timestamp precedes the channel's creation")]
+    TimestampAnomaly,
+    #[msg("Metadata does not match its declared format")]
+    InvalidMetadataFormat,
+    #[msg("Invite has already been accepted")]
+    InviteAlreadyAccepted,
+    #[msg("Channel would have fewer participants than the protocol minimum")]
+    TooFewParticipants,
+    #[msg("Account is not a valid SPL token mint")]
+    InvalidMint,
+    #[msg("Account does not match its derived address")]
+    InvalidAccountAddress,
+    #[msg("Account data no longer fits its allocated space")]
+    AccountSizeInconsistent,
+    #[msg("Notice exceeds maximum length")]
+    NoticeTooLong,
+    #[msg("No ownership transfer is pending for this balance")]
+    NoPendingOwnershipTransfer,
+    #[msg("Agent has used its message quota for this window")]
+    MessageQuotaExceeded,
+    #[msg("Participant was removed too recently to be re-added")]
+    ReaddTooSoon,
+    #[msg("Channel needs at least one participant besides the creator")]
+    NeedAtLeastOnePeer,
+    #[msg("Channel is archived and can no longer be changed")]
+    ChannelArchived,
+    #[msg("Unsupported signature scheme")]
+    UnsupportedSignatureScheme,
+    #[msg("Preceding instruction does not verify the message content signature")]
+    InvalidContentSignature,
+    #[msg("Agent has no secp256k1 signer registered")]
+    SigningKeyNotSet,
+    #[msg("Balance nonce does not match the expected nonce")]
+    NonceMismatch,
+    #[msg("Channel is already active")]
+    ChannelAlreadyActive,
+    #[msg("Recipient agent account is missing or invalid")]
+    InvalidRecipientAgentAccount,
+    #[msg("Direct channel membership cannot change")]
+    DirectChannelMembershipFixed,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Borsh reads all-zero bytes as an account with every field at its zero value
+    fn zeroed<T: AnchorDeserialize>() -> T {
+        T::deserialize(&mut &[0u8; 4096][..]).unwrap()
+    }
+
+    #[test]
+    fn send_gates_reject_a_zeroed_protocol_config() {
+        let config: ProtocolConfig = zeroed();
+        let channel: PrivateChannel = zeroed();
+        let sender = Pubkey::new_unique();
+        let recipient = Pubkey::new_unique();
+        let owner = Pubkey::default();
+        let mut lamports = 0u64;
+        let mut data = [0u8; 0];
+        let recipient_agent = AccountInfo::new(&recipient, false, false, &mut lamports, &mut data, &owner, false, 0);
+        
+        assert_eq!(
+            check_send_gates(&config, &channel, &sender, &recipient, None, &recipient_agent).unwrap_err(),
+            Error::from(ErrorCode::ProtocolNotInitialized)
+        );
+    }
+
+    #[test]
+    fn message_count_does_not_wrap_within_a_window() {
+        let mut config: ProtocolConfig = zeroed();
+        config.message_quota = 1;
+        config.message_quota_window_seconds = 3600;
+        let mut agent: Agent = zeroed();
+        agent.messages_in_window = u32::MAX;
+        
+        assert_eq!(
+            config.record_message(Some(&mut agent), 10).unwrap_err(),
+            Error::from(ErrorCode::MessageQuotaExceeded)
+        );
+        assert_eq!(agent.messages_in_window, u32::MAX);
+    }
 }
@@ -1,7 +1,8 @@
-import { Connection, PublicKey, Keypair, Transaction, SystemProgram, SYSVAR_RENT_PUBKEY } from '@solana/web3.js';
+import { Connection, PublicKey, Keypair, Transaction, TransactionInstruction, SystemProgram, SYSVAR_RENT_PUBKEY, SYSVAR_INSTRUCTIONS_PUBKEY } from '@solana/web3.js';
 import * as nacl from 'tweetnacl';
 import * as bs58 from 'bs58';
-import { Program, AnchorProvider, web3, utils, Wallet } from '@coral-xyz/anchor';
+import { Program, AnchorProvider, AnchorError, web3, utils, Wallet, BN } from '@coral-xyz/anchor';
+import { TOKEN_PROGRAM_ID } from '@solana/spl-token';
 import { IDL } from './types';
 
 export type { OpenclawPrivacyProtocol } from './types';
@@ -16,18 +17,50 @@ export interface PrivateChannel {
   channelId: string;
   participants: PublicKey[];
   encryptedMetadata: Uint8Array;
+  participantRoles?: number[];
 }
 
+export const ROLE_MEMBER = 0;
+export const ROLE_ADMIN = 1;
+export const ROLE_OBSERVER = 2;
+
+export const CONTACT_CHANNEL = 1 << 0;
+export const CONTACT_DM = 1 << 1;
+
+export const COMPRESSION_NONE = 0;
+export const COMPRESSION_ZSTD = 1;
+export const COMPRESSION_GZIP = 2;
+
+export const SIG_SCHEME_ED25519 = 0;
+export const SIG_SCHEME_SECP256K1 = 1;
+
+export const METADATA_OPAQUE = 0;
+export const METADATA_STRUCTURED_V1 = 1;
+
 export interface EncryptedMessage {
   messageId: string;
   encryptedContent: Uint8Array;
   recipient: PublicKey;
 }
 
+export interface SendOptions {
+  nonce?: Uint8Array;
+  requireAckBy?: number;
+  // COMPRESSION_* tag for how the plaintext was compressed before encryption
+  compression?: number;
+  // Relayer paying rent on the sender's behalf; defaults to the sender
+  feePayer?: Keypair;
+  // Token accounts for channels that charge a message fee
+  senderFeeAccount?: PublicKey;
+  creatorFeeAccount?: PublicKey;
+}
+
 export interface ShieldedTransfer {
   amountCommitment: Uint8Array;
   nullifier: Uint8Array;
   proof: Uint8Array;
+  // Sender balance nonce the transfer was built against; a mismatch fails with NonceMismatch
+  expectedNonce?: BN;
 }
 
 export class OCPClient {
@@ -39,6 +72,15 @@ export class OCPClient {
   readonly CHANNEL_SEED = "channel";
   readonly MESSAGE_SEED = "message";
   readonly BALANCE_SEED = "shielded_balance";
+  readonly SHIELD_MINT_SEED = "shieldmint";
+  readonly REGISTRANT_SEED = "registrant";
+  readonly SENDER_STATE_SEED = "sender_state";
+  readonly GROUP_MESSAGE_SEED = "group_message";
+  readonly PARTICIPANT_CHANNELS_SEED = "participant_channels";
+  readonly INVITE_SEED = "invite";
+  readonly REMOVAL_RECORD_SEED = "removal";
+  readonly CHANNEL_POLICY_SEED = "channel_policy";
+  readonly CHANNEL_CREATION_POLICY_ID = "channel_creation";
 
   static readonly PROGRAM_ID = new PublicKey('ocpP8j4zpgC9fqc3J2y6V3x9K1mNpRrL');
 
@@ -75,9 +117,12 @@ export class OCPClient {
     owner: Keypair,
     agentName: string,
     encryptionKeypair: Keypair,
-    capabilities: string[]
+    capabilities: string[],
+    feePayer: Keypair = owner
   ): Promise<{ tx: string; agent: PublicKey }> {
     const [agent] = this.findAgentAddress(owner.publicKey);
+    const [registrant] = this.findAgentRegistrantAddress(owner.publicKey);
+    const agentRegistrant = (await this.provider.connection.getAccountInfo(registrant)) ? registrant : null;
     
     const encryptionPubkey = encryptionKeypair.secretKey.slice(32, 64);
     
@@ -86,9 +131,12 @@ export class OCPClient {
       .accounts({
         agent,
         owner: owner.publicKey,
+        feePayer: feePayer.publicKey,
         protocolConfig: await this.getProtocolConfigAddress(),
+        agentRegistrant,
         systemProgram: SystemProgram.programId,
       })
+      .signers(feePayer === owner ? [owner] : [owner, feePayer])
       .rpc();
 
     return { tx, agent };
@@ -98,18 +146,147 @@ export class OCPClient {
     creator: Keypair,
     channelId: string,
     participants: PublicKey[],
+    encryptedMetadata: Uint8Array,
+    participantRoles: number[] = [],
+    metadataFormat: number = METADATA_OPAQUE
+  ): Promise<{ tx: string; channel: PublicKey }> {
+    const [channel] = this.findChannelAddress(creator.publicKey, channelId);
+    const channelPolicy = await this.getChannelCreationPolicyAddress();
+    const [creatorAgent] = this.findAgentAddress(creator.publicKey);
+    const creatorAgentInfo = await this.provider.connection.getAccountInfo(creatorAgent);
+    
+    // Every member's channel count PDA, in the sorted order the program stores participants
+    const members = [...participants, creator.publicKey]
+      .filter((p, i, all) => all.findIndex(q => q.equals(p)) === i)
+      .sort((a, b) => Buffer.compare(a.toBuffer(), b.toBuffer()));
+    const channelCounts = members.map(member => ({
+      pubkey: this.findParticipantChannelCountAddress(member)[0],
+      isSigner: false,
+      isWritable: true,
+    }));
+    
+    const tx = await this.program.methods
+      .createPrivateChannel(
+        channelId,
+        participants,
+        Array.from(encryptedMetadata),
+        Buffer.from(participantRoles),
+        metadataFormat
+      )
+      .accounts({
+        channel,
+        creator: creator.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        channelPolicy,
+        creatorAgent: creatorAgentInfo ? creatorAgent : null,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(channelCounts)
+      .signers([creator])
+      .rpc();
+
+    return { tx, channel };
+  }
+
+  async createChannelAndSend(
+    creator: Keypair,
+    channelId: string,
+    participants: PublicKey[],
+    encryptedMetadata: Uint8Array,
+    messageId: string,
+    encryptedContent: Uint8Array,
+    recipient: PublicKey,
+    options: {
+      nonce?: Uint8Array;
+      requireAckBy?: number;
+      compression?: number;
+      participantRoles?: number[];
+      metadataFormat?: number;
+    } = {}
+  ): Promise<{ tx: string; channel: PublicKey; message: PublicKey }> {
+    const [channel] = this.findChannelAddress(creator.publicKey, channelId);
+    const [message] = this.findMessageAddress(channel, creator.publicKey, messageId);
+    const [senderState] = this.findSenderStateAddress(channel, creator.publicKey);
+    const channelPolicy = await this.getChannelCreationPolicyAddress();
+    const [creatorAgent] = this.findAgentAddress(creator.publicKey);
+    const creatorAgentInfo = await this.program.provider.connection.getAccountInfo(creatorAgent);
+    const nonce = options.nonce ?? nacl.randomBytes(nacl.secretbox.nonceLength);
+    
+    const members = [...participants, creator.publicKey]
+      .filter((p, i, all) => all.findIndex(q => q.equals(p)) === i)
+      .sort((a, b) => Buffer.compare(a.toBuffer(), b.toBuffer()));
+    const channelCounts = members.map(member => ({
+      pubkey: this.findParticipantChannelCountAddress(member)[0],
+      isSigner: false,
+      isWritable: true,
+    }));
+    
+    const tx = await this.program.methods
+      .createChannelAndSend(
+        {
+          channelId,
+          participants,
+          encryptedMetadata: Buffer.from(encryptedMetadata),
+          participantRoles: Buffer.from(options.participantRoles ?? []),
+          metadataFormat: options.metadataFormat ?? METADATA_OPAQUE,
+        },
+        {
+          messageId,
+          encryptedContent: Buffer.from(encryptedContent),
+          recipient,
+          nonce: Array.from(nonce),
+          requireAckBy: new BN(options.requireAckBy ?? 0),
+          compression: options.compression ?? COMPRESSION_NONE,
+        }
+      )
+      .accounts({
+        channel,
+        message,
+        senderState,
+        recipientAgent: this.findAgentAddress(recipient)[0],
+        creatorAgent: creatorAgentInfo ? creatorAgent : null,
+        creator: creator.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        channelPolicy,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(channelCounts)
+      .signers([creator])
+      .rpc();
+
+    return { tx, channel, message };
+  }
+
+  async createDirectChannel(
+    creator: Keypair,
+    channelId: string,
+    peer: PublicKey,
     encryptedMetadata: Uint8Array
   ): Promise<{ tx: string; channel: PublicKey }> {
     const [channel] = this.findChannelAddress(creator.publicKey, channelId);
+    const [creatorAgent] = this.findAgentAddress(creator.publicKey);
+    const creatorAgentInfo = await this.provider.connection.getAccountInfo(creatorAgent);
+    
+    // Both members' channel count PDAs, in the sorted order the program stores participants
+    const channelCounts = [creator.publicKey, peer]
+      .sort((a, b) => Buffer.compare(a.toBuffer(), b.toBuffer()))
+      .map(member => ({
+        pubkey: this.findParticipantChannelCountAddress(member)[0],
+        isSigner: false,
+        isWritable: true,
+      }));
     
     const tx = await this.program.methods
-      .createPrivateChannel(channelId, participants, Array.from(encryptedMetadata))
+      .createDirectChannel(channelId, peer, Array.from(encryptedMetadata))
       .accounts({
         channel,
         creator: creator.publicKey,
         protocolConfig: await this.getProtocolConfigAddress(),
+        channelPolicy: await this.getChannelCreationPolicyAddress(),
+        creatorAgent: creatorAgentInfo ? creatorAgent : null,
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(channelCounts)
       .signers([creator])
       .rpc();
 
@@ -121,9 +298,18 @@ export class OCPClient {
     channel: PublicKey,
     messageId: string,
     encryptedContent: Uint8Array,
-    recipient: PublicKey
+    recipient: PublicKey,
+    options: SendOptions = {}
   ): Promise<string> {
+    const nonce = options.nonce ?? nacl.randomBytes(nacl.secretbox.nonceLength);
+    const requireAckBy = new BN(options.requireAckBy ?? 0);
+    const feePayer = options.feePayer ?? sender;
     const [message] = this.findMessageAddress(channel, sender.publicKey, messageId);
+    const [senderState] = this.findSenderStateAddress(channel, sender.publicKey);
+    const [recipientAgent] = this.findAgentAddress(recipient);
+    // Pass the sender's agent when registered so a reputation floor can be checked
+    const [senderAgent] = this.findAgentAddress(sender.publicKey);
+    const senderAgentInfo = await this.program.provider.connection.getAccountInfo(senderAgent);
     
     const channelData = await this.program.account.privateChannel.fetch(channel);
     if (!channelData.isActive) {
@@ -139,16 +325,144 @@ export class OCPClient {
     }
 
     const tx = await this.program.methods
-      .sendEncryptedMessage(messageId, Array.from(encryptedContent), recipient)
+      .sendEncryptedMessage(
+        messageId,
+        Array.from(encryptedContent),
+        recipient,
+        Array.from(nonce),
+        requireAckBy,
+        options.compression ?? COMPRESSION_NONE
+      )
       .accounts({
         message,
+        senderState,
+        recipientAgent,
+        senderAgent: senderAgentInfo ? senderAgent : null,
+        sender: sender.publicKey,
+        feePayer: feePayer.publicKey,
+        channel,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        senderFeeAccount: options.senderFeeAccount ?? null,
+        creatorFeeAccount: options.creatorFeeAccount ?? null,
+        tokenProgram: options.senderFeeAccount ? TOKEN_PROGRAM_ID : null,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers(feePayer === sender ? [sender] : [sender, feePayer])
+      .rpc();
+
+    return tx;
+  }
+
+  /**
+   * Sends one ciphertext per recipient in a single GroupMessage. Every recipient is checked
+   * like a sendEncryptedMessage recipient and counts against the sender's quota.
+   */
+  async broadcastEncryptedMessage(
+    sender: Keypair,
+    channel: PublicKey,
+    messageId: string,
+    ciphertexts: { recipient: PublicKey; ciphertext: Uint8Array }[]
+  ): Promise<{ tx: string; groupMessage: PublicKey }> {
+    const [groupMessage] = this.findGroupMessageAddress(channel, sender.publicKey, messageId);
+    const [senderAgent] = this.findAgentAddress(sender.publicKey);
+    const senderAgentInfo = await this.program.provider.connection.getAccountInfo(senderAgent);
+    const recipientAgents = ciphertexts.map(({ recipient }) => ({
+      pubkey: this.findAgentAddress(recipient)[0],
+      isSigner: false,
+      isWritable: false,
+    }));
+
+    const tx = await this.program.methods
+      .broadcastEncryptedMessage(
+        messageId,
+        ciphertexts.map(({ recipient, ciphertext }) => ({ recipient, ciphertext: Buffer.from(ciphertext) }))
+      )
+      .accounts({
+        groupMessage,
         sender: sender.publicKey,
         channel,
+        senderAgent: senderAgentInfo ? senderAgent : null,
+        protocolConfig: await this.getProtocolConfigAddress(),
         systemProgram: SystemProgram.programId,
       })
+      .remainingAccounts(recipientAgents)
       .signers([sender])
       .rpc();
 
+    return { tx, groupMessage };
+  }
+
+  /**
+   * Pre-flight for sendEncryptedMessage. Simulates can_send, so nothing is paid or written,
+   * and throws the AnchorError the send would fail with.
+   */
+  async canSend(
+    sender: PublicKey,
+    channel: PublicKey,
+    recipient: PublicKey,
+    options: Pick<SendOptions, 'senderFeeAccount' | 'creatorFeeAccount'> = {}
+  ): Promise<void> {
+    const [recipientAgent] = this.findAgentAddress(recipient);
+    const [senderAgent] = this.findAgentAddress(sender);
+    const senderAgentInfo = await this.provider.connection.getAccountInfo(senderAgent);
+
+    const transaction = await this.program.methods
+      .canSend(recipient)
+      .accounts({
+        recipientAgent,
+        senderAgent: senderAgentInfo ? senderAgent : null,
+        sender,
+        channel,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        senderFeeAccount: options.senderFeeAccount ?? null,
+        creatorFeeAccount: options.creatorFeeAccount ?? null,
+      })
+      .transaction();
+    transaction.feePayer = this.provider.wallet.publicKey;
+    transaction.recentBlockhash = (await this.provider.connection.getLatestBlockhash()).blockhash;
+
+    const { value } = await this.provider.connection.simulateTransaction(transaction);
+    if (value.err) {
+      throw AnchorError.parse(value.logs ?? []) ?? new Error(JSON.stringify(value.err));
+    }
+  }
+
+  async acknowledgeMessage(recipient: Keypair, message: PublicKey): Promise<string> {
+    const tx = await this.program.methods
+      .acknowledgeMessage()
+      .accounts({
+        message,
+        recipient: recipient.publicKey,
+      })
+      .signers([recipient])
+      .rpc();
+
+    return tx;
+  }
+
+  /**
+   * Authenticates a message's content. `verifyInstruction` is an Ed25519Program or
+   * Secp256k1Program instruction over keccak256 of the encrypted content; secp256k1
+   * instructions must reference instruction index 0.
+   */
+  async authenticateMessage(
+    message: PublicKey,
+    sigScheme: number,
+    verifyInstruction: TransactionInstruction
+  ): Promise<string> {
+    const { sender } = await this.getMessage(message);
+    const [senderAgent] = this.findAgentAddress(sender);
+
+    const tx = await this.program.methods
+      .authenticateMessage(sigScheme)
+      .accounts({
+        message,
+        senderAgent: (await this.provider.connection.getAccountInfo(senderAgent)) ? senderAgent : null,
+        instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+      })
+      .preInstructions([verifyInstruction])
+      .rpc();
+
     return tx;
   }
 
@@ -157,6 +471,8 @@ export class OCPClient {
     mint: PublicKey
   ): Promise<{ tx: string; balance: PublicKey }> {
     const [balance] = this.findShieldedBalanceAddress(owner.publicKey, mint);
+    const [shieldMint] = this.findAllowedShieldMintAddress(mint);
+    const allowedMint = (await this.provider.connection.getAccountInfo(shieldMint)) ? shieldMint : null;
     
     const tx = await this.program.methods
       .initializeShieldedBalance(mint)
@@ -164,6 +480,8 @@ export class OCPClient {
         shieldedBalance: balance,
         owner: owner.publicKey,
         mint,
+        allowedMint,
+        protocolConfig: await this.getProtocolConfigAddress(),
         systemProgram: SystemProgram.programId,
       })
       .signers([owner])
@@ -182,7 +500,8 @@ export class OCPClient {
       .shieldedTransfer(
         Array.from(transfer.amountCommitment),
         Array.from(transfer.nullifier),
-        Array.from(transfer.proof)
+        Array.from(transfer.proof),
+        transfer.expectedNonce ?? null
       )
       .accounts({
         senderBalance,
@@ -195,6 +514,34 @@ export class OCPClient {
     return tx;
   }
 
+  async transferShieldedOwnership(owner: Keypair, balance: PublicKey, newOwner: PublicKey): Promise<string> {
+    const tx = await this.program.methods
+      .transferShieldedOwnership(newOwner)
+      .accounts({
+        shieldedBalance: balance,
+        owner: owner.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+      })
+      .signers([owner])
+      .rpc();
+
+    return tx;
+  }
+
+  async acceptShieldedOwnership(newOwner: Keypair, balance: PublicKey): Promise<string> {
+    const tx = await this.program.methods
+      .acceptShieldedOwnership()
+      .accounts({
+        shieldedBalance: balance,
+        newOwner: newOwner.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+      })
+      .signers([newOwner])
+      .rpc();
+
+    return tx;
+  }
+
   async updateAgentCapabilities(
     owner: Keypair,
     agent: PublicKey,
@@ -205,6 +552,27 @@ export class OCPClient {
       .accounts({
         agent,
         owner: owner.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+
+    return tx;
+  }
+
+  async setSecp256k1Signer(
+    owner: Keypair,
+    agent: PublicKey,
+    ethAddress: Uint8Array
+  ): Promise<string> {
+    const tx = await this.program.methods
+      .setSecp256k1Signer(Array.from(ethAddress))
+      .accounts({
+        agent,
+        owner: owner.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
       })
       .signers([owner])
       .rpc();
@@ -233,6 +601,154 @@ export class OCPClient {
     return tx;
   }
 
+  async reopenChannel(
+    creator: Keypair,
+    channel: PublicKey
+  ): Promise<string> {
+    const tx = await this.program.methods
+      .reopenChannel()
+      .accounts({
+        channel,
+        creator: creator.publicKey,
+      })
+      .signers([creator])
+      .rpc();
+
+    return tx;
+  }
+
+  async archiveChannel(
+    signer: Keypair,
+    channel: PublicKey
+  ): Promise<string> {
+    const tx = await this.program.methods
+      .archiveChannel()
+      .accounts({
+        channel,
+        signer: signer.publicKey,
+      })
+      .signers([signer])
+      .rpc();
+
+    return tx;
+  }
+
+  async addChannelParticipant(
+    creator: Keypair,
+    channel: PublicKey,
+    participant: PublicKey,
+    role: number = ROLE_MEMBER
+  ): Promise<string> {
+    const tx = await this.program.methods
+      .addChannelParticipant(participant, role)
+      .accounts({
+        channel,
+        participantChannelCount: this.findParticipantChannelCountAddress(participant)[0],
+        removalRecord: this.findRemovalRecordAddress(channel, participant)[0],
+        creator: creator.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([creator])
+      .rpc();
+
+    return tx;
+  }
+
+  async removeChannelParticipant(creator: Keypair, channel: PublicKey, participant: PublicKey): Promise<string> {
+    const tx = await this.program.methods
+      .removeChannelParticipant(participant)
+      .accounts({
+        channel,
+        participantChannelCount: this.findParticipantChannelCountAddress(participant)[0],
+        removalRecord: this.findRemovalRecordAddress(channel, participant)[0],
+        creator: creator.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([creator])
+      .rpc();
+
+    return tx;
+  }
+
+  async inviteToChannel(
+    creator: Keypair,
+    channel: PublicKey,
+    invitee: PublicKey,
+    role: number = ROLE_MEMBER
+  ): Promise<{ tx: string; invite: PublicKey }> {
+    const [invite] = this.findChannelInviteAddress(channel, invitee);
+
+    const tx = await this.program.methods
+      .inviteToChannel(invitee, role)
+      .accounts({
+        invite,
+        channel,
+        creator: creator.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([creator])
+      .rpc();
+
+    return { tx, invite };
+  }
+
+  async acceptChannelInvite(invitee: Keypair, channel: PublicKey): Promise<string> {
+    const { creator } = await this.program.account.privateChannel.fetch(channel);
+    const tx = await this.program.methods
+      .acceptChannelInvite()
+      .accounts({
+        invite: this.findChannelInviteAddress(channel, invitee.publicKey)[0],
+        channel,
+        creator,
+        participantChannelCount: this.findParticipantChannelCountAddress(invitee.publicKey)[0],
+        removalRecord: this.findRemovalRecordAddress(channel, invitee.publicKey)[0],
+        invitee: invitee.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([invitee])
+      .rpc();
+
+    return tx;
+  }
+
+  async cancelChannelInvite(creator: Keypair, channel: PublicKey, invitee: PublicKey): Promise<string> {
+    const tx = await this.program.methods
+      .cancelChannelInvite(invitee)
+      .accounts({
+        invite: this.findChannelInviteAddress(channel, invitee)[0],
+        channel,
+        creator: creator.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+      })
+      .signers([creator])
+      .rpc();
+
+    return tx;
+  }
+
+  async setParticipantRole(
+    creator: Keypair,
+    channel: PublicKey,
+    participant: PublicKey,
+    role: number
+  ): Promise<string> {
+    const tx = await this.program.methods
+      .setParticipantRole(participant, role)
+      .accounts({
+        channel,
+        creator: creator.publicKey,
+        protocolConfig: await this.getProtocolConfigAddress(),
+      })
+      .signers([creator])
+      .rpc();
+
+    return tx;
+  }
+
   async getAgent(agent: PublicKey): Promise<any> {
     return await this.program.account.agent.fetch(agent);
   }
@@ -281,6 +797,45 @@ export class OCPClient {
     );
   }
 
+  findParticipantChannelCountAddress(participant: PublicKey): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from(this.PARTICIPANT_CHANNELS_SEED), participant.toBuffer()],
+      this.program.programId
+    );
+  }
+
+  findChannelInviteAddress(channel: PublicKey, invitee: PublicKey): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from(this.INVITE_SEED), channel.toBuffer(), invitee.toBuffer()],
+      this.program.programId
+    );
+  }
+
+  findRemovalRecordAddress(channel: PublicKey, participant: PublicKey): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from(this.REMOVAL_RECORD_SEED), channel.toBuffer(), participant.toBuffer()],
+      this.program.programId
+    );
+  }
+
+  findGroupMessageAddress(
+    channel: PublicKey,
+    sender: PublicKey,
+    messageId: string
+  ): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from(this.GROUP_MESSAGE_SEED), channel.toBuffer(), sender.toBuffer(), Buffer.from(messageId)],
+      this.program.programId
+    );
+  }
+
+  findSenderStateAddress(channel: PublicKey, sender: PublicKey): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from(this.SENDER_STATE_SEED), channel.toBuffer(), sender.toBuffer()],
+      this.program.programId
+    );
+  }
+
   findShieldedBalanceAddress(owner: PublicKey, mint: PublicKey): [PublicKey, number] {
     return PublicKey.findProgramAddressSync(
       [Buffer.from(this.BALANCE_SEED), owner.toBuffer(), mint.toBuffer()],
@@ -288,11 +843,38 @@ export class OCPClient {
     );
   }
 
+  findAllowedShieldMintAddress(mint: PublicKey): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from(this.SHIELD_MINT_SEED), mint.toBuffer()],
+      this.program.programId
+    );
+  }
+
+  findAgentRegistrantAddress(owner: PublicKey): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from(this.REGISTRANT_SEED), owner.toBuffer()],
+      this.program.programId
+    );
+  }
+
   async getProtocolConfigAddress(): Promise<PublicKey> {
     const [protocolConfig] = this.findProtocolConfigAddress();
     return protocolConfig;
   }
 
+  findChannelPolicyAddress(authority: PublicKey, policyId: string): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(
+      [Buffer.from(this.CHANNEL_POLICY_SEED), authority.toBuffer(), Buffer.from(policyId)],
+      this.program.programId
+    );
+  }
+
+  // The policy the program enforces on channel creation, under the protocol authority
+  async getChannelCreationPolicyAddress(): Promise<PublicKey> {
+    const config = await this.program.account.protocolConfig.fetch(await this.getProtocolConfigAddress());
+    return this.findChannelPolicyAddress(config.authority, this.CHANNEL_CREATION_POLICY_ID)[0];
+  }
+
   generateEncryptionKeypair(): Keypair {
     return Keypair.generate();
   }
@@ -2,8 +2,11 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program, Address } from "@coral-xyz/anchor";
 import { OpenclawPrivacyProtocol } from "../target/types/openclaw_privacy_protocol";
 import { expect } from "chai";
-import { PublicKey, Keypair, SystemProgram } from "@solana/web3.js";
-import { OCPClient } from "../src";
+import { PublicKey, Keypair, SystemProgram, Ed25519Program, Secp256k1Program } from "@solana/web3.js";
+import { keccak_256 } from "@noble/hashes/sha3";
+import * as nacl from "tweetnacl";
+import { TOKEN_PROGRAM_ID, createMint, createAccount, mintTo } from "@solana/spl-token";
+import { OCPClient, ROLE_MEMBER, ROLE_OBSERVER, CONTACT_CHANNEL, COMPRESSION_NONE, COMPRESSION_ZSTD, COMPRESSION_GZIP, METADATA_OPAQUE, METADATA_STRUCTURED_V1, SIG_SCHEME_ED25519, SIG_SCHEME_SECP256K1 } from "../src";
 
 describe("openclaw-privacy-protocol", () => {
   anchor.setProvider(anchor.AnchorProvider.env());
@@ -16,6 +19,165 @@ describe("openclaw-privacy-protocol", () => {
   const owner2 = Keypair.generate();
   const owner3 = Keypair.generate();
 
+  const fundedKeypair = async (): Promise<Keypair> => {
+    const keypair = Keypair.generate();
+    const signature = await provider.connection.requestAirdrop(keypair.publicKey, 1_000_000_000);
+    await provider.connection.confirmTransaction(signature);
+    return keypair;
+  };
+
+  const newMint = async (): Promise<PublicKey> => {
+    const payer = await fundedKeypair();
+    return createMint(provider.connection, payer, payer.publicKey, null, 0);
+  };
+
+  const fetchEvents = async (tx: string): Promise<anchor.Event[]> => {
+    await provider.connection.confirmTransaction(tx, "confirmed");
+    const txData = await provider.connection.getTransaction(tx, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    return Array.from(parser.parseLogs(txData.meta.logMessages));
+  };
+
+  // Dark pool instructions only exist in `--features darkpool` builds and CPI into Inco,
+  // so these tests run only when INCO_PROGRAM_ID points at a deployed Inco program
+  const darkPoolEnabled = (): boolean =>
+    "initializeDarkPool" in program.methods && !!process.env.INCO_PROGRAM_ID;
+  const darkPoolMethods = () => program.methods as any;
+
+  const findDarkPoolAddress = (poolId: string): PublicKey =>
+    PublicKey.findProgramAddressSync([Buffer.from("pool"), Buffer.from(poolId)], program.programId)[0];
+
+  const findPoolCommitment = (pool: PublicKey, nullifier: Uint8Array): PublicKey =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("pool_commitment"), pool.toBuffer(), Buffer.from(nullifier)],
+      program.programId
+    )[0];
+
+  const findPoolRegistration = (pool: PublicKey, agent: PublicKey): PublicKey =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("poolreg"), pool.toBuffer(), agent.toBuffer()],
+      program.programId
+    )[0];
+
+  // Mirrors compute_registration_root: keccak leaves over sorted commitments, odd nodes carried up
+  const registrationRoot = (commitments: PublicKey[]): number[] => {
+    if (commitments.length === 0) return Array(32).fill(0);
+    let level = commitments
+      .map((c) => c.toBuffer())
+      .sort(Buffer.compare)
+      .map((c) => Buffer.from(keccak_256(c)));
+    while (level.length > 1) {
+      const next: Buffer[] = [];
+      for (let i = 0; i < level.length; i += 2) {
+        next.push(i + 1 < level.length ? Buffer.from(keccak_256(Buffer.concat([level[i], level[i + 1]]))) : level[i]);
+      }
+      level = next;
+    }
+    return Array.from(level[0]);
+  };
+
+  const newDarkPool = async (
+    registrationFee = 0,
+    poolId = `pool-${Keypair.generate().publicKey.toBase58().slice(0, 8)}`
+  ): Promise<PublicKey> => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const incoProgram = new PublicKey(process.env.INCO_PROGRAM_ID);
+    const lightSystemProgram = process.env.LIGHT_SYSTEM_PROGRAM_ID
+      ? new PublicKey(process.env.LIGHT_SYSTEM_PROGRAM_ID)
+      : PublicKey.default;
+    await program.methods
+      .setDarkPoolProgramIds(incoProgram, lightSystemProgram)
+      .accounts({ protocolConfig, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    const darkPool = findDarkPoolAddress(poolId);
+    await darkPoolMethods()
+      .initializeDarkPool(poolId, new anchor.BN(1), new anchor.BN(1_000_000), new anchor.BN(registrationFee))
+      .accounts({
+        darkPool,
+        protocolConfig,
+        zkVerificationKey: Keypair.generate().publicKey,
+        mint: await newMint(),
+        incoProgram,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+    return darkPool;
+  };
+
+  const registerToPool = async (darkPool: PublicKey, owner: Keypair, agent: PublicKey, nullifier: Uint8Array) =>
+    darkPoolMethods()
+      .registerToPool(Array.from(nullifier))
+      .accounts({
+        poolRegistration: findPoolRegistration(darkPool, agent),
+        darkPool,
+        agent,
+        agentOwner: owner.publicKey,
+        poolAuthority: authority.publicKey,
+        protocolConfig: await ocpClient.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+
+  const findSpentNullifier = (pool: PublicKey, nullifier: Uint8Array): PublicKey =>
+    PublicKey.findProgramAddressSync(
+      [Buffer.from("nullifier"), pool.toBuffer(), Buffer.from(nullifier)],
+      program.programId
+    )[0];
+
+  // A 256-byte proof the mock verifier accepts: any buffer that isn't a single repeated byte
+  const mockProof = (): Buffer => Buffer.from(Array.from({ length: 256 }, (_, i) => i));
+
+  const darkPoolTransfer = async (
+    darkPool: PublicKey,
+    owner: Keypair,
+    agent: PublicKey,
+    nullifier: Uint8Array,
+    { proof = mockProof(), zkVerificationKey }: { proof?: Buffer; zkVerificationKey?: PublicKey } = {}
+  ) => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const config = await program.account.protocolConfig.fetch(protocolConfig);
+    const pool = await (program.account as any).darkPool.fetch(darkPool);
+    return darkPoolMethods()
+      .darkPoolTransfer(Buffer.from([1, 2, 3, 4]), Array.from(nullifier), Keypair.generate().publicKey, proof)
+      .accounts({
+        darkPool,
+        spentNullifier: findSpentNullifier(darkPool, nullifier),
+        senderRegistration: findPoolRegistration(darkPool, agent),
+        zkVerificationKey: zkVerificationKey ?? pool.verificationKey,
+        lightSystemProgram: (config as any).lightSystemProgramId,
+        incoProgram: (config as any).incoProgramId,
+        protocolConfig,
+        transferAuthority: owner.publicKey,
+        payer: owner.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([owner])
+      .rpc();
+  };
+
+  // Runs one of the pool authority's UpdateDarkPool setters
+  const updateDarkPool = (darkPool: PublicKey, method: string, ...args: any[]) =>
+    darkPoolMethods()[method](...args)
+      .accounts({ darkPool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+  // A fresh pool with one registered agent, ready to transfer
+  const newPoolMember = async (darkPool: PublicKey, name: string) => {
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, name, ocpClient.generateEncryptionKeypair(), []);
+    await registerToPool(darkPool, owner, agent, Keypair.generate().publicKey.toBytes());
+    return { owner, agent };
+  };
+
   before(async () => {
     const airdropSignature = await provider.connection.requestAirdrop(authority.publicKey, 1_000_000_000);
     await provider.connection.confirmTransaction(airdropSignature);
@@ -146,7 +308,7 @@ describe("openclaw-privacy-protocol", () => {
   });
 
   it("Initializes shielded balances", async () => {
-    const mint = Keypair.generate().publicKey;
+    const mint = await newMint();
     const { balance } = await ocpClient.initializeShieldedBalance(owner1, mint);
     
     const balanceData = await ocpClient.getShieldedBalance(balance);
@@ -275,4 +437,3371 @@ describe("openclaw-privacy-protocol", () => {
     }
     expect(nullifiers.size).to.equal(100);
   });
+
+  it("Rejects sends from observers but accepts sends from members", async () => {
+    const creator = await fundedKeypair();
+    const member = await fundedKeypair();
+    const observer = await fundedKeypair();
+    const channelId = "roles-channel";
+
+    const { channel } = await ocpClient.createPrivateChannel(
+      creator,
+      channelId,
+      [member.publicKey, observer.publicKey],
+      new Uint8Array([1, 2, 3]),
+      [0, ROLE_OBSERVER]
+    );
+
+    const channelData = await ocpClient.getChannel(channel);
+    const observerIndex = channelData.participants.findIndex(p => p.equals(observer.publicKey));
+    expect(channelData.roles[observerIndex]).to.equal(ROLE_OBSERVER);
+
+    try {
+      await ocpClient.sendEncryptedMessage(observer, channel, "observer-msg", new Uint8Array([9, 9]), member.publicKey);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InsufficientRole");
+    }
+
+    const tx = await ocpClient.sendEncryptedMessage(member, channel, "member-msg", new Uint8Array([9, 9]), observer.publicKey);
+    expect(tx).to.be.a("string");
+  });
+
+  it("Records the recipient commitment on shielded transfers", async () => {
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const mint = await newMint();
+
+    const { balance: senderBalance } = await ocpClient.initializeShieldedBalance(sender, mint);
+    const { balance: recipientBalance } = await ocpClient.initializeShieldedBalance(recipient, mint);
+    const recipientBefore = await ocpClient.getShieldedBalance(recipientBalance);
+
+    await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+      amountCommitment: ocpClient.createAmountCommitment(10, ocpClient.generateBlindingFactor()),
+      nullifier: ocpClient.generateNullifier(),
+      proof: new Uint8Array(64),
+    });
+
+    const recipientAfter = await ocpClient.getShieldedBalance(recipientBalance);
+    expect(recipientAfter.pendingTransfers[0].toCommitment).to.deep.equal(recipientBefore.commitment);
+  });
+
+  it("Rejects merging shielded balances of different mints", async () => {
+    const owner = await fundedKeypair();
+    const { balance: balanceA } = await ocpClient.initializeShieldedBalance(owner, await newMint());
+    const { balance: balanceB } = await ocpClient.initializeShieldedBalance(owner, await newMint());
+
+    try {
+      await program.methods
+        .mergeShieldedBalances(Buffer.from(new Uint8Array(64)), Array.from(new Uint8Array(32)))
+        .accounts({
+          targetBalance: balanceA,
+          sourceBalance: balanceB,
+          owner: owner.publicKey,
+          protocolConfig: await ocpClient.getProtocolConfigAddress(),
+        })
+        .signers([owner])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("MintMismatch");
+    }
+
+    const balanceData = await ocpClient.getShieldedBalance(balanceB);
+    expect(balanceData.owner.toString()).to.equal(owner.publicKey.toString());
+  });
+
+  it("Adds participants up to the channel account size ceiling", async () => {
+    const creator = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(
+      creator,
+      "c".repeat(32),
+      [Keypair.generate().publicKey],
+      new Uint8Array(512)
+    );
+
+    const MAX_CHANNEL_ACCOUNT_SIZE = 10240;
+    const PARTICIPANT_ENTRY_SIZE = 33;
+    const accountInfo = await provider.connection.getAccountInfo(channel);
+    const ceiling = Math.floor((MAX_CHANNEL_ACCOUNT_SIZE - accountInfo.data.length) / PARTICIPANT_ENTRY_SIZE);
+
+    for (let i = 0; i < ceiling; i++) {
+      await ocpClient.addChannelParticipant(creator, channel, Keypair.generate().publicKey);
+    }
+
+    const channelData = await ocpClient.getChannel(channel);
+    expect(channelData.participants.length).to.equal(2 + ceiling);
+    expect(channelData.roles.length).to.equal(channelData.participants.length);
+
+    try {
+      await ocpClient.addChannelParticipant(creator, channel, Keypair.generate().publicKey);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("AccountSizeLimitReached");
+    }
+  });
+
+  it("Omits participant lists and names from events when minimal events are on", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setMinimalEvents = (minimalEvents: boolean) =>
+      program.methods
+        .setMinimalEvents(minimalEvents)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const fullCreator = await fundedKeypair();
+    const { tx: fullTx } = await ocpClient.createPrivateChannel(
+      fullCreator,
+      "full-events",
+      [Keypair.generate().publicKey],
+      new Uint8Array([1])
+    );
+    const fullEvent = (await fetchEvents(fullTx)).find(e => e.name === "privateChannelCreated");
+    expect(fullEvent.data.participants.length).to.equal(2);
+    expect(fullEvent.data.participantCount).to.equal(2);
+
+    await setMinimalEvents(true);
+    try {
+      const minimalCreator = await fundedKeypair();
+      const { tx: channelTx } = await ocpClient.createPrivateChannel(
+        minimalCreator,
+        "minimal-events",
+        [Keypair.generate().publicKey],
+        new Uint8Array([1])
+      );
+      const channelEvent = (await fetchEvents(channelTx)).find(e => e.name === "privateChannelCreated");
+      expect(channelEvent.data.participants.length).to.equal(0);
+      expect(channelEvent.data.participantCount).to.equal(2);
+
+      const { tx: agentTx } = await ocpClient.registerAgent(
+        minimalCreator,
+        "Hidden-Agent",
+        ocpClient.generateEncryptionKeypair(),
+        []
+      );
+      const agentEvent = (await fetchEvents(agentTx)).find(e => e.name === "agentRegistered");
+      expect(agentEvent.data.name).to.equal("");
+    } finally {
+      await setMinimalEvents(false);
+    }
+  });
+
+  it("Derives channel addresses that match the on-chain PDA", async () => {
+    const creator = await fundedKeypair();
+    const channelId = "deep-link";
+    const [derived] = ocpClient.findChannelAddress(creator.publicKey, channelId);
+
+    const { channel } = await ocpClient.createPrivateChannel(
+      creator,
+      channelId,
+      [Keypair.generate().publicKey],
+      new Uint8Array([1])
+    );
+    expect(channel.toString()).to.equal(derived.toString());
+
+    const tx = await program.methods.resolveChannel().accounts({ channel }).rpc();
+    const event = (await fetchEvents(tx)).find(e => e.name === "channelResolved");
+    expect(event.data.channel.toString()).to.equal(derived.toString());
+    expect(event.data.channelId).to.equal(channelId);
+  });
+
+  it("Rejects capability changes after locking", async () => {
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(
+      owner,
+      "Audited-Agent",
+      ocpClient.generateEncryptionKeypair(),
+      ["trading", "audit"]
+    );
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+
+    await program.methods
+      .lockCapabilities()
+      .accounts({ agent, owner: owner.publicKey, protocolConfig })
+      .signers([owner])
+      .rpc();
+
+    const agentData = await ocpClient.getAgent(agent);
+    expect(agentData.capabilitiesLocked).to.be.true;
+
+    try {
+      await ocpClient.updateAgentCapabilities(owner, agent, ["trading"]);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("CapabilitiesLocked");
+    }
+
+    try {
+      await program.methods
+        .removeCapabilities(["audit"])
+        .accounts({ agent, owner: owner.publicKey, protocolConfig })
+        .signers([owner])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("CapabilitiesLocked");
+    }
+
+    expect((await ocpClient.getAgent(agent)).capabilities).to.deep.equal(["trading", "audit"]);
+  });
+
+  it("Broadcasts a group message to every participant", async () => {
+    const sender = await fundedKeypair();
+    const peerA = Keypair.generate().publicKey;
+    const peerB = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(
+      sender,
+      "group-broadcast",
+      [peerA, peerB],
+      new Uint8Array([1])
+    );
+
+    const { tx, groupMessage } = await ocpClient.broadcastEncryptedMessage(sender, channel, "broadcast-001", [
+      { recipient: peerA, ciphertext: new Uint8Array([1, 2, 3]) },
+      { recipient: peerB, ciphertext: new Uint8Array([4, 5, 6]) },
+    ]);
+
+    const event = (await fetchEvents(tx)).find(e => e.name === "groupMessageSent");
+    expect(event.data.recipientCount).to.equal(2);
+
+    const messageData = await program.account.groupMessage.fetch(groupMessage);
+    expect(messageData.ciphertexts.map(c => c.recipient.toString())).to.deep.equal([peerA.toString(), peerB.toString()]);
+    expect(Array.from(messageData.ciphertexts[1].ciphertext)).to.deep.equal([4, 5, 6]);
+  });
+
+  it("Gates and counts every recipient of a group message", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setQuota = (quota: number, windowSeconds: number) =>
+      program.methods
+        .setMessageQuota(quota, new anchor.BN(windowSeconds))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const sender = await fundedKeypair();
+    const peerA = Keypair.generate().publicKey;
+    const peerB = Keypair.generate().publicKey;
+    const observer = await fundedKeypair();
+    await ocpClient.registerAgent(sender, "Group-Sender-Agent", ocpClient.generateEncryptionKeypair(), ["trading"]);
+    const { channel } = await ocpClient.createPrivateChannel(
+      sender,
+      "group-gated",
+      [peerA, peerB, observer.publicKey],
+      new Uint8Array([1]),
+      [ROLE_MEMBER, ROLE_MEMBER, ROLE_OBSERVER]
+    );
+    const slices = (recipients: PublicKey[]) =>
+      recipients.map(recipient => ({ recipient, ciphertext: nacl.randomBytes(48) }));
+
+    // Observers can no more broadcast than send
+    try {
+      await ocpClient.broadcastEncryptedMessage(observer, channel, "from-observer", slices([peerA]));
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InsufficientRole");
+    }
+
+    await setQuota(2, 3600);
+    try {
+      try {
+        await ocpClient.broadcastEncryptedMessage(sender, channel, "over-quota", slices([peerA, peerB, observer.publicKey]));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("MessageQuotaExceeded");
+      }
+
+      await ocpClient.broadcastEncryptedMessage(sender, channel, "within-quota", slices([peerA, peerB]));
+      const [senderAgent] = ocpClient.findAgentAddress(sender.publicKey);
+      expect((await program.account.agent.fetch(senderAgent)).messagesInWindow).to.equal(2);
+      try {
+        await ocpClient.sendEncryptedMessage(sender, channel, "after-broadcast", nacl.randomBytes(48), peerA);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("MessageQuotaExceeded");
+      }
+    } finally {
+      await setQuota(0, 0);
+    }
+  });
+
+  it("Restricts shielded balances to allowlisted mints", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const owner = await fundedKeypair();
+    const allowed = await newMint();
+    const disallowed = await newMint();
+    const [allowedMint] = ocpClient.findAllowedShieldMintAddress(allowed);
+
+    await program.methods
+      .addShieldMint()
+      .accounts({
+        allowedMint,
+        mint: allowed,
+        protocolConfig,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+    await program.methods
+      .setRestrictShieldMints(true)
+      .accounts({ protocolConfig, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    try {
+      const { balance } = await ocpClient.initializeShieldedBalance(owner, allowed);
+      expect((await ocpClient.getShieldedBalance(balance)).mint.toString()).to.equal(allowed.toString());
+
+      try {
+        await ocpClient.initializeShieldedBalance(owner, disallowed);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("MintNotAllowed");
+      }
+    } finally {
+      await program.methods
+        .setRestrictShieldMints(false)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    }
+  });
+
+  it("Stamps messages with slot and per-sender sequence for strict ordering", async () => {
+    const sender = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(sender, "ordering", [peer], new Uint8Array([1]));
+
+    await ocpClient.sendEncryptedMessage(sender, channel, "order-1", new Uint8Array([1]), peer);
+    await ocpClient.sendEncryptedMessage(sender, channel, "order-2", new Uint8Array([2]), peer);
+
+    const [first] = ocpClient.findMessageAddress(channel, sender.publicKey, "order-1");
+    const [second] = ocpClient.findMessageAddress(channel, sender.publicKey, "order-2");
+    const firstData = await ocpClient.getMessage(first);
+    const secondData = await ocpClient.getMessage(second);
+
+    expect(firstData.sequence.toNumber()).to.equal(0);
+    expect(secondData.sequence.toNumber()).to.equal(1);
+    expect(secondData.slot.toNumber()).to.be.at.least(firstData.slot.toNumber());
+
+    const ordered = [secondData, firstData].sort((a, b) =>
+      a.slot.cmp(b.slot) || a.sequence.cmp(b.sequence)
+    );
+    expect(ordered[0].messageId).to.equal("order-1");
+    expect(ordered[1].messageId).to.equal("order-2");
+  });
+
+  it("Lets the authority force-close an orphaned channel", async () => {
+    const lostCreator = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(
+      lostCreator,
+      "orphaned",
+      [Keypair.generate().publicKey],
+      new Uint8Array([1])
+    );
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const configBefore = await program.account.protocolConfig.fetch(protocolConfig);
+
+    const tx = await program.methods
+      .forceCloseChannel()
+      .accounts({ channel, protocolConfig, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    expect((await ocpClient.getChannel(channel)).isActive).to.be.false;
+    const configAfter = await program.account.protocolConfig.fetch(protocolConfig);
+    expect(configAfter.totalChannels.toNumber()).to.equal(configBefore.totalChannels.toNumber() - 1);
+    const event = (await fetchEvents(tx)).find(e => e.name === "channelForceClosed");
+    expect(event.data.creator.toString()).to.equal(lostCreator.publicKey.toString());
+
+    try {
+      await program.methods
+        .forceCloseChannel()
+        .accounts({ channel, protocolConfig, authority: lostCreator.publicKey })
+        .signers([lostCreator])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Unauthorized");
+    }
+  });
+
+  it("Creates direct channels with sorted fixed participants", async () => {
+    const creator = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const configBefore = await program.account.protocolConfig.fetch(protocolConfig);
+
+    const { channel } = await ocpClient.createDirectChannel(creator, "dm-1", peer, new Uint8Array([7, 7]));
+
+    const channelData = await program.account.privateChannel.fetch(channel);
+    const expected = [creator.publicKey, peer].sort((a, b) => a.toBuffer().compare(b.toBuffer()));
+    expect(channelData.participants.map(p => p.toString())).to.deep.equal(expected.map(p => p.toString()));
+    expect(channelData.creator.toString()).to.equal(creator.publicKey.toString());
+    expect(channelData.encryptedMetadata).to.deep.equal([7, 7]);
+    expect(channelData.metadataHash).to.deep.equal(Array.from(keccak_256(new Uint8Array([7, 7]))));
+    expect(channelData.isActive).to.be.true;
+    expect(channelData.isDirect).to.be.true;
+    expect(channelData.archived).to.be.false;
+    const creatorIndex = expected.findIndex(p => p.equals(creator.publicKey));
+    expect(channelData.roles[creatorIndex]).to.equal(1);
+    expect(channelData.roles[1 - creatorIndex]).to.equal(0);
+
+    const configAfter = await program.account.protocolConfig.fetch(protocolConfig);
+    expect(configAfter.totalChannels.toNumber()).to.equal(configBefore.totalChannels.toNumber() + 1);
+    for (const member of [creator.publicKey, peer]) {
+      const [count] = ocpClient.findParticipantChannelCountAddress(member);
+      expect((await program.account.participantChannelCount.fetch(count)).channelCount.toNumber()).to.be.greaterThan(0);
+    }
+
+    // A direct channel is a regular channel for messaging, but its membership is fixed
+    await ocpClient.sendEncryptedMessage(creator, channel, "dm-hello", nacl.randomBytes(48), peer);
+    try {
+      await ocpClient.addChannelParticipant(creator, channel, Keypair.generate().publicKey);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("DirectChannelMembershipFixed");
+    }
+  });
+
+  it("Rejects direct channels with the creator as peer", async () => {
+    const creator = await fundedKeypair();
+    try {
+      await ocpClient.createDirectChannel(creator, "dm-self", creator.publicKey, new Uint8Array([1]));
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidParticipants");
+    }
+  });
+
+  it("Rejects shielded transfers to an uninitialized balance", async () => {
+    const sender = await fundedKeypair();
+    const mint = await newMint();
+    const { balance: senderBalance } = await ocpClient.initializeShieldedBalance(sender, mint);
+
+    // Allocate a zeroed account of the right size without initializing it
+    const uninitialized = Keypair.generate();
+    const space = (await provider.connection.getAccountInfo(senderBalance)).data.length;
+    const createIx = SystemProgram.createAccount({
+      fromPubkey: sender.publicKey,
+      newAccountPubkey: uninitialized.publicKey,
+      lamports: await provider.connection.getMinimumBalanceForRentExemption(space),
+      space,
+      programId: program.programId,
+    });
+    await provider.sendAndConfirm(new anchor.web3.Transaction().add(createIx), [sender, uninitialized]);
+
+    try {
+      await ocpClient.executeShieldedTransfer(sender, senderBalance, uninitialized.publicKey, {
+        amountCommitment: new Uint8Array(32).fill(1),
+        nullifier: ocpClient.generateNullifier(),
+        proof: new Uint8Array(64),
+      });
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.not.include("Should have thrown error");
+    }
+
+    const senderData = await ocpClient.getShieldedBalance(senderBalance);
+    expect(senderData.nonce.toNumber()).to.equal(0);
+  });
+
+  it("Rejects direct messages to agents that disabled DMs", async () => {
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(
+      recipient,
+      "Channel-Only",
+      ocpClient.generateEncryptionKeypair(),
+      []
+    );
+    await program.methods
+      .setContactPrefs(CONTACT_CHANNEL)
+      .accounts({ agent, owner: recipient.publicKey, protocolConfig: await ocpClient.getProtocolConfigAddress() })
+      .signers([recipient])
+      .rpc();
+
+    const { channel: dm } = await ocpClient.createPrivateChannel(sender, "prefs-dm", [recipient.publicKey], new Uint8Array([1]));
+    try {
+      await ocpClient.sendEncryptedMessage(sender, dm, "dm-1", new Uint8Array([1]), recipient.publicKey);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("ContactMethodNotAccepted");
+    }
+
+    const { channel: group } = await ocpClient.createPrivateChannel(
+      sender,
+      "prefs-group",
+      [recipient.publicKey, Keypair.generate().publicKey],
+      new Uint8Array([1])
+    );
+    const tx = await ocpClient.sendEncryptedMessage(sender, group, "group-1", new Uint8Array([1]), recipient.publicKey);
+    expect(tx).to.be.a("string");
+  });
+
+  it("Enforces the minimum channel lifetime before close", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setMinLifetime = (seconds: number) =>
+      program.methods
+        .setMinChannelLifetime(new anchor.BN(seconds))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const creator = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(
+      creator,
+      "short-lived",
+      [Keypair.generate().publicKey],
+      new Uint8Array([1])
+    );
+
+    await setMinLifetime(3);
+    try {
+      try {
+        await ocpClient.closePrivateChannel(creator, channel);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ChannelTooYoung");
+      }
+
+      await new Promise(resolve => setTimeout(resolve, 4000));
+      await ocpClient.closePrivateChannel(creator, channel);
+      expect((await ocpClient.getChannel(channel)).isActive).to.be.false;
+    } finally {
+      await setMinLifetime(0);
+    }
+  });
+
+  it("Tracks a keccak256 hash of channel metadata across updates", async () => {
+    const creator = await fundedKeypair();
+    const metadata = new Uint8Array([1, 2, 3, 4]);
+    const { channel } = await ocpClient.createPrivateChannel(
+      creator,
+      "hashed-metadata",
+      [Keypair.generate().publicKey],
+      metadata
+    );
+
+    let channelData = await ocpClient.getChannel(channel);
+    expect(channelData.metadataHash).to.deep.equal(Array.from(keccak_256(metadata)));
+    await program.methods.verifyMetadataIntegrity().accounts({ channel }).rpc();
+
+    const updated = new Uint8Array(64).fill(9);
+    await program.methods
+      .updateChannelMetadata(Buffer.from(updated))
+      .accounts({
+        channel,
+        creator: creator.publicKey,
+        protocolConfig: await ocpClient.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([creator])
+      .rpc();
+
+    channelData = await ocpClient.getChannel(channel);
+    expect(channelData.encryptedMetadata).to.deep.equal(Array.from(updated));
+    expect(channelData.metadataHash).to.deep.equal(Array.from(keccak_256(updated)));
+    await program.methods.verifyMetadataIntegrity().accounts({ channel }).rpc();
+  });
+
+  it("Emits the expected shielded balance nonce after a transfer", async () => {
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const mint = await newMint();
+    const { balance: senderBalance } = await ocpClient.initializeShieldedBalance(sender, mint);
+    const { balance: recipientBalance } = await ocpClient.initializeShieldedBalance(recipient, mint);
+
+    await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+      amountCommitment: new Uint8Array(32).fill(3),
+      nullifier: ocpClient.generateNullifier(),
+      proof: new Uint8Array(64),
+    });
+
+    const tx = await program.methods.getExpectedNonce().accounts({ shieldedBalance: senderBalance }).rpc();
+    const event = (await fetchEvents(tx)).find(e => e.name === "balanceNonce");
+    const balanceData = await ocpClient.getShieldedBalance(senderBalance);
+    expect(event.data.nonce.toNumber()).to.equal(balanceData.nonce.toNumber());
+    expect(event.data.nonce.toNumber()).to.equal(1);
+  });
+
+  it("Detects a sender reusing an encryption nonce", async () => {
+    const sender = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(sender, "nonce-reuse", [peer], new Uint8Array([1]));
+    const nonce = new Uint8Array(24).fill(5);
+
+    await ocpClient.sendEncryptedMessage(sender, channel, "nonce-1", new Uint8Array([1]), peer, { nonce });
+    const [message] = ocpClient.findMessageAddress(channel, sender.publicKey, "nonce-1");
+    expect((await ocpClient.getMessage(message)).nonce).to.deep.equal(Array.from(nonce));
+
+    try {
+      await ocpClient.sendEncryptedMessage(sender, channel, "nonce-2", new Uint8Array([2]), peer, { nonce });
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("NonceReused");
+    }
+  });
+
+  it("Reports missed acknowledgement deadlines but not met ones", async () => {
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(sender, "ack-sla", [recipient.publicKey], new Uint8Array([1]));
+    const deadline = Math.floor(Date.now() / 1000) + 3;
+
+    await ocpClient.sendEncryptedMessage(sender, channel, "ack-met", new Uint8Array([1]), recipient.publicKey, { requireAckBy: deadline });
+    await ocpClient.sendEncryptedMessage(sender, channel, "ack-missed", new Uint8Array([2]), recipient.publicKey, { requireAckBy: deadline });
+    const [met] = ocpClient.findMessageAddress(channel, sender.publicKey, "ack-met");
+    const [missed] = ocpClient.findMessageAddress(channel, sender.publicKey, "ack-missed");
+
+    await ocpClient.acknowledgeMessage(recipient, met);
+    await new Promise(resolve => setTimeout(resolve, 5000));
+
+    const metTx = await program.methods.checkAckDeadline().accounts({ message: met }).rpc();
+    expect((await fetchEvents(metTx)).find(e => e.name === "ackDeadlineMissed")).to.be.undefined;
+
+    const missedTx = await program.methods.checkAckDeadline().accounts({ message: missed }).rpc();
+    const event = (await fetchEvents(missedTx)).find(e => e.name === "ackDeadlineMissed");
+    expect(event.data.message.toString()).to.equal(missed.toString());
+    expect(event.data.requireAckBy.toNumber()).to.equal(deadline);
+  });
+
+  it("Rejects devnet token transfers below the dust threshold", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const sender = await fundedKeypair();
+    const recipient = Keypair.generate();
+    const mint = await createMint(provider.connection, sender, sender.publicKey, null, 0);
+    const senderTokenAccount = await createAccount(provider.connection, sender, mint, sender.publicKey);
+    const recipientTokenAccount = await createAccount(provider.connection, sender, mint, recipient.publicKey);
+    await mintTo(provider.connection, sender, mint, senderTokenAccount, sender, 1_000);
+
+    const setMinTokenTransfer = (amount: number) =>
+      program.methods
+        .setMinTokenTransfer(new anchor.BN(amount))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const send = (amount: number) =>
+      program.methods
+        .sendDevnetTokens(new anchor.BN(amount), null)
+        .accounts({
+          senderTokenAccount,
+          recipientTokenAccount,
+          mint,
+          sender: sender.publicKey,
+          protocolConfig,
+          transferIdempotency: null,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([sender])
+        .rpc();
+
+    await setMinTokenTransfer(10);
+    try {
+      try {
+        await send(9);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("AmountBelowDust");
+      }
+      await send(10);
+      await send(11);
+    } finally {
+      await setMinTokenTransfer(0);
+    }
+
+    const recipientData = await provider.connection.getTokenAccountBalance(recipientTokenAccount);
+    expect(recipientData.value.amount).to.equal("21");
+  });
+
+  it("Derives message addresses that match the on-chain PDA", async () => {
+    const sender = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(sender, "message-pda", [peer], new Uint8Array([1]));
+    const messageId = "lookup-1";
+
+    await ocpClient.sendEncryptedMessage(sender, channel, messageId, new Uint8Array([1]), peer);
+    const [derived] = ocpClient.findMessageAddress(channel, sender.publicKey, messageId);
+
+    const tx = await program.methods.resolveMessage().accounts({ message: derived }).rpc();
+    const event = (await fetchEvents(tx)).find(e => e.name === "messageResolved");
+    expect(event.data.message.toString()).to.equal(derived.toString());
+    expect(event.data.messageId).to.equal(messageId);
+  });
+
+  it("Keeps an ordered reputation history per agent", async () => {
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Rep-Agent", ocpClient.generateEncryptionKeypair(), []);
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const [reputationLog] = PublicKey.findProgramAddressSync(
+      [Buffer.from("reputation_log"), agent.toBuffer()],
+      program.programId
+    );
+
+    const adjustments = [[10, 1], [-4, 2], [7, 3]];
+    for (const [delta, reasonCode] of adjustments) {
+      await program.methods
+        .adjustReputation(new anchor.BN(delta), reasonCode)
+        .accounts({
+          agent,
+          reputationLog,
+          protocolConfig,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+    }
+
+    expect((await ocpClient.getAgent(agent)).reputationScore.toNumber()).to.equal(13);
+
+    const tx = await program.methods.emitReputationHistory().accounts({ reputationLog }).rpc();
+    const event = (await fetchEvents(tx)).find(e => e.name === "reputationHistory");
+    expect(event.data.entries.map(e => [e.delta.toNumber(), e.reasonCode])).to.deep.equal(adjustments);
+  });
+
+  it("Lets a co-admin pause the protocol but not manage admins", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const admin = await fundedKeypair();
+
+    await program.methods
+      .addAdmin(admin.publicKey)
+      .accounts({ protocolConfig, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    try {
+      await program.methods
+        .setProtocolPause(true)
+        .accounts({ protocolConfig, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      expect((await program.account.protocolConfig.fetch(protocolConfig)).paused).to.be.true;
+
+      await program.methods
+        .setProtocolPause(false)
+        .accounts({ protocolConfig, authority: admin.publicKey })
+        .signers([admin])
+        .rpc();
+
+      try {
+        await program.methods
+          .addAdmin(Keypair.generate().publicKey)
+          .accounts({ protocolConfig, authority: admin.publicKey })
+          .signers([admin])
+          .rpc();
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    } finally {
+      await program.methods
+        .removeAdmin(admin.publicKey)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    }
+
+    const config = await program.account.protocolConfig.fetch(protocolConfig);
+    expect(config.paused).to.be.false;
+    expect(config.admins).to.be.empty;
+  });
+
+  it("Emits ShieldedToPublic on withdraw but not on shielded transfers", async () => {
+    const owner = await fundedKeypair();
+    const peer = await fundedKeypair();
+    const mint = await createMint(provider.connection, owner, owner.publicKey, null, 0);
+    const { balance } = await ocpClient.initializeShieldedBalance(owner, mint);
+    const { balance: peerBalance } = await ocpClient.initializeShieldedBalance(peer, mint);
+    const vault = await createAccount(provider.connection, owner, mint, balance, Keypair.generate());
+    const destination = await createAccount(provider.connection, owner, mint, owner.publicKey);
+    await mintTo(provider.connection, owner, mint, vault, owner, 500);
+
+    const transferTx = await ocpClient.executeShieldedTransfer(owner, balance, peerBalance, {
+      amountCommitment: new Uint8Array(32).fill(1),
+      nullifier: ocpClient.generateNullifier(),
+      proof: new Uint8Array(64),
+    });
+    expect((await fetchEvents(transferTx)).find(e => e.name === "shieldedToPublic")).to.be.undefined;
+
+    const withdrawTx = await program.methods
+      .withdrawShielded(new anchor.BN(200), Array.from(new Uint8Array(32).fill(2)), Buffer.from(new Uint8Array(64)))
+      .accounts({
+        shieldedBalance: balance,
+        vault,
+        destination,
+        owner: owner.publicKey,
+        protocolConfig: await ocpClient.getProtocolConfigAddress(),
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([owner])
+      .rpc();
+
+    const event = (await fetchEvents(withdrawTx)).find(e => e.name === "shieldedToPublic");
+    expect(event.data.owner.toString()).to.equal(owner.publicKey.toString());
+    expect(event.data.mint.toString()).to.equal(mint.toString());
+    expect(event.data.destination.toString()).to.equal(destination.toString());
+    expect(event.data.amount.toNumber()).to.equal(200);
+    expect(Number((await provider.connection.getTokenAccountBalance(destination)).value.amount)).to.equal(200);
+  });
+
+  it("Enforces the cooldown between encryption key rotations", async () => {
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Rotating-Agent", ocpClient.generateEncryptionKeypair(), []);
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+
+    const setCooldown = (seconds: number) =>
+      program.methods
+        .setKeyRotationCooldown(new anchor.BN(seconds))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const rotate = (fill: number) =>
+      program.methods
+        .rotateEncryptionKey(Array.from(new Uint8Array(32).fill(fill)))
+        .accounts({ agent, owner: owner.publicKey, protocolConfig })
+        .signers([owner])
+        .rpc();
+
+    await setCooldown(2);
+    try {
+      await rotate(1);
+      try {
+        await rotate(2);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("RotationTooSoon");
+      }
+
+      await new Promise(resolve => setTimeout(resolve, 3000));
+      await rotate(3);
+      expect((await ocpClient.getAgent(agent)).encryptionPubkey).to.deep.equal(Array.from(new Uint8Array(32).fill(3)));
+    } finally {
+      await setCooldown(0);
+    }
+  });
+
+  it("Lets a relayer pay rent while the owner authorizes", async () => {
+    const relayer = await fundedKeypair();
+    const owner = Keypair.generate();
+    const peer = Keypair.generate().publicKey;
+
+    const { agent } = await ocpClient.registerAgent(owner, "Gasless-Agent", ocpClient.generateEncryptionKeypair(), [], relayer);
+    expect((await ocpClient.getAgent(agent)).owner.toString()).to.equal(owner.publicKey.toString());
+    expect(await provider.connection.getBalance(owner.publicKey)).to.equal(0);
+
+    const { channel } = await ocpClient.createPrivateChannel(relayer, "relayed", [owner.publicKey, peer], new Uint8Array([1]));
+    await ocpClient.sendEncryptedMessage(owner, channel, "relayed-1", new Uint8Array([1]), peer, { feePayer: relayer });
+    const [message] = ocpClient.findMessageAddress(channel, owner.publicKey, "relayed-1");
+    expect((await ocpClient.getMessage(message)).sender.toString()).to.equal(owner.publicKey.toString());
+    expect(await provider.connection.getBalance(owner.publicKey)).to.equal(0);
+
+    try {
+      await program.methods
+        .registerAgent("Unsigned-Agent", Array.from(new Uint8Array(32)), [])
+        .accounts({
+          agent: ocpClient.findAgentAddress(peer)[0],
+          owner: peer,
+          feePayer: relayer.publicKey,
+          protocolConfig: await ocpClient.getProtocolConfigAddress(),
+          agentRegistrant: null,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([relayer])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Signature verification failed");
+    }
+  });
+
+  it("Pads pending transfers with dummies that settlement ignores", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setPadPending = (padPending: boolean) =>
+      program.methods
+        .setPadPending(padPending)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const mint = await newMint();
+    const { balance: senderBalance } = await ocpClient.initializeShieldedBalance(sender, mint);
+    const { balance: recipientBalance } = await ocpClient.initializeShieldedBalance(recipient, mint);
+
+    await setPadPending(true);
+    try {
+      await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+        amountCommitment: new Uint8Array(32).fill(4),
+        nullifier: ocpClient.generateNullifier(),
+        proof: new Uint8Array(64),
+      });
+
+      const padded = (await ocpClient.getShieldedBalance(recipientBalance)).pendingTransfers;
+      expect(padded).to.have.length(16);
+      expect(padded.filter(r => !r.isDummy)).to.have.length(1);
+
+      const tx = await program.methods
+        .settleShieldedBalance(Array.from(new Uint8Array(32).fill(5)), Buffer.from(new Uint8Array(64)))
+        .accounts({ shieldedBalance: recipientBalance, owner: recipient.publicKey, protocolConfig })
+        .signers([recipient])
+        .rpc();
+      const event = (await fetchEvents(tx)).find(e => e.name === "shieldedBalanceSettled");
+      expect(event.data.settledCount.toNumber()).to.equal(1);
+
+      const settled = (await ocpClient.getShieldedBalance(recipientBalance)).pendingTransfers;
+      expect(settled).to.have.length(16);
+      expect(settled.every(r => r.isDummy)).to.be.true;
+    } finally {
+      await setPadPending(false);
+    }
+  });
+
+  it("Caps how many channels a participant can be added to", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setMaxChannels = (max: number) =>
+      program.methods
+        .setMaxChannelsPerParticipant(new anchor.BN(max))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const target = Keypair.generate().publicKey;
+    const creator = await fundedKeypair();
+
+    await setMaxChannels(2);
+    try {
+      await ocpClient.createPrivateChannel(creator, "cap-1", [target], new Uint8Array([1]));
+      const { channel } = await ocpClient.createPrivateChannel(creator, "cap-2", [Keypair.generate().publicKey], new Uint8Array([1]));
+      await ocpClient.addChannelParticipant(creator, channel, target);
+
+      const [countAddress] = ocpClient.findParticipantChannelCountAddress(target);
+      const count = await program.account.participantChannelCount.fetch(countAddress);
+      expect(count.channelCount.toNumber()).to.equal(2);
+
+      try {
+        await ocpClient.createPrivateChannel(await fundedKeypair(), "cap-3", [target], new Uint8Array([1]));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ParticipantChannelLimitReached");
+      }
+
+      const other = await fundedKeypair();
+      const { channel: otherChannel } = await ocpClient.createPrivateChannel(other, "cap-4", [Keypair.generate().publicKey], new Uint8Array([1]));
+      try {
+        await ocpClient.addChannelParticipant(other, otherChannel, target);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ParticipantChannelLimitReached");
+      }
+    } finally {
+      await setMaxChannels(0);
+    }
+  });
+
+  it("Creates a participant's channel count even when its address was pre-funded", async () => {
+    const target = await fundedKeypair();
+    const [countAddress] = ocpClient.findParticipantChannelCountAddress(target.publicKey);
+
+    // Anyone can send lamports to the PDA before it exists
+    const griefer = await fundedKeypair();
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        SystemProgram.transfer({ fromPubkey: griefer.publicKey, toPubkey: countAddress, lamports: 1 })
+      ),
+      [griefer]
+    );
+
+    const creator = await fundedKeypair();
+    await ocpClient.createPrivateChannel(creator, "prefunded-count", [target.publicKey], new Uint8Array([1]));
+    expect((await program.account.participantChannelCount.fetch(countAddress)).channelCount.toNumber()).to.equal(1);
+
+    // Later memberships, here through an invite, count on the same PDA
+    const { channel } = await ocpClient.createPrivateChannel(creator, "prefunded-invite", [Keypair.generate().publicKey], new Uint8Array([1]));
+    await ocpClient.inviteToChannel(creator, channel, target.publicKey);
+    await ocpClient.acceptChannelInvite(target, channel);
+    expect((await program.account.participantChannelCount.fetch(countAddress)).channelCount.toNumber()).to.equal(2);
+  });
+
+  it("Exports snapshots that mirror agent, channel and balance accounts", async () => {
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Snapshot-Agent", ocpClient.generateEncryptionKeypair(), ["audit"]);
+    const { channel } = await ocpClient.createPrivateChannel(owner, "snapshot", [Keypair.generate().publicKey], new Uint8Array([1, 2]));
+    const { balance } = await ocpClient.initializeShieldedBalance(owner, await newMint());
+
+    const snapshot = async (tx: string, name: string) => {
+      const event = (await fetchEvents(tx)).find(e => e.name === name);
+      return JSON.parse(JSON.stringify(event.data));
+    };
+    const mirror = (account: any) => JSON.parse(JSON.stringify(account));
+
+    const agentSnapshot = await snapshot(
+      await program.methods.exportAgentSnapshot().accounts({ agent }).rpc(),
+      "agentSnapshot"
+    );
+    const { agent: agentKey, ...agentFields } = agentSnapshot;
+    expect(agentKey).to.equal(agent.toString());
+    expect(agentFields).to.deep.equal(mirror(await ocpClient.getAgent(agent)));
+
+    const channelSnapshot = await snapshot(
+      await program.methods.exportChannelSnapshot().accounts({ channel }).rpc(),
+      "channelSnapshot"
+    );
+    const { channel: channelKey, ...channelFields } = channelSnapshot;
+    expect(channelKey).to.equal(channel.toString());
+    expect(channelFields).to.deep.equal(mirror(await program.account.privateChannel.fetch(channel)));
+
+    const balanceSnapshot = await snapshot(
+      await program.methods.exportBalanceSnapshot().accounts({ shieldedBalance: balance }).rpc(),
+      "balanceSnapshot"
+    );
+    const { balanceAccount, ...balanceFields } = balanceSnapshot;
+    expect(balanceAccount).to.equal(balance.toString());
+    expect(balanceFields).to.deep.equal(mirror(await ocpClient.getShieldedBalance(balance)));
+  });
+
+  it("Charges the channel message fee when one is set", async () => {
+    const creator = await fundedKeypair();
+    const sender = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(creator, "paid", [sender.publicKey], new Uint8Array([1]));
+
+    const freeTx = await ocpClient.sendEncryptedMessage(sender, channel, "free-1", new Uint8Array([1]), creator.publicKey);
+    const freeEvent = (await fetchEvents(freeTx)).find(e => e.name === "encryptedMessageSent");
+    expect(freeEvent.data.feePaid.toNumber()).to.equal(0);
+
+    const mint = await createMint(provider.connection, creator, creator.publicKey, null, 0);
+    const senderFeeAccount = await createAccount(provider.connection, sender, mint, sender.publicKey);
+    const creatorFeeAccount = await createAccount(provider.connection, creator, mint, creator.publicKey);
+    await mintTo(provider.connection, creator, mint, senderFeeAccount, creator, 7);
+
+    await program.methods
+      .setChannelMessageFee(new anchor.BN(5), mint)
+      .accounts({ channel, creator: creator.publicKey, protocolConfig: await ocpClient.getProtocolConfigAddress() })
+      .signers([creator])
+      .rpc();
+
+    try {
+      await ocpClient.sendEncryptedMessage(sender, channel, "unpaid", new Uint8Array([2]), creator.publicKey);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("MissingFeeAccounts");
+    }
+
+    const feeOptions = { senderFeeAccount, creatorFeeAccount };
+    const paidTx = await ocpClient.sendEncryptedMessage(sender, channel, "paid-1", new Uint8Array([3]), creator.publicKey, feeOptions);
+    const paidEvent = (await fetchEvents(paidTx)).find(e => e.name === "encryptedMessageSent");
+    expect(paidEvent.data.feePaid.toNumber()).to.equal(5);
+    expect(Number((await provider.connection.getTokenAccountBalance(creatorFeeAccount)).value.amount)).to.equal(5);
+
+    try {
+      await ocpClient.sendEncryptedMessage(sender, channel, "paid-2", new Uint8Array([4]), creator.publicKey, feeOptions);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InsufficientBalance");
+    }
+  });
+
+  it("Rejects a retried devnet token transfer with the same idempotency key", async () => {
+    const sender = await fundedKeypair();
+    const recipient = Keypair.generate();
+    const mint = await createMint(provider.connection, sender, sender.publicKey, null, 0);
+    const senderTokenAccount = await createAccount(provider.connection, sender, mint, sender.publicKey);
+    const recipientTokenAccount = await createAccount(provider.connection, sender, mint, recipient.publicKey);
+    await mintTo(provider.connection, sender, mint, senderTokenAccount, sender, 1_000);
+
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const key = Array.from(Keypair.generate().publicKey.toBytes().slice(0, 16));
+    const [transferIdempotency] = PublicKey.findProgramAddressSync(
+      [Buffer.from("transfer_idempotency"), sender.publicKey.toBuffer(), Buffer.from(key)],
+      program.programId
+    );
+    const send = () =>
+      program.methods
+        .sendDevnetTokens(new anchor.BN(100), key)
+        .accounts({
+          senderTokenAccount,
+          recipientTokenAccount,
+          mint,
+          sender: sender.publicKey,
+          protocolConfig,
+          transferIdempotency,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([sender])
+        .rpc();
+
+    await send();
+    try {
+      await send();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("DuplicateTransfer");
+    }
+
+    expect(Number((await provider.connection.getTokenAccountBalance(recipientTokenAccount)).value.amount)).to.equal(100);
+  });
+
+  it("Lets the authority repair a channel's message count", async () => {
+    const creator = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(creator, "repair", [Keypair.generate().publicKey], new Uint8Array([1]));
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+
+    const tx = await program.methods
+      .repairChannelCount(new anchor.BN(42))
+      .accounts({ channel, protocolConfig, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    expect((await ocpClient.getChannel(channel)).messageCount.toNumber()).to.equal(42);
+    const event = (await fetchEvents(tx)).find(e => e.name === "channelCountRepaired");
+    expect(event.data.previousCount.toNumber()).to.equal(0);
+    expect(event.data.messageCount.toNumber()).to.equal(42);
+
+    try {
+      await program.methods
+        .repairChannelCount(new anchor.BN(0))
+        .accounts({ channel, protocolConfig, authority: creator.publicKey })
+        .signers([creator])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Unauthorized");
+    }
+  });
+
+  it("Enforces capability policies on channel creation", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const channelPolicy = await ocpClient.getChannelCreationPolicyAddress();
+    await program.methods
+      .createChannelPolicy("channel_creation", [["trading"], ["audit", "compliance"]])
+      .accounts({
+        channelPolicy,
+        authority: authority.publicKey,
+        protocolConfig,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    const unqualified = await fundedKeypair();
+    await ocpClient.registerAgent(unqualified, "Unqualified", ocpClient.generateEncryptionKeypair(), ["trading"]);
+    try {
+      const qualified = await fundedKeypair();
+      await ocpClient.registerAgent(qualified, "Qualified", ocpClient.generateEncryptionKeypair(), ["trading", "compliance"]);
+      const { channel } = await ocpClient.createPrivateChannel(
+        qualified,
+        "policy-ok",
+        [Keypair.generate().publicKey],
+        new Uint8Array([1])
+      );
+      expect((await ocpClient.getChannel(channel)).policy.toString()).to.equal(channelPolicy.toString());
+
+      try {
+        await ocpClient.createPrivateChannel(unqualified, "policy-denied", [Keypair.generate().publicKey], new Uint8Array([1]));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("PolicyNotSatisfied");
+      }
+
+      // Leaving the policy or the agent out doesn't get around it
+      const agentless = await fundedKeypair();
+      try {
+        await ocpClient.createPrivateChannel(agentless, "policy-agentless", [Keypair.generate().publicKey], new Uint8Array([1]));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("PolicyNotSatisfied");
+      }
+      const [channelId, peer] = ["policy-omitted", Keypair.generate().publicKey];
+      try {
+        await program.methods
+          .createPrivateChannel(channelId, [peer], Buffer.from([1]), Buffer.from([]), METADATA_OPAQUE)
+          .accounts({
+            channel: ocpClient.findChannelAddress(unqualified.publicKey, channelId)[0],
+            creator: unqualified.publicKey,
+            protocolConfig,
+            channelPolicy: Keypair.generate().publicKey,
+            creatorAgent: null,
+            systemProgram: SystemProgram.programId,
+          })
+          .remainingAccounts(
+            [unqualified.publicKey, peer]
+              .sort((a, b) => Buffer.compare(a.toBuffer(), b.toBuffer()))
+              .map((p) => ({ pubkey: ocpClient.findParticipantChannelCountAddress(p)[0], isSigner: false, isWritable: true }))
+          )
+          .signers([unqualified])
+          .rpc();
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ConstraintSeeds");
+      }
+    } finally {
+      await program.methods
+        .closeChannelPolicy()
+        .accounts({ channelPolicy, authority: authority.publicKey, protocolConfig })
+        .signers([authority])
+        .rpc();
+    }
+
+    // With the policy closed, creation is open again
+    const { channel } = await ocpClient.createPrivateChannel(unqualified, "policy-lifted", [Keypair.generate().publicKey], new Uint8Array([1]));
+    expect((await ocpClient.getChannel(channel)).policy.toString()).to.equal(PublicKey.default.toString());
+  });
+
+  it("Stamps state-changing events with both unix time and slot", async () => {
+    const creator = await fundedKeypair();
+    const { tx } = await ocpClient.createPrivateChannel(creator, "stamped", [Keypair.generate().publicKey], new Uint8Array([1]));
+    const event = (await fetchEvents(tx)).find(e => e.name === "privateChannelCreated");
+
+    const now = Math.floor(Date.now() / 1000);
+    expect(Math.abs(event.data.unixTimestamp.toNumber() - now)).to.be.lessThan(120);
+    const txSlot = (await provider.connection.getTransaction(tx, { commitment: "confirmed", maxSupportedTransactionVersion: 0 })).slot;
+    expect(event.data.slot.toNumber()).to.equal(txSlot);
+  });
+
+  it("Blocks messages to inactive or stale recipients when configured", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setBlockInactive = (block: boolean) =>
+      program.methods
+        .setBlockInactiveRecipients(block)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const setStaleSeconds = (seconds: number) =>
+      program.methods
+        .setHeartbeatStaleSeconds(new anchor.BN(seconds))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const sender = await fundedKeypair();
+    const active = await fundedKeypair();
+    const inactive = await fundedKeypair();
+    const stale = await fundedKeypair();
+    const { agent: inactiveAgent } = await ocpClient.registerAgent(inactive, "Inactive", ocpClient.generateEncryptionKeypair(), []);
+    const { agent: staleAgent } = await ocpClient.registerAgent(stale, "Stale", ocpClient.generateEncryptionKeypair(), []);
+    const { channel } = await ocpClient.createPrivateChannel(
+      sender,
+      "liveness",
+      [active.publicKey, inactive.publicKey, stale.publicKey],
+      new Uint8Array([1])
+    );
+
+    await program.methods
+      .deactivateAgent()
+      .accounts({ agent: inactiveAgent, owner: inactive.publicKey, protocolConfig })
+      .signers([inactive])
+      .rpc();
+
+    await setBlockInactive(true);
+    await setStaleSeconds(2);
+    try {
+      await ocpClient.registerAgent(active, "Active", ocpClient.generateEncryptionKeypair(), []);
+      await ocpClient.sendEncryptedMessage(sender, channel, "to-active", new Uint8Array([1]), active.publicKey);
+
+      try {
+        await ocpClient.sendEncryptedMessage(sender, channel, "to-inactive", new Uint8Array([2]), inactive.publicKey);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("RecipientInactive");
+      }
+
+      await new Promise(resolve => setTimeout(resolve, 3000));
+      try {
+        await ocpClient.sendEncryptedMessage(sender, channel, "to-stale", new Uint8Array([3]), stale.publicKey);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("RecipientInactive");
+      }
+
+      await program.methods
+        .heartbeat()
+        .accounts({ agent: staleAgent, owner: stale.publicKey, protocolConfig })
+        .signers([stale])
+        .rpc();
+      await ocpClient.sendEncryptedMessage(sender, channel, "to-revived", new Uint8Array([4]), stale.publicKey);
+    } finally {
+      await setStaleSeconds(0);
+      await setBlockInactive(false);
+    }
+  });
+
+  it("Enforces the protocol-wide max message size", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setMaxMessageSize = (size: number) =>
+      program.methods
+        .setMaxMessageSize(size)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const sender = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(sender, "sized", [peer], new Uint8Array([1]));
+
+    try {
+      await setMaxMessageSize(8193);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidConfigValue");
+    }
+
+    await setMaxMessageSize(100);
+    try {
+      await ocpClient.sendEncryptedMessage(sender, channel, "at-max", new Uint8Array(100), peer);
+      try {
+        await ocpClient.sendEncryptedMessage(sender, channel, "over-max", new Uint8Array(101), peer);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("MessageTooLarge");
+      }
+    } finally {
+      await setMaxMessageSize(2048);
+    }
+  });
+
+  it("Lets the owner reset a settled balance's nonce", async () => {
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const mint = await newMint();
+    const { balance: senderBalance } = await ocpClient.initializeShieldedBalance(sender, mint);
+    const { balance: recipientBalance } = await ocpClient.initializeShieldedBalance(recipient, mint);
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+
+    await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+      amountCommitment: new Uint8Array(32).fill(6),
+      nullifier: ocpClient.generateNullifier(),
+      proof: new Uint8Array(64),
+    });
+    expect((await ocpClient.getShieldedBalance(senderBalance)).nonce.toNumber()).to.equal(1);
+
+    const tx = await program.methods
+      .resetBalanceNonce(Buffer.from(new Uint8Array(64)))
+      .accounts({ shieldedBalance: senderBalance, owner: sender.publicKey, protocolConfig })
+      .signers([sender])
+      .rpc();
+
+    expect((await ocpClient.getShieldedBalance(senderBalance)).nonce.toNumber()).to.equal(0);
+    const event = (await fetchEvents(tx)).find(e => e.name === "balanceNonceReset");
+    expect(event.data.previousNonce.toNumber()).to.equal(1);
+
+    // The recipient still has an unsettled credit
+    try {
+      await program.methods
+        .resetBalanceNonce(Buffer.from(new Uint8Array(64)))
+        .accounts({ shieldedBalance: recipientBalance, owner: recipient.publicKey, protocolConfig })
+        .signers([recipient])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("PendingTransfersNotSettled");
+    }
+  });
+
+  it("Gives messages sent in one slot strictly increasing global ordinals", async () => {
+    const sender = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(sender, "ordinals", [peer], new Uint8Array([1]));
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const [senderState] = ocpClient.findSenderStateAddress(channel, sender.publicKey);
+
+    // All three sends share one transaction, so they land in the same slot
+    const transaction = new anchor.web3.Transaction();
+    for (const i of [1, 2, 3]) {
+      const messageId = `ordinal-${i}`;
+      transaction.add(
+        await program.methods
+          .sendEncryptedMessage(messageId, Buffer.from([i]), peer, Array.from(new Uint8Array(24).fill(i)), new anchor.BN(0), 0)
+          .accounts({
+            message: ocpClient.findMessageAddress(channel, sender.publicKey, messageId)[0],
+            senderState,
+            recipientAgent: ocpClient.findAgentAddress(peer)[0],
+            senderAgent: null,
+            sender: sender.publicKey,
+            feePayer: sender.publicKey,
+            channel,
+            protocolConfig,
+            senderFeeAccount: null,
+            creatorFeeAccount: null,
+            tokenProgram: null,
+            systemProgram: SystemProgram.programId,
+          })
+          .instruction()
+      );
+    }
+    const tx = await provider.sendAndConfirm(transaction, [sender]);
+
+    const events = (await fetchEvents(tx)).filter(e => e.name === "encryptedMessageSent");
+    expect(events).to.have.length(3);
+    expect(new Set(events.map(e => e.data.slot.toNumber())).size).to.equal(1);
+    const ordinals = events.map(e => e.data.messageSeq.toNumber());
+    expect(ordinals[1]).to.be.greaterThan(ordinals[0]);
+    expect(ordinals[2]).to.be.greaterThan(ordinals[1]);
+  });
+
+  it("Enforces the configurable max capabilities and grows agents to fit", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setMaxCapabilities = (max: number) =>
+      program.methods
+        .setMaxCapabilities(max)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const capabilities = (n: number) => Array.from({ length: n }, (_, i) => `cap-${i}`);
+
+    try {
+      await setMaxCapabilities(33);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidConfigValue");
+    }
+
+    await setMaxCapabilities(12);
+    try {
+      const atMax = await fundedKeypair();
+      await ocpClient.registerAgent(atMax, "AtMax", Keypair.generate(), capabilities(12));
+
+      const overMax = await fundedKeypair();
+      try {
+        await ocpClient.registerAgent(overMax, "OverMax", Keypair.generate(), capabilities(13));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("TooManyCapabilities");
+      }
+
+      // Registered with one capability, then grown to the configured max
+      const grower = await fundedKeypair();
+      const { agent } = await ocpClient.registerAgent(grower, "Grower", Keypair.generate(), ["one"]);
+      await ocpClient.updateAgentCapabilities(grower, agent, capabilities(12));
+      const agentAccount = await program.account.agent.fetch(agent);
+      expect(agentAccount.capabilities).to.have.lengthOf(12);
+
+      try {
+        await ocpClient.updateAgentCapabilities(grower, agent, capabilities(13));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("TooManyCapabilities");
+      }
+    } finally {
+      await setMaxCapabilities(10);
+    }
+  });
+
+  it("Pauses messaging alone while other modules keep working", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const MODULE_MESSAGING = 1 << 0;
+    const setModulePause = (flag: number, paused: boolean) =>
+      program.methods
+        .setModulePause(flag, paused)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const sender = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+
+    await setModulePause(MODULE_MESSAGING, true);
+    try {
+      expect((await program.account.protocolConfig.fetch(protocolConfig)).modulePauseFlags).to.equal(MODULE_MESSAGING);
+
+      // Agent registration and channels are unaffected
+      await ocpClient.registerAgent(sender, "ModulePaused", Keypair.generate(), ["messaging"]);
+      const { channel } = await ocpClient.createPrivateChannel(sender, "module-paused", [peer], new Uint8Array([1]));
+
+      try {
+        await ocpClient.sendEncryptedMessage(sender, channel, "paused-msg", new Uint8Array([1]), peer);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ModulePaused");
+      }
+
+      await setModulePause(MODULE_MESSAGING, false);
+      await ocpClient.sendEncryptedMessage(sender, channel, "resumed-msg", new Uint8Array([1]), peer);
+    } finally {
+      await setModulePause(MODULE_MESSAGING, false);
+    }
+  });
+
+  it("Requires senders to meet the reputation floor", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setMinReputation = (min: anchor.BN) =>
+      program.methods
+        .setMinReputationToSend(min)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const trusted = await fundedKeypair();
+    const penalized = await fundedKeypair();
+    const { agent: trustedAgent } = await ocpClient.registerAgent(trusted, "Trusted", Keypair.generate(), ["messaging"]);
+    const { agent: penalizedAgent } = await ocpClient.registerAgent(penalized, "Penalized", Keypair.generate(), ["messaging"]);
+    for (const [agent, delta] of [[trustedAgent, 10], [penalizedAgent, -10]] as [PublicKey, number][]) {
+      const [reputationLog] = PublicKey.findProgramAddressSync(
+        [Buffer.from("reputation_log"), agent.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .adjustReputation(new anchor.BN(delta), 1)
+        .accounts({
+          agent,
+          reputationLog,
+          protocolConfig,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+    }
+    const { channel } = await ocpClient.createPrivateChannel(trusted, "high-trust", [penalized.publicKey], new Uint8Array([1]));
+
+    await setMinReputation(new anchor.BN(0));
+    try {
+      await ocpClient.sendEncryptedMessage(trusted, channel, "above-floor", new Uint8Array([1]), penalized.publicKey);
+      try {
+        await ocpClient.sendEncryptedMessage(penalized, channel, "below-floor", new Uint8Array([1]), trusted.publicKey);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ReputationTooLow");
+      }
+    } finally {
+      await setMinReputation(new anchor.BN("-9223372036854775808"));
+    }
+  });
+
+  it("Creates a channel and sends its first message in one instruction", async () => {
+    const creator = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const content = new Uint8Array([7, 7, 7]);
+
+    const { tx, channel, message } = await ocpClient.createChannelAndSend(
+      creator,
+      "opened-with-message",
+      [peer],
+      new Uint8Array([1]),
+      "first",
+      content,
+      peer
+    );
+
+    const channelData = await program.account.privateChannel.fetch(channel);
+    expect(channelData.participants.map(p => p.toString())).to.include(peer.toString());
+    const messageData = await program.account.encryptedMessage.fetch(message);
+    expect(messageData.channel.toString()).to.equal(channel.toString());
+    expect(messageData.encryptedContent).to.deep.equal(Array.from(content));
+    expect(messageData.sequence.toNumber()).to.equal(0);
+
+    const events = (await fetchEvents(tx)).map(e => e.name);
+    expect(events).to.include("privateChannelCreated");
+    expect(events).to.include("encryptedMessageSent");
+
+    // The sender's sequence continues from the first message
+    await ocpClient.sendEncryptedMessage(creator, channel, "second", new Uint8Array([8]), peer);
+    const [second] = ocpClient.findMessageAddress(channel, creator.publicKey, "second");
+    expect((await program.account.encryptedMessage.fetch(second)).sequence.toNumber()).to.equal(1);
+  });
+
+  it("Applies the send gates, quota and plaintext flagging to a channel's first message", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setQuota = (quota: number, windowSeconds: number) =>
+      program.methods
+        .setMessageQuota(quota, new anchor.BN(windowSeconds))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const creator = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    await ocpClient.registerAgent(creator, "First-Message-Agent", ocpClient.generateEncryptionKeypair(), ["trading"]);
+    const [creatorAgent] = ocpClient.findAgentAddress(creator.publicKey);
+
+    // The recipient must be a member, exactly as for send_encrypted_message
+    try {
+      await ocpClient.createChannelAndSend(
+        creator, "first-to-outsider", [peer], new Uint8Array([1]), "first", nacl.randomBytes(48), Keypair.generate().publicKey
+      );
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidRecipient");
+    }
+
+    await setQuota(1, 3600);
+    try {
+      // Too short to be ciphertext, so flagged while still delivered
+      const { tx, channel } = await ocpClient.createChannelAndSend(
+        creator, "first-counted", [peer], new Uint8Array([1]), "first", new Uint8Array([7, 7, 7]), peer
+      );
+      expect((await fetchEvents(tx)).map(e => e.name)).to.include("suspiciousMessageDetected");
+      const agent = await program.account.agent.fetch(creatorAgent);
+      expect(agent.suspiciousMessages).to.equal(1);
+      expect(agent.messagesInWindow).to.equal(1);
+
+      try {
+        await ocpClient.sendEncryptedMessage(creator, channel, "second", nacl.randomBytes(48), peer);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("MessageQuotaExceeded");
+      }
+    } finally {
+      await setQuota(0, 0);
+    }
+  });
+
+  it("Settles a subset of pending transfers by nullifier", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const mint = await newMint();
+    const { balance: senderBalance } = await ocpClient.initializeShieldedBalance(sender, mint);
+    const { balance: recipientBalance } = await ocpClient.initializeShieldedBalance(recipient, mint);
+
+    const nullifiers = [ocpClient.generateNullifier(), ocpClient.generateNullifier(), ocpClient.generateNullifier()];
+    for (const [i, nullifier] of nullifiers.entries()) {
+      await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+        amountCommitment: new Uint8Array(32).fill(i + 1),
+        nullifier,
+        proof: new Uint8Array(64),
+      });
+    }
+
+    const settleSubset = (subset: Uint8Array[], commitmentByte: number) =>
+      program.methods
+        .settlePendingSubset(
+          subset.map(n => Array.from(n)),
+          Array.from(new Uint8Array(32).fill(commitmentByte)),
+          Buffer.from(new Uint8Array(64))
+        )
+        .accounts({ shieldedBalance: recipientBalance, owner: recipient.publicKey, protocolConfig })
+        .signers([recipient])
+        .rpc();
+
+    try {
+      await settleSubset([ocpClient.generateNullifier()], 8);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("PendingNotFound");
+    }
+
+    const tx = await settleSubset(nullifiers.slice(0, 2), 9);
+    const event = (await fetchEvents(tx)).find(e => e.name === "shieldedBalanceSettled");
+    expect(event.data.settledCount.toNumber()).to.equal(2);
+
+    const balance = await ocpClient.getShieldedBalance(recipientBalance);
+    expect(balance.commitment).to.deep.equal(Array.from(new Uint8Array(32).fill(9)));
+    expect(balance.pendingTransfers).to.have.length(1);
+    expect(balance.pendingTransfers[0].nullifier).to.deep.equal(Array.from(nullifiers[2]));
+
+    // The remaining record carries over and settles against the new commitment
+    await settleSubset([nullifiers[2]], 10);
+    expect((await ocpClient.getShieldedBalance(recipientBalance)).pendingTransfers).to.have.length(0);
+  });
+
+  it("Matches the highest-reputation qualifying agent for a task", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const capability = `routing-${Date.now()}`;
+    const candidates = [
+      { name: "Low", capabilities: [capability], reputation: 5 },
+      { name: "High-No-Cap", capabilities: ["other"], reputation: 50 },
+      { name: "High", capabilities: [capability], reputation: 20 },
+    ];
+
+    const agents: PublicKey[] = [];
+    for (const candidate of candidates) {
+      const owner = await fundedKeypair();
+      const { agent } = await ocpClient.registerAgent(owner, candidate.name, Keypair.generate(), candidate.capabilities);
+      const [reputationLog] = PublicKey.findProgramAddressSync(
+        [Buffer.from("reputation_log"), agent.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .adjustReputation(new anchor.BN(candidate.reputation), 1)
+        .accounts({
+          agent,
+          reputationLog,
+          protocolConfig,
+          authority: authority.publicKey,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([authority])
+        .rpc();
+      agents.push(agent);
+    }
+    const remainingAccounts = agents.map(pubkey => ({ pubkey, isSigner: false, isWritable: false }));
+
+    const tx = await program.methods
+      .matchAgentForTask(capability, new anchor.BN(10))
+      .remainingAccounts(remainingAccounts)
+      .rpc();
+    const event = (await fetchEvents(tx)).find(e => e.name === "agentMatched");
+    expect(event.data.agent.toString()).to.equal(agents[2].toString());
+    expect(event.data.reputation.toNumber()).to.equal(20);
+
+    try {
+      await program.methods
+        .matchAgentForTask(capability, new anchor.BN(100))
+        .remainingAccounts(remainingAccounts)
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("NoMatchingAgent");
+    }
+  });
+
+  it("Requires encrypted metadata at channel creation when configured", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setRequireMetadata = (requireMetadata: boolean) =>
+      program.methods
+        .setRequireMetadata(requireMetadata)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const creator = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+
+    await setRequireMetadata(true);
+    try {
+      try {
+        await ocpClient.createPrivateChannel(creator, "no-metadata", [peer], new Uint8Array([]));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("EncryptedMetadataRequired");
+      }
+
+      const { channel } = await ocpClient.createPrivateChannel(creator, "with-metadata", [peer], new Uint8Array([1, 2]));
+      expect((await program.account.privateChannel.fetch(channel)).encryptedMetadata).to.deep.equal([1, 2]);
+    } finally {
+      await setRequireMetadata(false);
+    }
+  });
+
+  it("Attests and revokes an agent capability", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const owner = await fundedKeypair();
+    const attester = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Attested", Keypair.generate(), ["benchmarked"]);
+    const [attestation] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("attest"),
+        agent.toBuffer(),
+        attester.publicKey.toBuffer(),
+        Buffer.from(keccak_256(Buffer.from("benchmarked"))),
+      ],
+      program.programId
+    );
+
+    const tx = await program.methods
+      .attestCapability("benchmarked")
+      .accounts({
+        attestation,
+        agent,
+        attester: attester.publicKey,
+        protocolConfig,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([attester])
+      .rpc();
+    const attestationData = await program.account.capabilityAttestation.fetch(attestation);
+    expect(attestationData.agent.toString()).to.equal(agent.toString());
+    expect(attestationData.attester.toString()).to.equal(attester.publicKey.toString());
+    expect(attestationData.capability).to.equal("benchmarked");
+    expect((await fetchEvents(tx)).map(e => e.name)).to.include("capabilityAttested");
+
+    // Only the attester can revoke
+    try {
+      await program.methods
+        .revokeAttestation()
+        .accounts({ attestation, attester: owner.publicKey })
+        .signers([owner])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Unauthorized");
+    }
+
+    await program.methods
+      .revokeAttestation()
+      .accounts({ attestation, attester: attester.publicKey })
+      .signers([attester])
+      .rpc();
+    expect(await provider.connection.getAccountInfo(attestation)).to.be.null;
+  });
+
+  it("Restricts agent registration to allowlisted owners", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setRestrictRegistration = (restrictRegistration: boolean) =>
+      program.methods
+        .setRestrictRegistration(restrictRegistration)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const member = await fundedKeypair();
+    const outsider = await fundedKeypair();
+    const [agentRegistrant] = ocpClient.findAgentRegistrantAddress(member.publicKey);
+    await program.methods
+      .addAgentRegistrant()
+      .accounts({
+        agentRegistrant,
+        owner: member.publicKey,
+        protocolConfig,
+        authority: authority.publicKey,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([authority])
+      .rpc();
+
+    await setRestrictRegistration(true);
+    try {
+      const { agent } = await ocpClient.registerAgent(member, "Consortium-Member", Keypair.generate(), []);
+      expect((await ocpClient.getAgent(agent)).owner.toString()).to.equal(member.publicKey.toString());
+
+      try {
+        await ocpClient.registerAgent(outsider, "Outsider", Keypair.generate(), []);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("RegistrationNotAllowed");
+      }
+    } finally {
+      await setRestrictRegistration(false);
+      await program.methods
+        .removeAgentRegistrant()
+        .accounts({ agentRegistrant, protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    }
+  });
+
+  it("Stamps messages with a channel-wide sequence across senders", async () => {
+    const alice = await fundedKeypair();
+    const bob = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(alice, "channel-sequence", [bob.publicKey], new Uint8Array([1]));
+
+    const sends: [Keypair, PublicKey, string][] = [
+      [alice, bob.publicKey, "seq-a1"],
+      [bob, alice.publicKey, "seq-b1"],
+      [alice, bob.publicKey, "seq-a2"],
+    ];
+    const channelSequences: number[] = [];
+    for (const [sender, recipient, messageId] of sends) {
+      await ocpClient.sendEncryptedMessage(sender, channel, messageId, new Uint8Array([1]), recipient);
+      const [message] = ocpClient.findMessageAddress(channel, sender.publicKey, messageId);
+      channelSequences.push((await program.account.encryptedMessage.fetch(message)).channelSequence.toNumber());
+    }
+
+    expect(channelSequences).to.deep.equal([0, 1, 2]);
+    expect((await program.account.privateChannel.fetch(channel)).nextSequence.toNumber()).to.equal(3);
+  });
+
+  it("Rewards reputation on task completion when configured", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setReputationPerTask = (reward: number) =>
+      program.methods
+        .setReputationPerTask(new anchor.BN(reward))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const completeTask = (agent: PublicKey) =>
+      program.methods
+        .completeTask()
+        .accounts({ agent, protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Worker", Keypair.generate(), ["tasks"]);
+
+    // Off by default: the task counts but reputation is unchanged
+    await completeTask(agent);
+    let agentData = await ocpClient.getAgent(agent);
+    expect(agentData.totalTasksCompleted.toNumber()).to.equal(1);
+    expect(agentData.reputationScore.toNumber()).to.equal(0);
+
+    await setReputationPerTask(3);
+    try {
+      const tx = await completeTask(agent);
+      agentData = await ocpClient.getAgent(agent);
+      expect(agentData.totalTasksCompleted.toNumber()).to.equal(2);
+      expect(agentData.reputationScore.toNumber()).to.equal(3);
+      const event = (await fetchEvents(tx)).find(e => e.name === "taskCompleted");
+      expect(event.data.newScore.toNumber()).to.equal(3);
+
+      try {
+        await program.methods
+          .completeTask()
+          .accounts({ agent, protocolConfig, authority: owner.publicKey })
+          .signers([owner])
+          .rpc();
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("Unauthorized");
+      }
+    } finally {
+      await setReputationPerTask(0);
+    }
+  });
+
+  it("Rejects reinitializing the protocol with a domain error", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+
+    for (const signer of [authority, await fundedKeypair()]) {
+      try {
+        await ocpClient.initializeProtocol(signer);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("AlreadyInitialized");
+      }
+    }
+
+    const config = await program.account.protocolConfig.fetch(protocolConfig);
+    expect(config.authority.toString()).to.equal(authority.publicKey.toString());
+  });
+
+  it("Round-trips the compression tag and rejects unknown ones", async () => {
+    const alice = await fundedKeypair();
+    const bob = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(alice, "compression", [bob.publicKey], new Uint8Array([1]));
+
+    for (const compression of [COMPRESSION_NONE, COMPRESSION_ZSTD, COMPRESSION_GZIP]) {
+      const messageId = `compressed-${compression}`;
+      const tx = await ocpClient.sendEncryptedMessage(alice, channel, messageId, new Uint8Array([1]), bob.publicKey, {
+        compression,
+      });
+      const [message] = ocpClient.findMessageAddress(channel, alice.publicKey, messageId);
+      expect((await program.account.encryptedMessage.fetch(message)).compression).to.equal(compression);
+
+      const event = (await fetchEvents(tx)).find(e => e.name === "encryptedMessageSent");
+      expect(event.data.compression).to.equal(compression);
+    }
+
+    try {
+      await ocpClient.sendEncryptedMessage(alice, channel, "compressed-bad", new Uint8Array([1]), bob.publicKey, {
+        compression: 3,
+      });
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("UnsupportedCompression");
+    }
+  });
+
+  it("Recovers agent ownership through the recovery key after the delay", async () => {
+    const owner = await fundedKeypair();
+    const recoveryKey = await fundedKeypair();
+    const newOwner = await fundedKeypair();
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    await ocpClient.registerAgent(owner, "Recoverable", ocpClient.generateEncryptionKeypair(), []);
+    const [agent] = ocpClient.findAgentAddress(owner.publicKey);
+
+    await program.methods
+      .setRecoveryKey(recoveryKey.publicKey, new anchor.BN(2))
+      .accounts({ agent, owner: owner.publicKey, protocolConfig })
+      .signers([owner])
+      .rpc();
+
+    const initiateTx = await program.methods
+      .initiateRecovery(newOwner.publicKey)
+      .accounts({ agent, recoveryKey: recoveryKey.publicKey, protocolConfig })
+      .signers([recoveryKey])
+      .rpc();
+    const initiated = (await fetchEvents(initiateTx)).find(e => e.name === "recoveryInitiated");
+    expect(initiated.data.newOwner.toString()).to.equal(newOwner.publicKey.toString());
+
+    const completeRecovery = () =>
+      program.methods
+        .completeRecovery()
+        .accounts({ agent, recoveryKey: recoveryKey.publicKey, protocolConfig })
+        .signers([recoveryKey])
+        .rpc();
+
+    try {
+      await completeRecovery();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("RecoveryDelayNotElapsed");
+    }
+
+    await new Promise(resolve => setTimeout(resolve, 3000));
+    const completeTx = await completeRecovery();
+    const completed = (await fetchEvents(completeTx)).find(e => e.name === "recoveryCompleted");
+    expect(completed.data.previousOwner.toString()).to.equal(owner.publicKey.toString());
+
+    const agentData = await program.account.agent.fetch(agent);
+    expect(agentData.owner.toString()).to.equal(newOwner.publicKey.toString());
+    expect(agentData.recoveryInitiatedAt.toNumber()).to.equal(0);
+
+    // The new owner now controls the agent and the old one no longer does
+    await program.methods
+      .heartbeat()
+      .accounts({ agent, owner: newOwner.publicKey, protocolConfig })
+      .signers([newOwner])
+      .rpc();
+    try {
+      await program.methods
+        .heartbeat()
+        .accounts({ agent, owner: owner.publicKey, protocolConfig })
+        .signers([owner])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Unauthorized");
+    }
+  });
+
+  it("Lets the owner cancel a pending recovery", async () => {
+    const owner = await fundedKeypair();
+    const recoveryKey = await fundedKeypair();
+    const attacker = await fundedKeypair();
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    await ocpClient.registerAgent(owner, "Guarded", ocpClient.generateEncryptionKeypair(), []);
+    const [agent] = ocpClient.findAgentAddress(owner.publicKey);
+
+    await program.methods
+      .setRecoveryKey(recoveryKey.publicKey, new anchor.BN(3600))
+      .accounts({ agent, owner: owner.publicKey, protocolConfig })
+      .signers([owner])
+      .rpc();
+    await program.methods
+      .initiateRecovery(attacker.publicKey)
+      .accounts({ agent, recoveryKey: recoveryKey.publicKey, protocolConfig })
+      .signers([recoveryKey])
+      .rpc();
+
+    const cancelTx = await program.methods
+      .cancelRecovery()
+      .accounts({ agent, owner: owner.publicKey, protocolConfig })
+      .signers([owner])
+      .rpc();
+    const cancelled = (await fetchEvents(cancelTx)).find(e => e.name === "recoveryCancelled");
+    expect(cancelled.data.cancelledOwner.toString()).to.equal(attacker.publicKey.toString());
+
+    try {
+      await program.methods
+        .completeRecovery()
+        .accounts({ agent, recoveryKey: recoveryKey.publicKey, protocolConfig })
+        .signers([recoveryKey])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("NoPendingRecovery");
+    }
+
+    const agentData = await program.account.agent.fetch(agent);
+    expect(agentData.owner.toString()).to.equal(owner.publicKey.toString());
+  });
+
+  it("Stamps messages with a timestamp no earlier than the channel", async () => {
+    const alice = await fundedKeypair();
+    const bob = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(alice, "monotonic", [bob.publicKey], new Uint8Array([1]));
+    await ocpClient.sendEncryptedMessage(alice, channel, "monotonic-1", new Uint8Array([1]), bob.publicKey);
+
+    const [message] = ocpClient.findMessageAddress(channel, alice.publicKey, "monotonic-1");
+    const messageData = await program.account.encryptedMessage.fetch(message);
+    const channelData = await program.account.privateChannel.fetch(channel);
+    expect(messageData.timestamp.toNumber()).to.be.at.least(channelData.createdAt.toNumber());
+  });
+
+  it("Records the metadata format tag and checks the structured-v1 header", async () => {
+    const creator = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+
+    const opaque = await ocpClient.createPrivateChannel(creator, "format-opaque", [peer], new Uint8Array([9]));
+    expect((await program.account.privateChannel.fetch(opaque.channel)).metadataFormat).to.equal(METADATA_OPAQUE);
+    const opaqueEvent = (await fetchEvents(opaque.tx)).find(e => e.name === "privateChannelCreated");
+    expect(opaqueEvent.data.metadataFormat).to.equal(METADATA_OPAQUE);
+
+    const structuredMetadata = new Uint8Array([METADATA_STRUCTURED_V1, 1, 7, 7, 7]);
+    const structured = await ocpClient.createPrivateChannel(
+      creator, "format-structured", [peer], structuredMetadata, [], METADATA_STRUCTURED_V1
+    );
+    expect((await program.account.privateChannel.fetch(structured.channel)).metadataFormat).to.equal(METADATA_STRUCTURED_V1);
+    const structuredEvent = (await fetchEvents(structured.tx)).find(e => e.name === "privateChannelCreated");
+    expect(structuredEvent.data.metadataFormat).to.equal(METADATA_STRUCTURED_V1);
+
+    for (const [channelId, metadata] of [
+      ["format-short", new Uint8Array([METADATA_STRUCTURED_V1])],
+      ["format-bad-tag", new Uint8Array([0, 1, 7])],
+    ] as [string, Uint8Array][]) {
+      try {
+        await ocpClient.createPrivateChannel(creator, channelId, [peer], metadata, [], METADATA_STRUCTURED_V1);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("InvalidMetadataFormat");
+      }
+    }
+  });
+
+  it("Summarizes a balance's pending transfers", async () => {
+    const alice = await fundedKeypair();
+    const bob = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const mint = await newMint();
+    const { balance: aliceBalance } = await ocpClient.initializeShieldedBalance(alice, mint);
+    const { balance: bobBalance } = await ocpClient.initializeShieldedBalance(bob, mint);
+    const { balance: recipientBalance } = await ocpClient.initializeShieldedBalance(recipient, mint);
+
+    const summarize = async () => {
+      const tx = await program.methods.emitPendingSummary().accounts({ shieldedBalance: recipientBalance }).rpc();
+      return (await fetchEvents(tx)).find(e => e.name === "pendingSummary");
+    };
+
+    const empty = await summarize();
+    expect(empty.data.pendingCount).to.equal(0);
+    expect(empty.data.earliestTimestamp.toNumber()).to.equal(0);
+
+    for (const [i, [sender, senderBalance]] of ([
+      [alice, aliceBalance],
+      [bob, bobBalance],
+      [alice, aliceBalance],
+    ] as [Keypair, PublicKey][]).entries()) {
+      await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+        amountCommitment: new Uint8Array(32).fill(i + 1),
+        nullifier: ocpClient.generateNullifier(),
+        proof: new Uint8Array(64),
+      });
+    }
+
+    const summary = await summarize();
+    const pending = (await ocpClient.getShieldedBalance(recipientBalance)).pendingTransfers;
+    const timestamps = pending.map(record => record.timestamp.toNumber());
+    expect(summary.data.pendingCount).to.equal(3);
+    expect(summary.data.recordCount).to.equal(pending.length);
+    expect(summary.data.senders.map(s => s.toString()).sort()).to.deep.equal(
+      [alice.publicKey.toString(), bob.publicKey.toString()].sort()
+    );
+    expect(summary.data.earliestTimestamp.toNumber()).to.equal(Math.min(...timestamps));
+    expect(summary.data.latestTimestamp.toNumber()).to.equal(Math.max(...timestamps));
+  });
+
+  it("Lets the creator cancel a pending channel invite but not an accepted one", async () => {
+    const creator = await fundedKeypair();
+    const invitee = await fundedKeypair();
+    const joiner = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(creator, "invites", [Keypair.generate().publicKey], new Uint8Array([1]));
+
+    const { invite } = await ocpClient.inviteToChannel(creator, channel, invitee.publicKey);
+    const balanceBefore = await provider.connection.getBalance(creator.publicKey);
+    const tx = await ocpClient.cancelChannelInvite(creator, channel, invitee.publicKey);
+
+    const event = (await fetchEvents(tx)).find(e => e.name === "channelInviteCancelled");
+    expect(event.data.invitee.toString()).to.equal(invitee.publicKey.toString());
+    expect(await provider.connection.getAccountInfo(invite)).to.be.null;
+    expect(await provider.connection.getBalance(creator.publicKey)).to.be.greaterThan(balanceBefore);
+    try {
+      await ocpClient.acceptChannelInvite(invitee, channel);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("AccountNotInitialized");
+    }
+
+    const { invite: joinerInvite } = await ocpClient.inviteToChannel(creator, channel, joiner.publicKey);
+    await ocpClient.acceptChannelInvite(joiner, channel);
+    const participants = (await ocpClient.getChannel(channel)).participants.map(p => p.toString());
+    expect(participants).to.include(joiner.publicKey.toString());
+
+    // Accepting closes the invite, so there is nothing left to cancel
+    expect(await provider.connection.getAccountInfo(joinerInvite)).to.be.null;
+    try {
+      await ocpClient.cancelChannelInvite(creator, channel, joiner.publicKey);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("AccountNotInitialized");
+    }
+
+    // Membership is what marks an invite accepted, however the invitee got in
+    const added = await fundedKeypair();
+    await ocpClient.inviteToChannel(creator, channel, added.publicKey);
+    await ocpClient.addChannelParticipant(creator, channel, added.publicKey);
+    try {
+      await ocpClient.cancelChannelInvite(creator, channel, added.publicKey);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InviteAlreadyAccepted");
+    }
+  });
+
+  it("Lets a removed participant be invited again", async () => {
+    const creator = await fundedKeypair();
+    const invitee = await fundedKeypair();
+    const { channel } = await ocpClient.createPrivateChannel(
+      creator,
+      "reinvite",
+      [Keypair.generate().publicKey, Keypair.generate().publicKey],
+      new Uint8Array([1])
+    );
+
+    await ocpClient.inviteToChannel(creator, channel, invitee.publicKey);
+    await ocpClient.acceptChannelInvite(invitee, channel);
+    await ocpClient.removeChannelParticipant(creator, channel, invitee.publicKey);
+
+    await ocpClient.inviteToChannel(creator, channel, invitee.publicKey);
+    await ocpClient.acceptChannelInvite(invitee, channel);
+    const participants = (await ocpClient.getChannel(channel)).participants.map(p => p.toString());
+    expect(participants).to.include(invitee.publicKey.toString());
+  });
+
+  it("Enforces the configured minimum channel membership", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setMinParticipants = (minParticipants: number) =>
+      program.methods
+        .setMinParticipants(minParticipants)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const creator = await fundedKeypair();
+    const [first, second] = [Keypair.generate().publicKey, Keypair.generate().publicKey];
+
+    try {
+      await setMinParticipants(1);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidConfigValue");
+    }
+
+    await setMinParticipants(3);
+    try {
+      try {
+        await ocpClient.createPrivateChannel(creator, "committee-small", [first], new Uint8Array([1]));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("TooFewParticipants");
+      }
+
+      const { channel } = await ocpClient.createPrivateChannel(creator, "committee", [first, second], new Uint8Array([1]));
+      expect((await ocpClient.getChannel(channel)).participants).to.have.length(3);
+
+      try {
+        await ocpClient.removeChannelParticipant(creator, channel, first);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("TooFewParticipants");
+      }
+    } finally {
+      await setMinParticipants(2);
+    }
+  });
+
+  it("Rejects shielded balances against non-mint accounts", async () => {
+    const owner = await fundedKeypair();
+
+    // Neither an uninitialized key nor a program-owned non-mint account is accepted
+    const { channel } = await ocpClient.createPrivateChannel(owner, "not-a-mint", [Keypair.generate().publicKey], new Uint8Array([1]));
+    for (const notMint of [Keypair.generate().publicKey, channel]) {
+      try {
+        await ocpClient.initializeShieldedBalance(owner, notMint);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("InvalidMint");
+      }
+    }
+
+    const mint = await newMint();
+    const { balance } = await ocpClient.initializeShieldedBalance(owner, mint);
+    expect((await ocpClient.getShieldedBalance(balance)).mint.toString()).to.equal(mint.toString());
+  });
+
+  it("Suppresses per-transfer events when emit_events is off", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setEmitEvents = (emitEvents: boolean) =>
+      program.methods
+        .setEmitEvents(emitEvents)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const mint = await newMint();
+    const { balance: senderBalance } = await ocpClient.initializeShieldedBalance(sender, mint);
+    const { balance: recipientBalance } = await ocpClient.initializeShieldedBalance(recipient, mint);
+    const transfer = () =>
+      ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+        amountCommitment: new Uint8Array(32).fill(3),
+        nullifier: ocpClient.generateNullifier(),
+        proof: new Uint8Array(64),
+      });
+
+    const toggleTx = await setEmitEvents(false);
+    try {
+      // The admin change itself is always logged
+      expect((await fetchEvents(toggleTx)).find(e => e.name === "emitEventsChanged")).to.exist;
+
+      const quietTx = await transfer();
+      expect((await fetchEvents(quietTx)).find(e => e.name === "shieldedTransferExecuted")).to.be.undefined;
+      expect((await ocpClient.getShieldedBalance(recipientBalance)).pendingTransfers).to.have.length(1);
+    } finally {
+      await setEmitEvents(true);
+    }
+
+    const loudTx = await transfer();
+    expect((await fetchEvents(loudTx)).find(e => e.name === "shieldedTransferExecuted")).to.exist;
+  });
+
+  it("Migrates stored bumps for agents, channels and balances", async () => {
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Bump-Agent", ocpClient.generateEncryptionKeypair(), ["migration"]);
+    const { channel } = await ocpClient.createPrivateChannel(owner, "bump-channel", [Keypair.generate().publicKey], new Uint8Array([1]));
+    const { balance } = await ocpClient.initializeShieldedBalance(owner, await newMint());
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const systemProgram = SystemProgram.programId;
+
+    const [, agentBump] = PublicKey.findProgramAddressSync([Buffer.from("agent"), owner.publicKey.toBuffer()], program.programId);
+    const agentTx = await program.methods
+      .migrateAgentBump(owner.publicKey)
+      .accounts({ agent, signer: owner.publicKey, protocolConfig, systemProgram })
+      .signers([owner])
+      .rpc();
+    expect((await ocpClient.getAgent(agent)).bump).to.equal(agentBump);
+    const migrated = (await fetchEvents(agentTx)).find(e => e.name === "accountMigrated");
+    expect(migrated.data.account.toString()).to.equal(agent.toString());
+    expect(migrated.data.bump).to.equal(agentBump);
+
+    // The protocol authority can migrate accounts it does not own
+    await program.methods
+      .migrateChannelBump()
+      .accounts({ channel, signer: authority.publicKey, protocolConfig, systemProgram })
+      .signers([authority])
+      .rpc();
+    const [, channelBump] = PublicKey.findProgramAddressSync(
+      [Buffer.from("channel"), owner.publicKey.toBuffer(), Buffer.from("bump-channel")],
+      program.programId
+    );
+    expect((await ocpClient.getChannel(channel)).bump).to.equal(channelBump);
+
+    await program.methods
+      .migrateBalanceBump()
+      .accounts({ shieldedBalance: balance, signer: owner.publicKey, protocolConfig, systemProgram })
+      .signers([owner])
+      .rpc();
+    const balanceData = await ocpClient.getShieldedBalance(balance);
+    const [, balanceBump] = PublicKey.findProgramAddressSync(
+      [Buffer.from("shielded_balance"), owner.publicKey.toBuffer(), balanceData.mint.toBuffer()],
+      program.programId
+    );
+    expect(balanceData.bump).to.equal(balanceBump);
+
+    const stranger = await fundedKeypair();
+    try {
+      await program.methods
+        .migrateAgentBump(owner.publicKey)
+        .accounts({ agent, signer: stranger.publicKey, protocolConfig, systemProgram })
+        .signers([stranger])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Unauthorized");
+    }
+  });
+
+  it("Rejects a send that follows a close in the same transaction", async () => {
+    const creator = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(creator, "close-then-send", [peer], new Uint8Array([1]));
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+
+    const closeIx = await program.methods
+      .closePrivateChannel()
+      .accounts({ channel, creator: creator.publicKey, protocolConfig })
+      .instruction();
+    const sendIx = await program.methods
+      .sendEncryptedMessage("late-1", Buffer.from([1]), peer, Array.from(new Uint8Array(24)), new anchor.BN(0), 0)
+      .accounts({
+        message: ocpClient.findMessageAddress(channel, creator.publicKey, "late-1")[0],
+        senderState: ocpClient.findSenderStateAddress(channel, creator.publicKey)[0],
+        recipientAgent: ocpClient.findAgentAddress(peer)[0],
+        senderAgent: null,
+        sender: creator.publicKey,
+        feePayer: creator.publicKey,
+        channel,
+        protocolConfig,
+        senderFeeAccount: null,
+        creatorFeeAccount: null,
+        tokenProgram: null,
+        systemProgram: SystemProgram.programId,
+      })
+      .instruction();
+
+    try {
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(closeIx, sendIx), [creator]);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("ChannelInactive");
+    }
+
+    // The whole transaction rolled back, so the channel is still open
+    expect((await ocpClient.getChannel(channel)).isActive).to.equal(true);
+  });
+
+  it("Keeps agents loadable after updating to the maximum capabilities", async () => {
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Full-Agent", ocpClient.generateEncryptionKeypair(), ["one"]);
+    const capabilities = (n: number, width: number) =>
+      Array.from({ length: n }, (_, i) => `cap-${i}-`.padEnd(width, "x"));
+
+    // Ten capabilities of 28 bytes fill the sized allowance exactly
+    await ocpClient.updateAgentCapabilities(owner, agent, capabilities(10, 28));
+    expect((await ocpClient.getAgent(agent)).capabilities).to.deep.equal(capabilities(10, 28));
+    await ocpClient.updateAgentCapabilities(owner, agent, capabilities(10, 20));
+    expect((await ocpClient.getAgent(agent)).capabilities).to.deep.equal(capabilities(10, 20));
+
+    try {
+      await ocpClient.updateAgentCapabilities(owner, agent, capabilities(10, 64));
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("AccountSizeInconsistent");
+    }
+    expect((await ocpClient.getAgent(agent)).capabilities).to.deep.equal(capabilities(10, 20));
+  });
+
+  it("Broadcasts authority notices through an event", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const notice = "Scheduled maintenance at 18:00 UTC; sends may be paused briefly";
+
+    const tx = await program.methods
+      .broadcastNotice(notice)
+      .accounts({ protocolConfig, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    const event = (await fetchEvents(tx)).find(e => e.name === "protocolNotice");
+    expect(event.data.notice).to.equal(notice);
+    expect(event.data.authority.toString()).to.equal(authority.publicKey.toString());
+
+    try {
+      await program.methods
+        .broadcastNotice("x".repeat(257))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("NoticeTooLong");
+    }
+
+    const impostor = await fundedKeypair();
+    try {
+      await program.methods
+        .broadcastNotice("Fake incident")
+        .accounts({ protocolConfig, authority: impostor.publicKey })
+        .signers([impostor])
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Unauthorized");
+    }
+  });
+
+  it("Flags implausibly short ciphertexts against the sender agent", async () => {
+    const sender = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { agent } = await ocpClient.registerAgent(sender, "Plaintext-Agent", ocpClient.generateEncryptionKeypair(), []);
+    const { channel } = await ocpClient.createPrivateChannel(sender, "audit-channel", [peer], new Uint8Array([1]));
+
+    const shortTx = await ocpClient.sendEncryptedMessage(sender, channel, "short-1", new TextEncoder().encode("hi"), peer);
+    const flagged = (await fetchEvents(shortTx)).find(e => e.name === "suspiciousMessageDetected");
+    expect(flagged.data.contentLen).to.equal(2);
+    expect(flagged.data.suspiciousMessages).to.equal(1);
+    expect((await ocpClient.getAgent(agent)).suspiciousMessages).to.equal(1);
+
+    // A real secretbox ciphertext passes the heuristic
+    const key = nacl.randomBytes(nacl.secretbox.keyLength);
+    const nonce = nacl.randomBytes(nacl.secretbox.nonceLength);
+    const sealed = nacl.secretbox(nacl.randomBytes(48), nonce, key);
+    const sealedTx = await ocpClient.sendEncryptedMessage(sender, channel, "sealed-1", sealed, peer, { nonce });
+    expect((await fetchEvents(sealedTx)).find(e => e.name === "suspiciousMessageDetected")).to.be.undefined;
+    expect((await ocpClient.getAgent(agent)).suspiciousMessages).to.equal(1);
+  });
+
+  it("Transfers shielded balance ownership in two steps", async () => {
+    const owner = await fundedKeypair();
+    const newOwner = await fundedKeypair();
+    const peer = await fundedKeypair();
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const mint = await createMint(provider.connection, owner, owner.publicKey, null, 0);
+    const { balance } = await ocpClient.initializeShieldedBalance(owner, mint);
+    const { balance: peerBalance } = await ocpClient.initializeShieldedBalance(peer, mint);
+    const vault = await createAccount(provider.connection, owner, mint, balance, Keypair.generate());
+    await mintTo(provider.connection, owner, mint, vault, owner, 500);
+
+    // An unsettled incoming credit blocks the transfer
+    await ocpClient.executeShieldedTransfer(peer, peerBalance, balance, {
+      amountCommitment: new Uint8Array(32).fill(1),
+      nullifier: ocpClient.generateNullifier(),
+      proof: new Uint8Array(64),
+    });
+    try {
+      await ocpClient.transferShieldedOwnership(owner, balance, newOwner.publicKey);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("PendingTransfersNotSettled");
+    }
+    await program.methods
+      .settleShieldedBalance(Array.from(new Uint8Array(32).fill(2)), Buffer.from(new Uint8Array(64)))
+      .accounts({ shieldedBalance: balance, owner: owner.publicKey, protocolConfig })
+      .signers([owner])
+      .rpc();
+
+    const startTx = await ocpClient.transferShieldedOwnership(owner, balance, newOwner.publicKey);
+    const started = (await fetchEvents(startTx)).find(e => e.name === "shieldedOwnershipTransferStarted");
+    expect(started.data.pendingOwner.toString()).to.equal(newOwner.publicKey.toString());
+
+    try {
+      await ocpClient.acceptShieldedOwnership(peer, balance);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Unauthorized");
+    }
+
+    const acceptTx = await ocpClient.acceptShieldedOwnership(newOwner, balance);
+    const transferred = (await fetchEvents(acceptTx)).find(e => e.name === "shieldedOwnershipTransferred");
+    expect(transferred.data.previousOwner.toString()).to.equal(owner.publicKey.toString());
+    const balanceData = await ocpClient.getShieldedBalance(balance);
+    expect(balanceData.owner.toString()).to.equal(newOwner.publicKey.toString());
+    expect(balanceData.seedOwner.toString()).to.equal(owner.publicKey.toString());
+
+    // The new owner can withdraw from the balance, still at its original address
+    const destination = await createAccount(provider.connection, newOwner, mint, newOwner.publicKey);
+    const withdraw = (signer: Keypair) =>
+      program.methods
+        .withdrawShielded(new anchor.BN(100), Array.from(new Uint8Array(32).fill(3)), Buffer.from(new Uint8Array(64)))
+        .accounts({ shieldedBalance: balance, vault, destination, owner: signer.publicKey, protocolConfig, tokenProgram: TOKEN_PROGRAM_ID })
+        .signers([signer])
+        .rpc();
+    try {
+      await withdraw(owner);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidBalanceOwner");
+    }
+    await withdraw(newOwner);
+    expect(Number((await provider.connection.getTokenAccountBalance(destination)).value.amount)).to.equal(100);
+  });
+
+  it("Reports final channel stats in the close event", async () => {
+    const creator = await fundedKeypair();
+    const peers = [Keypair.generate().publicKey, Keypair.generate().publicKey];
+    const { channel } = await ocpClient.createPrivateChannel(creator, "stats-channel", peers, new Uint8Array([1]));
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+
+    await program.methods
+      .repairChannelCount(new anchor.BN(7))
+      .accounts({ channel, protocolConfig, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    const before = await ocpClient.getChannel(channel);
+
+    const tx = await program.methods
+      .closePrivateChannel()
+      .accounts({ channel, creator: creator.publicKey, protocolConfig })
+      .signers([creator])
+      .rpc();
+    const event = (await fetchEvents(tx)).find(e => e.name === "privateChannelClosed");
+    expect(event.data.messageCount.toNumber()).to.equal(7);
+    expect(event.data.messageCount.toNumber()).to.equal(before.messageCount.toNumber());
+    expect(event.data.participantCount).to.equal(before.participants.length);
+    expect(event.data.createdAt.toNumber()).to.equal(before.createdAt.toNumber());
+  });
+
+  it("Scales the message quota for agents holding the multiplier capability", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setQuota = (quota: number, windowSeconds: number) =>
+      program.methods
+        .setMessageQuota(quota, new anchor.BN(windowSeconds))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const setMultiplier = (multiplier: number) =>
+      program.methods
+        .setRateMultiplier("premium", multiplier)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const standard = await fundedKeypair();
+    const premium = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    await ocpClient.registerAgent(standard, "Standard-Agent", ocpClient.generateEncryptionKeypair(), ["trading"]);
+    await ocpClient.registerAgent(premium, "Premium-Agent", ocpClient.generateEncryptionKeypair(), ["trading", "premium"]);
+    const { channel } = await ocpClient.createPrivateChannel(standard, "quota-channel", [premium.publicKey, peer], new Uint8Array([1]));
+    const send = (sender: Keypair, id: string) =>
+      ocpClient.sendEncryptedMessage(sender, channel, id, nacl.randomBytes(48), peer);
+
+    await setQuota(2, 3600);
+    await setMultiplier(2);
+    try {
+      await send(standard, "standard-1");
+      await send(standard, "standard-2");
+      try {
+        await send(standard, "standard-3");
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("MessageQuotaExceeded");
+      }
+
+      // Twice the base quota for the premium capability
+      for (let i = 1; i <= 4; i++) {
+        await send(premium, `premium-${i}`);
+      }
+      try {
+        await send(premium, "premium-5");
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("MessageQuotaExceeded");
+      }
+    } finally {
+      await setQuota(0, 0);
+      await setMultiplier(1);
+    }
+  });
+
+  it("Enforces the re-add cooldown for removed participants", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setReaddCooldown = (seconds: number) =>
+      program.methods
+        .setReaddCooldown(new anchor.BN(seconds))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const creator = await fundedKeypair();
+    const churner = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(
+      creator,
+      "churn-channel",
+      [churner, Keypair.generate().publicKey],
+      new Uint8Array([1])
+    );
+
+    await setReaddCooldown(2);
+    try {
+      await ocpClient.removeChannelParticipant(creator, channel, churner);
+      const [removalRecord] = ocpClient.findRemovalRecordAddress(channel, churner);
+      expect((await program.account.participantRemovalRecord.fetch(removalRecord)).participant.toString()).to.equal(churner.toString());
+
+      try {
+        await ocpClient.addChannelParticipant(creator, channel, churner);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ReaddTooSoon");
+      }
+
+      await new Promise(resolve => setTimeout(resolve, 3000));
+      await ocpClient.addChannelParticipant(creator, channel, churner);
+      expect((await ocpClient.getChannel(channel)).participants.map(p => p.toString())).to.include(churner.toString());
+    } finally {
+      await setReaddCooldown(0);
+    }
+  });
+
+  it("Asks for a peer when the participant list only names the creator", async () => {
+    const creator = await fundedKeypair();
+    try {
+      await ocpClient.createPrivateChannel(creator, "solo", [creator.publicKey, creator.publicKey], new Uint8Array([1]));
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("NeedAtLeastOnePeer");
+    }
+  });
+
+  it("Freezes archived channels against sends and closes", async () => {
+    const creator = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(creator, "archive-channel", [peer], new Uint8Array([1]));
+    await ocpClient.sendEncryptedMessage(creator, channel, "before-archive", nacl.randomBytes(48), peer);
+
+    const tx = await ocpClient.archiveChannel(creator, channel);
+    const archived = (await fetchEvents(tx)).find(e => e.name === "channelArchived");
+    expect(archived.data.messageCount.toNumber()).to.equal(1);
+    expect((await ocpClient.getChannel(channel)).archived).to.be.true;
+
+    try {
+      await ocpClient.sendEncryptedMessage(creator, channel, "after-archive", nacl.randomBytes(48), peer);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("ChannelArchived");
+    }
+    try {
+      await ocpClient.closePrivateChannel(creator, channel);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("ChannelArchived");
+    }
+    try {
+      await ocpClient.archiveChannel(creator, channel);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("ChannelArchived");
+    }
+
+    // Existing messages remain readable
+    const [message] = ocpClient.findMessageAddress(channel, creator.publicKey, "before-archive");
+    expect((await ocpClient.getMessage(message)).channel.toString()).to.equal(channel.toString());
+  });
+
+  it("Reopens closed channels unless they are archived", async () => {
+    const creator = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { channel } = await ocpClient.createPrivateChannel(creator, "reopen-channel", [peer], new Uint8Array([1]));
+
+    try {
+      await ocpClient.reopenChannel(creator, channel);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("ChannelAlreadyActive");
+    }
+
+    // total_channels counts active channels, so it follows the close and the reopen
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const totalChannels = async () => (await program.account.protocolConfig.fetch(protocolConfig)).totalChannels.toNumber();
+    const before = await totalChannels();
+    await ocpClient.closePrivateChannel(creator, channel);
+    expect(await totalChannels()).to.equal(before - 1);
+    const tx = await ocpClient.reopenChannel(creator, channel);
+    expect((await fetchEvents(tx)).find(e => e.name === "privateChannelReopened")).to.exist;
+    expect((await ocpClient.getChannel(channel)).isActive).to.be.true;
+    expect(await totalChannels()).to.equal(before);
+    await ocpClient.sendEncryptedMessage(creator, channel, "after-reopen", nacl.randomBytes(48), peer);
+
+    // Archiving a closed channel freezes it closed
+    await ocpClient.closePrivateChannel(creator, channel);
+    await ocpClient.archiveChannel(creator, channel);
+    try {
+      await ocpClient.reopenChannel(creator, channel);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("ChannelArchived");
+    }
+    expect((await ocpClient.getChannel(channel)).isActive).to.be.false;
+  });
+
+  it("Authenticates message content signed with ed25519 or secp256k1", async () => {
+    const sender = await fundedKeypair();
+    const peer = Keypair.generate().publicKey;
+    const { agent } = await ocpClient.registerAgent(sender, "Signing-Agent", ocpClient.generateEncryptionKeypair(), []);
+    const { channel } = await ocpClient.createPrivateChannel(sender, "signed-channel", [peer], new Uint8Array([1]));
+    const content = nacl.randomBytes(48);
+    await ocpClient.sendEncryptedMessage(sender, channel, "signed-1", content, peer);
+    const [message] = ocpClient.findMessageAddress(channel, sender.publicKey, "signed-1");
+    const contentHash = keccak_256(content);
+
+    const ed25519Ix = Ed25519Program.createInstructionWithPrivateKey({ privateKey: sender.secretKey, message: contentHash });
+    const tx = await ocpClient.authenticateMessage(message, SIG_SCHEME_ED25519, ed25519Ix);
+    const authenticated = (await fetchEvents(tx)).find(e => e.name === "messageAuthenticated");
+    expect(authenticated.data.sigScheme).to.equal(SIG_SCHEME_ED25519);
+    expect(authenticated.data.sender.toString()).to.equal(sender.publicKey.toString());
+
+    // A valid signature by anyone but the sender doesn't count
+    const impostorIx = Ed25519Program.createInstructionWithPrivateKey({ privateKey: Keypair.generate().secretKey, message: contentHash });
+    try {
+      await ocpClient.authenticateMessage(message, SIG_SCHEME_ED25519, impostorIx);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidContentSignature");
+    }
+
+    const secp256k1Ix = Secp256k1Program.createInstructionWithPrivateKey({
+      privateKey: nacl.randomBytes(32),
+      message: contentHash,
+      instructionIndex: 0,
+    });
+    // The signer's address follows the instruction's 12-byte offsets header
+    const ethAddress = secp256k1Ix.data.subarray(12, 32);
+    try {
+      await ocpClient.authenticateMessage(message, SIG_SCHEME_SECP256K1, secp256k1Ix);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("SigningKeyNotSet");
+    }
+
+    await ocpClient.setSecp256k1Signer(sender, agent, ethAddress);
+    const secpTx = await ocpClient.authenticateMessage(message, SIG_SCHEME_SECP256K1, secp256k1Ix);
+    const secpAuthenticated = (await fetchEvents(secpTx)).find(e => e.name === "messageAuthenticated");
+    expect(secpAuthenticated.data.sigScheme).to.equal(SIG_SCHEME_SECP256K1);
+
+    try {
+      await ocpClient.authenticateMessage(message, 7, ed25519Ix);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("UnsupportedSignatureScheme");
+    }
+  });
+
+  it("Pre-flights sends with the error the send would hit", async () => {
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const creator = await fundedKeypair();
+    const member = await fundedKeypair();
+    const observer = Keypair.generate().publicKey;
+    const picky = await fundedKeypair();
+    const outsider = Keypair.generate().publicKey;
+    const { agent: pickyAgent } = await ocpClient.registerAgent(picky, "Picky-Agent", ocpClient.generateEncryptionKeypair(), []);
+    await program.methods
+      .setContactPrefs(0)
+      .accounts({ agent: pickyAgent, owner: picky.publicKey, protocolConfig })
+      .signers([picky])
+      .rpc();
+    const { channel } = await ocpClient.createPrivateChannel(
+      creator,
+      "preflight-channel",
+      [member.publicKey, observer, picky.publicKey],
+      new Uint8Array([1]),
+      [0, ROLE_OBSERVER, 0]
+    );
+    const expectBlocked = async (sender: PublicKey, recipient: PublicKey, code: string) => {
+      try {
+        await ocpClient.canSend(sender, channel, recipient);
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include(code);
+      }
+    };
+
+    await ocpClient.canSend(creator.publicKey, channel, member.publicKey);
+    await expectBlocked(creator.publicKey, creator.publicKey, "InvalidRecipient");
+    await expectBlocked(creator.publicKey, outsider, "InvalidRecipient");
+    await expectBlocked(outsider, member.publicKey, "NotAParticipant");
+    await expectBlocked(observer, member.publicKey, "InsufficientRole");
+    await expectBlocked(creator.publicKey, picky.publicKey, "ContactMethodNotAccepted");
+
+    const setMinReputation = (min: anchor.BN) =>
+      program.methods
+        .setMinReputationToSend(min)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    await setMinReputation(new anchor.BN(0));
+    try {
+      await expectBlocked(creator.publicKey, member.publicKey, "ReputationTooLow");
+    } finally {
+      await setMinReputation(new anchor.BN("-9223372036854775808"));
+    }
+
+    const setQuota = (quota: number, windowSeconds: number) =>
+      program.methods
+        .setMessageQuota(quota, new anchor.BN(windowSeconds))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    await setQuota(1, 3600);
+    try {
+      await expectBlocked(creator.publicKey, member.publicKey, "MessageQuotaExceeded");
+    } finally {
+      await setQuota(0, 0);
+    }
+
+    const mint = await newMint();
+    await program.methods
+      .setChannelMessageFee(new anchor.BN(5), mint)
+      .accounts({ channel, creator: creator.publicKey, protocolConfig })
+      .signers([creator])
+      .rpc();
+    await expectBlocked(creator.publicKey, member.publicKey, "MissingFeeAccounts");
+
+    await ocpClient.closePrivateChannel(creator, channel);
+    await expectBlocked(creator.publicKey, member.publicKey, "ChannelInactive");
+  });
+
+  it("Rejects shielded transfers built against a stale nonce", async () => {
+    const sender = await fundedKeypair();
+    const recipient = await fundedKeypair();
+    const mint = await newMint();
+    const { balance: senderBalance } = await ocpClient.initializeShieldedBalance(sender, mint);
+    const { balance: recipientBalance } = await ocpClient.initializeShieldedBalance(recipient, mint);
+    const transfer = () => ({
+      amountCommitment: new Uint8Array(32).fill(4),
+      nullifier: ocpClient.generateNullifier(),
+      proof: new Uint8Array(64),
+    });
+
+    await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+      ...transfer(),
+      expectedNonce: new anchor.BN(0),
+    });
+    expect((await ocpClient.getShieldedBalance(senderBalance)).nonce.toNumber()).to.equal(1);
+
+    // Replaying the nonce the first transfer consumed
+    try {
+      await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, {
+        ...transfer(),
+        expectedNonce: new anchor.BN(0),
+      });
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("NonceMismatch");
+    }
+    expect((await ocpClient.getShieldedBalance(senderBalance)).nonce.toNumber()).to.equal(1);
+
+    // Without an expected nonce the transfer goes through as before
+    await ocpClient.executeShieldedTransfer(sender, senderBalance, recipientBalance, transfer());
+    expect((await ocpClient.getShieldedBalance(senderBalance)).nonce.toNumber()).to.equal(2);
+  });
+
+  it("Tracks dark pool registrations under an order-independent registration root", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    
+    const members: { owner: Keypair; agent: PublicKey; nullifier: Uint8Array }[] = [];
+    for (let i = 0; i < 3; i++) {
+      const owner = await fundedKeypair();
+      const { agent } = await ocpClient.registerAgent(owner, `Pool-Member-${i}`, ocpClient.generateEncryptionKeypair(), []);
+      const nullifier = Keypair.generate().publicKey.toBytes();
+      await registerToPool(darkPool, owner, agent, nullifier);
+      members.push({ owner, agent, nullifier });
+    }
+
+    const commitments = members.map((m) => findPoolCommitment(darkPool, m.nullifier));
+    let pool = await (program.account as any).darkPool.fetch(darkPool);
+    expect(pool.registeredCommitments.map((c: PublicKey) => c.toString())).to.deep.equal(
+      commitments.map((c) => c.toString())
+    );
+    expect(pool.registrationRoot).to.deep.equal(registrationRoot(commitments));
+
+    // Leaving shrinks the set and the root follows it
+    const [leaving] = members;
+    await darkPoolMethods()
+      .deregisterFromPool()
+      .accounts({
+        poolRegistration: findPoolRegistration(darkPool, leaving.agent),
+        darkPool,
+        agentOwner: leaving.owner.publicKey,
+      })
+      .signers([leaving.owner])
+      .rpc();
+    pool = await (program.account as any).darkPool.fetch(darkPool);
+    expect(pool.registeredCommitments).to.have.length(2);
+    expect(pool.registrationRoot).to.deep.equal(registrationRoot(commitments.slice(1).reverse()));
+  });
+
+  it("Rejects a dark pool registration that reuses a registered nullifier", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const nullifier = Keypair.generate().publicKey.toBytes();
+    
+    const first = await fundedKeypair();
+    const { agent: firstAgent } = await ocpClient.registerAgent(first, "Pool-First", ocpClient.generateEncryptionKeypair(), []);
+    await registerToPool(darkPool, first, firstAgent, nullifier);
+
+    const second = await fundedKeypair();
+    const { agent: secondAgent } = await ocpClient.registerAgent(second, "Pool-Second", ocpClient.generateEncryptionKeypair(), []);
+    try {
+      await registerToPool(darkPool, second, secondAgent, nullifier);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("DuplicateNullifier");
+    }
+
+    const pool = await (program.account as any).darkPool.fetch(darkPool);
+    expect(pool.registeredCommitments).to.have.length(1);
+    expect(pool.registrationRoot).to.deep.equal(registrationRoot([findPoolCommitment(darkPool, nullifier)]));
+  });
+
+  it("Batch-registers to a dark pool even when the registration PDA was pre-funded", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Pool-Batch", ocpClient.generateEncryptionKeypair(), []);
+    const poolRegistration = findPoolRegistration(darkPool, agent);
+
+    // Anyone can send lamports to the PDA before it exists
+    await provider.sendAndConfirm(
+      new anchor.web3.Transaction().add(
+        SystemProgram.transfer({ fromPubkey: owner.publicKey, toPubkey: poolRegistration, lamports: 1_000_000 })
+      ),
+      [owner]
+    );
+
+    const nullifier = Keypair.generate().publicKey.toBytes();
+    await darkPoolMethods()
+      .registerToPoolBatch([{ agent, nullifier: Array.from(nullifier) }])
+      .accounts({
+        darkPool,
+        agentOwner: owner.publicKey,
+        poolAuthority: authority.publicKey,
+        protocolConfig: await ocpClient.getProtocolConfigAddress(),
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts([
+        { pubkey: agent, isSigner: false, isWritable: false },
+        { pubkey: poolRegistration, isSigner: false, isWritable: true },
+      ])
+      .signers([owner])
+      .rpc();
+
+    const registration = await (program.account as any).poolRegistration.fetch(poolRegistration);
+    expect(registration.agent.toString()).to.equal(agent.toString());
+    expect(registration.isActive).to.be.true;
+    expect((await provider.connection.getAccountInfo(poolRegistration)).owner.toString()).to.equal(
+      program.programId.toString()
+    );
+  });
+
+  it("Keeps dark pools and registrations at canonical addresses, one per id and agent", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const poolId = `canon-${Keypair.generate().publicKey.toBase58().slice(0, 8)}`;
+    const darkPool = await newDarkPool(0, poolId);
+    const [, poolBump] = PublicKey.findProgramAddressSync([Buffer.from("pool"), Buffer.from(poolId)], program.programId);
+    const pool = await (program.account as any).darkPool.fetch(darkPool);
+    expect(pool.poolId).to.equal(poolId);
+    expect(pool.bump).to.equal(poolBump);
+
+    // The id maps to a single address, so a second pool under it can't be created
+    try {
+      await newDarkPool(0, poolId);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("already in use");
+    }
+
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Pool-Canonical", ocpClient.generateEncryptionKeypair(), []);
+    const squatter = await fundedKeypair();
+    try {
+      await registerToPool(darkPool, squatter, agent, Keypair.generate().publicKey.toBytes());
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("Unauthorized");
+    }
+
+    await registerToPool(darkPool, owner, agent, Keypair.generate().publicKey.toBytes());
+    const [poolRegistration, registrationBump] = PublicKey.findProgramAddressSync(
+      [Buffer.from("poolreg"), darkPool.toBuffer(), agent.toBuffer()],
+      program.programId
+    );
+    const registration = await (program.account as any).poolRegistration.fetch(poolRegistration);
+    expect(registration.agentOwner.toString()).to.equal(owner.publicKey.toString());
+    expect(registration.bump).to.equal(registrationBump);
+
+    // One registration per agent per pool, even under a fresh nullifier
+    try {
+      await registerToPool(darkPool, owner, agent, Keypair.generate().publicKey.toBytes());
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("already in use");
+    }
+  });
+
+  it("Pauses dark pool registrations and transfers with the protocol or the dark pool module", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const MODULE_DARKPOOL = 1 << 3;
+    const darkPool = await newDarkPool();
+    const member = await newPoolMember(darkPool, "Pool-Paused");
+    const joiner = await fundedKeypair();
+    const { agent: joinerAgent } = await ocpClient.registerAgent(joiner, "Pool-Joiner", ocpClient.generateEncryptionKeypair(), []);
+
+    const setModulePause = (paused: boolean) =>
+      program.methods
+        .setModulePause(MODULE_DARKPOOL, paused)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+    const setProtocolPause = (paused: boolean) =>
+      program.methods
+        .setProtocolPause(paused)
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    await setModulePause(true);
+    try {
+      try {
+        await registerToPool(darkPool, joiner, joinerAgent, Keypair.generate().publicKey.toBytes());
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ModulePaused");
+      }
+      try {
+        await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("ModulePaused");
+      }
+    } finally {
+      await setModulePause(false);
+    }
+
+    await setProtocolPause(true);
+    try {
+      await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("ProtocolPaused");
+    } finally {
+      await setProtocolPause(false);
+    }
+
+    await registerToPool(darkPool, joiner, joinerAgent, Keypair.generate().publicKey.toBytes());
+  });
+
+  it("Rejects dark pool transfers against any key but the pool's verification key", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const member = await newPoolMember(darkPool, "Pool-VK");
+
+    try {
+      await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes(), {
+        zkVerificationKey: Keypair.generate().publicKey,
+      });
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidVerificationKey");
+    }
+
+    // After a rotation the old key is refused and the new one accepted
+    const oldVk = (await (program.account as any).darkPool.fetch(darkPool)).verificationKey;
+    const newVk = Keypair.generate().publicKey;
+    await updateDarkPool(darkPool, "rotateVerificationKey", newVk);
+    try {
+      await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes(), {
+        zkVerificationKey: oldVk,
+      });
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidVerificationKey");
+    }
+    await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes(), {
+      zkVerificationKey: newVk,
+    });
+  });
+
+  it("Rejects zeroed, uniform and mis-sized dark pool proofs", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const member = await newPoolMember(darkPool, "Pool-Proof");
+
+    for (const [proof, expected] of [
+      [Buffer.alloc(256), "InvalidProof"],
+      [Buffer.alloc(256, 0xff), "InvalidProof"],
+      [mockProof().subarray(0, 255), "InvalidProofSize"],
+    ] as [Buffer, string][]) {
+      try {
+        await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes(), { proof });
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include(expected);
+      }
+    }
+
+    const pool = await (program.account as any).darkPool.fetch(darkPool);
+    expect(pool.totalTransfers.toNumber()).to.equal(0);
+    expect(pool.inProgress).to.be.false;
+  });
+
+  it("Spends a dark pool nullifier once and reports it through the pre-flight check", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const member = await newPoolMember(darkPool, "Pool-Nullifier");
+    const nullifier = Keypair.generate().publicKey.toBytes();
+    const unused = Keypair.generate().publicKey.toBytes();
+
+    await darkPoolTransfer(darkPool, member.owner, member.agent, nullifier);
+    const spent = await (program.account as any).spentNullifier.fetch(findSpentNullifier(darkPool, nullifier));
+    expect(spent.pool.toString()).to.equal(darkPool.toString());
+    expect(spent.nullifier).to.deep.equal(Array.from(nullifier));
+
+    try {
+      await darkPoolTransfer(darkPool, member.owner, member.agent, nullifier);
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("already in use");
+    }
+
+    // The same nullifier spends independently in another pool
+    const otherPool = await newDarkPool();
+    const otherMember = await newPoolMember(otherPool, "Pool-Nullifier-Other");
+    await darkPoolTransfer(otherPool, otherMember.owner, otherMember.agent, nullifier);
+
+    const tx = await darkPoolMethods()
+      .checkNullifiersUnspent([Array.from(nullifier), Array.from(unused)])
+      .accounts({ darkPool })
+      .remainingAccounts(
+        [nullifier, unused].map((n) => ({ pubkey: findSpentNullifier(darkPool, n), isSigner: false, isWritable: false }))
+      )
+      .rpc();
+    const status = (await fetchEvents(tx)).find((e) => e.name === "nullifierStatus");
+    expect(status.data.spent).to.deep.equal([true, false]);
+
+    // PDAs out of order don't match their nullifiers
+    try {
+      await darkPoolMethods()
+        .checkNullifiersUnspent([Array.from(nullifier), Array.from(unused)])
+        .accounts({ darkPool })
+        .remainingAccounts(
+          [unused, nullifier].map((n) => ({ pubkey: findSpentNullifier(darkPool, n), isSigner: false, isWritable: false }))
+        )
+        .rpc();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InvalidNullifierAccount");
+    }
+  });
+
+  it("Reconciles a dark pool's transfer count from its spent nullifiers", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const member = await newPoolMember(darkPool, "Pool-Reconcile");
+    const nullifiers = [Keypair.generate().publicKey.toBytes(), Keypair.generate().publicKey.toBytes()];
+    for (const nullifier of nullifiers) {
+      await darkPoolTransfer(darkPool, member.owner, member.agent, nullifier);
+    }
+
+    // Duplicates and another pool's records don't count
+    const otherPool = await newDarkPool();
+    const otherMember = await newPoolMember(otherPool, "Pool-Reconcile-Other");
+    const foreign = Keypair.generate().publicKey.toBytes();
+    await darkPoolTransfer(otherPool, otherMember.owner, otherMember.agent, foreign);
+
+    const accounts = [
+      findSpentNullifier(darkPool, nullifiers[0]),
+      findSpentNullifier(darkPool, nullifiers[1]),
+      findSpentNullifier(darkPool, nullifiers[0]),
+      findSpentNullifier(otherPool, foreign),
+    ];
+    await darkPoolMethods()
+      .reconcilePool()
+      .accounts({ darkPool, authority: authority.publicKey })
+      .remainingAccounts(accounts.map((pubkey) => ({ pubkey, isSigner: false, isWritable: false })))
+      .signers([authority])
+      .rpc();
+    expect((await (program.account as any).darkPool.fetch(darkPool)).totalTransfers.toNumber()).to.equal(2);
+  });
+
+  it("Stops dark pool transfers once the decrypted volume reaches the pool cap", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const member = await newPoolMember(darkPool, "Pool-Volume");
+
+    await updateDarkPool(darkPool, "setMaxTotalVolume", new anchor.BN(1_000));
+    expect((await (program.account as any).darkPool.fetch(darkPool)).maxTotalVolume.toNumber()).to.equal(1_000);
+    // Below the cap transfers go through; the encrypted check zeroes any that would cross it
+    await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+
+    await darkPoolMethods()
+      .requestVolumeDecryption()
+      .accounts({ darkPool, requester: authority.publicKey })
+      .signers([authority])
+      .rpc();
+    await darkPoolMethods()
+      .fulfillVolumeDecryption(new anchor.BN(1_000))
+      .accounts({ darkPool, authority: authority.publicKey })
+      .signers([authority])
+      .rpc();
+
+    try {
+      await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("PoolVolumeCapReached");
+    }
+
+    // Lifting the cap reopens the pool
+    await updateDarkPool(darkPool, "setMaxTotalVolume", new anchor.BN(0));
+    await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+  });
+
+  it("Caps how many dark pools the protocol holds", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const protocolConfig = await ocpClient.getProtocolConfigAddress();
+    const setMaxDarkPools = (max: number) =>
+      program.methods
+        .setMaxDarkPools(new anchor.BN(max))
+        .accounts({ protocolConfig, authority: authority.publicKey })
+        .signers([authority])
+        .rpc();
+
+    await newDarkPool();
+    const total = ((await program.account.protocolConfig.fetch(protocolConfig)) as any).totalDarkPools.toNumber();
+    await setMaxDarkPools(total);
+    try {
+      await newDarkPool();
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("PoolLimitReached");
+    } finally {
+      await setMaxDarkPools(0);
+    }
+    await newDarkPool();
+  });
+
+  it("Charges the dark pool registration fee to the pool authority", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const fee = 5_000_000;
+    const darkPool = await newDarkPool(fee);
+    const owner = await fundedKeypair();
+    const { agent } = await ocpClient.registerAgent(owner, "Pool-Fee", ocpClient.generateEncryptionKeypair(), []);
+
+    const before = await provider.connection.getBalance(authority.publicKey);
+    const tx = await registerToPool(darkPool, owner, agent, Keypair.generate().publicKey.toBytes());
+    expect(await provider.connection.getBalance(authority.publicKey)).to.equal(before + fee);
+    const registered = (await fetchEvents(tx)).find((e) => e.name === "agentPoolRegistered");
+    expect(registered.data.feePaid.toNumber()).to.equal(fee);
+
+    // An owner who can't cover the fee isn't added to the anonymity set
+    const pricey = await newDarkPool(10_000_000_000);
+    const poor = await fundedKeypair();
+    const { agent: poorAgent } = await ocpClient.registerAgent(poor, "Pool-Fee-Poor", ocpClient.generateEncryptionKeypair(), []);
+    try {
+      await registerToPool(pricey, poor, poorAgent, Keypair.generate().publicKey.toBytes());
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("InsufficientRegistrationFee");
+    }
+    expect((await (program.account as any).darkPool.fetch(pricey)).registeredCommitments).to.have.length(0);
+  });
+
+  it("Enforces the dark pool transfer interval and registration TTL", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const member = await newPoolMember(darkPool, "Pool-Timing");
+
+    await updateDarkPool(darkPool, "setMinTransferIntervalSlots", new anchor.BN(1_000_000));
+    await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+    try {
+      await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("TransferTooSoon");
+    }
+    await updateDarkPool(darkPool, "setMinTransferIntervalSlots", new anchor.BN(0));
+
+    await updateDarkPool(darkPool, "setRegistrationTtl", new anchor.BN(1));
+    await new Promise((resolve) => setTimeout(resolve, 2_500));
+    try {
+      await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+      expect.fail("Should have thrown error");
+    } catch (error) {
+      expect(error.toString()).to.include("RegistrationExpired");
+    }
+
+    // A refresh restarts the TTL window
+    await darkPoolMethods()
+      .refreshRegistration()
+      .accounts({
+        poolRegistration: findPoolRegistration(darkPool, member.agent),
+        darkPool,
+        agentOwner: member.owner.publicKey,
+      })
+      .signers([member.owner])
+      .rpc();
+    await updateDarkPool(darkPool, "setRegistrationTtl", new anchor.BN(3_600));
+    await darkPoolTransfer(darkPool, member.owner, member.agent, Keypair.generate().publicKey.toBytes());
+  });
+
+  it("Accepts only distinct in-bounds dark pool denominations", async function () {
+    if (!darkPoolEnabled()) this.skip();
+    const darkPool = await newDarkPool();
+    const bn = (values: number[]) => values.map((v) => new anchor.BN(v));
+
+    for (const denominations of [[0], [2_000_000], [100, 100]]) {
+      try {
+        await updateDarkPool(darkPool, "setAllowedDenominations", bn(denominations));
+        expect.fail("Should have thrown error");
+      } catch (error) {
+        expect(error.toString()).to.include("InvalidDenomination");
+      }
+    }
+
+    await updateDarkPool(darkPool, "setAllowedDenominations", bn([100, 1_000, 10_000]));
+    let pool = await (program.account as any).darkPool.fetch(darkPool);
+    expect(pool.allowedDenominations.map((d: anchor.BN) => d.toNumber())).to.deep.equal([100, 1_000, 10_000]);
+
+    await updateDarkPool(darkPool, "setAllowedDenominations", []);
+    pool = await (program.account as any).darkPool.fetch(darkPool);
+    expect(pool.allowedDenominations).to.have.length(0);
+  });
 });